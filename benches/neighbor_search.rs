@@ -0,0 +1,55 @@
+// compare cell list neighbor search with looping all atom pairs in MM calculation
+// run with `cargo bench --bench neighbor_search`
+use std::time::Instant;
+use ndarray::{Array1, Array2, ArrayView2};
+use s_mmpbsa::neighbor;
+
+// protein-like system of ~0.1 atom/A^3, atoms on a randomly perturbed cubic lattice
+fn random_coordinates(n_atoms: usize) -> Array2<f64> {
+    let spacing = 0.1_f64.powf(-1.0 / 3.0);
+    let n_edge = (n_atoms as f64).cbrt().ceil() as usize;
+    let mut seed: u64 = 20221017;
+    let mut random = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+    };
+    Array2::from_shape_fn((n_atoms, 3), |(i, d)| {
+        let lattice = [i / n_edge / n_edge, i / n_edge % n_edge, i % n_edge][d];
+        (lattice as f64 + 0.3 * random()) * spacing
+    })
+}
+
+fn pair_energy(de: &mut Array1<f64>, i: usize, j: usize, r: f64) {
+    let r = r / 10.0;
+    let e = 1.0 / r + (1e-6 / r.powi(6) - 1e-3) / r.powi(6);
+    de[i] += e;
+    de[j] += e;
+}
+
+fn run(name: &str, coord: &ArrayView2<f64>, ndx_rec: &[usize], ndx_lig: &[usize], r_cutoff: f64) {
+    let mut de_all: Array1<f64> = Array1::zeros(coord.shape()[0]);
+    let t = Instant::now();
+    neighbor::all_pairs(coord, ndx_rec, ndx_lig, r_cutoff, |i, j, r| pair_energy(&mut de_all, i, j, r));
+    let t_all = t.elapsed().as_secs_f64();
+    let mut de_cell: Array1<f64> = Array1::zeros(coord.shape()[0]);
+    let t = Instant::now();
+    neighbor::cell_list_pairs(coord, ndx_rec, ndx_lig, r_cutoff, |i, j, r| pair_energy(&mut de_cell, i, j, r));
+    let t_cell = t.elapsed().as_secs_f64();
+    let max_diff = (&de_all - &de_cell).iter().fold(0.0_f64, |m, d| m.max(d.abs()))
+        / de_all.iter().fold(0.0_f64, |m, e| m.max(e.abs()));
+    println!("{:<28} all pairs {:>9.4} s, cell list {:>9.4} s, speedup {:>7.1}x, max relative deviation {:.2e}",
+             name, t_all, t_cell, t_all / t_cell, max_diff);
+}
+
+fn main() {
+    let r_cutoff = 12.0;
+    for n_atoms in [2000, 10000, 20000] {
+        let coord = random_coordinates(n_atoms);
+        let n_lig = 100;
+        let ndx_rec: Vec<usize> = (0..n_atoms - n_lig).collect();
+        let ndx_lig: Vec<usize> = (n_atoms - n_lig..n_atoms).collect();
+        let ndx_all: Vec<usize> = (0..n_atoms).collect();
+        run(&format!("{} atoms, receptor-ligand", n_atoms), &coord.view(), &ndx_rec, &ndx_lig, r_cutoff);
+        run(&format!("{} atoms, receptor only", n_atoms), &coord.view(), &ndx_all, &ndx_all, r_cutoff);
+    }
+}
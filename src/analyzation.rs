@@ -1,19 +1,31 @@
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::Path;
-use std::process::{exit, Command, Stdio};
+use std::process::{Command, Stdio};
 use ndarray::{s, Array1, Array2, Array3, Axis};
 use serde::{Deserialize, Serialize};
-use plotpy::{Barplot, Curve, Plot};
+use plotpy::{Barplot, Curve, Image, Plot};
 use crate::parse_tpr::Residue;
 use crate::settings::Settings;
-use crate::utils::{self, get_input, get_input_selection, get_residue_range_ca, range2list};
+use crate::statistics::{bootstrap_samples, stat_ineff, std_dev, Stats};
+use crate::quasi_harmonic::qh_entropy;
+
+// estimators of TΔS used for ΔG, by settings.entropy_method
+pub const ENTROPY_METHODS: [&str; 2] = ["IE", "C2"];
+// σ(ΔMM) (kJ/mol, 3.6 kcal/mol) above which the entropy estimates are not reliable
+const SIGMA_MM_MAX: f64 = 15.0;
+// receptor residues with any pair weaker than this (kJ/mol) are left out of the pairwise heatmap
+const PAIR_PLOT_MIN: f64 = 1.0;
+use crate::error::SmError;
+use crate::utils::{get_residue_range_ca, range2list};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SMResult {
     pub mutation: String,
     pub atom_names: Vec<String>,
     pub atom_res: Vec<usize>,
+    #[serde(default)]
+    pub atom_masses: Vec<f64>,      // empty in results saved before masses were kept
     pub residues: Vec<Residue>,
     pub ndx_lig: Vec<usize>,
     pub times: Vec<f64>,
@@ -30,12 +42,24 @@ pub struct SMResult {
     pub sa_atom: Array2<f64>,
     pub elec_atom: Array2<f64>,
     pub vdw_atom: Array2<f64>,
+    #[serde(default)]
+    pub pairwise: Option<PairwiseMM>,
+}
+
+// MM energy between receptor residues (rows) and ligand residues or atoms (columns) by frame
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PairwiseMM {
+    pub by_atom: bool,
+    pub rows: Vec<usize>,       // residue ids
+    pub cols: Vec<usize>,       // residue ids, or atom ids if by_atom
+    pub elec: Array3<f64>,
+    pub vdw: Array3<f64>,
 }
 
 impl SMResult {
-    pub fn new(atom_names: &Vec<String>, atom_res: &Vec<usize>, 
-               residues: &Vec<Residue>, ndx_lig: &Vec<usize>, 
-               times: &Vec<f64>, coord: &Array3<f64>, mutation: &str,
+    pub fn new(atom_names: &[String], atom_res: &[usize], atom_masses: &[f64],
+               residues: &[Residue], ndx_lig: &[usize], 
+               times: &[f64], coord: &Array3<f64>, mutation: &str,
                elec_atom: &Array2<f64>, vdw_atom: &Array2<f64>, 
                pb_atom: &Array2<f64>, sa_atom: &Array2<f64>) -> SMResult {
         let mut dh: Array1<f64> = Array1::zeros(times.len());
@@ -60,6 +84,7 @@ impl SMResult {
             mutation: mutation.to_string(),
             atom_names: atom_names.to_vec(),
             atom_res: atom_res.to_vec(),
+            atom_masses: atom_masses.to_vec(),
             residues: residues.to_owned(),
             ndx_lig: ndx_lig.to_owned(),
             times: times.to_owned(),
@@ -76,121 +101,57 @@ impl SMResult {
             sa_atom: sa_atom.to_owned(),
             elec_atom: elec_atom.to_owned(),
             vdw_atom: vdw_atom.to_owned(),
+            pairwise: None,
         }
     }
 
-    pub fn to_bin(&self, target: &Path) {
-        println!("Saving results to {}", target.to_str().unwrap());
-        let mut result_as_serialize = std::fs::File::create(target).unwrap();
-        serde_pickle::to_writer(&mut result_as_serialize, self, serde_pickle::SerOptions::new()).unwrap();
-    }
-
-    pub fn from(result_serialize: &str) -> SMResult {
-        let result_deserialize = std::fs::File::open(result_serialize).unwrap();
-        serde_pickle::from_reader(&result_deserialize, serde_pickle::DeOptions::new()).unwrap()
+    // result files of format 0, see result_file for the current ones
+    pub fn from(result_serialize: &str) -> Result<SMResult, SmError> {
+        let result_deserialize = std::fs::File::open(result_serialize).map_err(|e| SmError::io(result_serialize, e))?;
+        serde_pickle::from_reader(&result_deserialize, serde_pickle::DeOptions::new())
+            .map_err(|e| SmError::parse(result_serialize, 0, &e.to_string()))
     }
 }
 
-pub fn analyze_controller(result_wt: &SMResult, result_as: &Vec<SMResult>, temperature: f64, sys_name: &String, wd: &Path, settings: &Settings) {
-    println!("\nTime range: {} - {} ns, step = {} ns", result_wt.times[0], result_wt.times.last().unwrap(), if result_wt.times.len() > 1 {
-        result_wt.times[1] - result_wt.times[0]
-    } else {
-        0.0
-    });
-    let mut results = result_as.clone();
-    results.insert(0, result_wt.clone());
-    loop {
-        println!("\n                 ************ MM-PBSA analyzation ************");
-        println!("-1 Write residue-wised binding energy at specific time to pdb file");
-        println!(" 0 Exit program");
-        println!(" 1 View binding energy summary");
-        println!(" 2 Output binding energy by trajectory");
-        println!(" 3 Output binding energy by residue at specific time");
-        println!(" 4 Output ligand binding energy by atom at specific time");
-        // println!("10 Output residue-wised binding energy by time as default names");
-        let sel_fun = get_input_selection();
-        match sel_fun {
-            Ok(-1) => {
-                println!("Input the time point (in ns) to output (default: average):");
-                let ts_ids = get_time_range(result_wt);
-                if ts_ids.is_empty() {
-                    println!("Not valid time.");
-                    continue;
-                }
-                println!("Writing pdb and pml file(s)...");
-                for result in &results {
-                    let def_name = format!("MMPBSA_binding_energy_{}.pdb", sys_name);
-                    write_pdb_with_bf(result, &def_name, &ts_ids, wd, &(0..result.atom_res.len()).collect(), true);
-                    let pml_name = format!("MMPBSA_binding_energy_{}.pml", sys_name);
-                    let png_name = format!("MMPBSA_binding_energy_{}", sys_name);
-                    write_pml(&pml_name, &def_name, &png_name, wd, settings);
-                }
-                println!("Finished writing pdb file(s) with binding energy information.");
-                println!("Finished drawing figures with pml file(s) by PyMOL.");
-            },
-            Ok(0) => exit(0),
-            Ok(1) => {
-                println!("Input the time point (in ns) to output (default: average):");
-                let ts_ids = get_time_range(result_wt);
-                if ts_ids.is_empty() {
-                    println!("Not valid time.");
-                    continue;
-                }
-                for result in &results {
-                    analyze_summary(result, temperature, wd, &format!("{}-{}", sys_name, result.mutation), &ts_ids)
-                }
-            },
-            Ok(2) => {
-                for result in &results {
-                    analyze_traj(result, wd, &format!("{}-{}", sys_name, result.mutation))
-                }
-            },
-            Ok(3) => {
-                println!("Input the time point (in ns) to output (default: average):");
-                let ts_ids = get_time_range(result_wt);
-                if ts_ids.is_empty() {
-                    println!("Not valid time.");
-                    continue;
-                }
-                let (range_des, target_res) = select_res_by_range(result_wt);
-                println!("Writing energy file(s)...");
-                for result in &results {
-                    analyze_res(result, wd, &format!("{}-{}", sys_name, result.mutation), &ts_ids, &range_des, &target_res);
-                }
-                println!("Finished writing residue-wised binding energy file(s).");
-            },
-            Ok(4) => {
-                for result in &results {
-                    analyze_atom(result, wd, &format!("{}-{}", sys_name, result.mutation))
-                }
-                println!("Finished writing atom-wised binding energy pdb file(s) for ligand.");
-            },
-            // Ok(10) => {
-            //     for result in &results {
-            //         output_all_details(result, wd, &format!("{}-{}", sys_name, result.mutation))
-            //     }
-            // },
-            Ok(_) => {},
-            Err(_) => {}
+// non-interactive counterpart of the analyzation menu, used by job files
+pub fn analyze_all(result_wt: &SMResult, result_as: &[SMResult], temperature: f64, sys_name: &String, wd: &Path,
+                   res_cutoff: f64, settings: &Settings) {
+    let entropy_method = settings.entropy_method;
+    let mut results = vec![result_wt.clone()];
+    results.extend_from_slice(result_as);
+    let ts_ids: Vec<usize> = (0..result_wt.times.len()).collect();
+    let range_des = format!("{:.1}A", res_cutoff);
+    let target_res = get_residue_range_from_results(result_wt, res_cutoff);
+    for result in &results {
+        let name = format!("{}-{}", sys_name, result.mutation);
+        analyze_summary(result, temperature, wd, &name, &ts_ids, entropy_method);
+        analyze_traj(result, wd, &name);
+        analyze_entropy(result, temperature, wd, &name);
+        analyze_res(result, wd, &name, &ts_ids, &range_des, &target_res);
+        analyze_res_traj(result, wd, &name, &range_des, &target_res);
+        analyze_atom(result, wd, &name);
+        if result.pairwise.is_some() {
+            analyze_pairwise(result, wd, &name, &ts_ids);
         }
+        let def_name = format!("MMPBSA_binding_energy_{}.pdb", name);
+        write_pdb_with_bf(result, &def_name, &ts_ids, wd, &(0..result.atom_res.len()).collect(), true);
     }
-}
-
-fn get_time_range(result: &SMResult) -> Vec<usize> {
-    let ts = get_input("".to_string());
-    if !ts.trim().is_empty() {
-        let ts: Vec<f64> = utils::range2list(&ts).iter().map(|&i| i as f64).collect();
-        get_time_index(&ts, result)
-    } else {
-        (0..result.times.len()).collect()
+    if results.len() > 1 {
+        analyze_mutations(&results, temperature, wd, sys_name, &ts_ids, entropy_method, settings);
     }
+    println!("Finished writing analyzation files of {}.", sys_name);
 }
 
-fn get_time_index(ts: &Vec<f64>, results: &SMResult) -> Vec<usize> {
+pub fn get_time_index(ts: &Vec<f64>, results: &SMResult) -> Vec<usize> {
     ts.iter().filter_map(|&t| results.times.iter().position(|&x| x == t)).collect()
 }
 
-fn write_pml(pml_name: &String, def_name: &String, png_name: &String, wd: &Path, settings: &Settings) {
+// frames in ts_ids except those failed in PBSA calculation (NaN)
+fn valid_frames(results: &SMResult, ts_ids: &[usize]) -> Vec<usize> {
+    ts_ids.iter().filter(|&&t| results.dh[t].is_finite()).cloned().collect()
+}
+
+pub fn write_pml(pml_name: &String, def_name: &String, png_name: &String, wd: &Path, settings: &Settings) {
     let mut pml_file = fs::File::create(wd.join(pml_name)).unwrap();
     writeln!(pml_file, "cmd.load(\"{}\", \"complex\")", def_name).unwrap();
     writeln!(pml_file, "select protein, polymer.protein").unwrap();
@@ -217,7 +178,12 @@ fn write_pml(pml_name: &String, def_name: &String, png_name: &String, wd: &Path,
     }
 }
 
-fn write_pdb_with_bf(result: &SMResult, def_name: &String, ts_ids: &Vec<usize>, wd: &Path, atom_range: &Vec<usize>, reverse: bool) {
+pub fn write_pdb_with_bf(result: &SMResult, def_name: &String, ts_ids: &[usize], wd: &Path, atom_range: &Vec<usize>, reverse: bool) {
+    let ts_ids = match valid_frames(result, ts_ids) {
+        valid if valid.is_empty() => ts_ids.to_vec(),
+        valid => valid
+    };
+    let ts_ids = &ts_ids;
     let mut f = fs::File::create(wd.join(def_name)).unwrap();
     let coord = &result.coord;
     writeln!(f, "REMARK  Generated by s_mmpbsa (https://github.com/supernova4869/s_mmpbsa)").unwrap();
@@ -240,53 +206,348 @@ fn write_atom_line(result: &SMResult, id: usize, name: &String, res_id: usize, t
                 reverse * dh_avg[id], name.get(0..1).unwrap()).unwrap();
 }
 
-fn analyze_summary(results: &SMResult, temperature: f64, wd: &Path, sys_name: &String, ts_ids: &Vec<usize>) {
+pub fn analyze_summary(results: &SMResult, temperature: f64, wd: &Path, sys_name: &String, ts_ids: &[usize], entropy_method: usize) {
+    let valid = valid_frames(results, ts_ids);
+    if valid.len() < ts_ids.len() {
+        let skipped: Vec<String> = ts_ids.iter().filter(|t| !valid.contains(t)).map(|&t| results.times[t].to_string()).collect();
+        println!("\nWarning: PBSA failed at {} ns of {}, excluded from averages.", skipped.join(", "), sys_name);
+    }
+    if valid.is_empty() {
+        println!("No valid frames of {} to summarize.", sys_name);
+        return;
+    }
+    let ts_ids = &valid;
     let beta_kj = 1000.0 / 8.314462618 / temperature;
-    let dh_avg = results.dh.select(Axis(0), ts_ids).mean().unwrap();
-    let mm_avg = results.mm.select(Axis(0), ts_ids).mean().unwrap();
-    let elec_avg = results.elec.select(Axis(0), ts_ids).mean().unwrap();
-    let vdw_avg = results.vdw.select(Axis(0), ts_ids).mean().unwrap();
-    let pb_avg = results.pb.select(Axis(0), ts_ids).mean().unwrap();
-    let sa_avg = results.sa.select(Axis(0), ts_ids).mean().unwrap();
-
-    // Interactive Entropy
-    let mm_sum: f64 = results.mm.select(Axis(0), ts_ids).iter().map(|&mm| f64::exp((mm - mm_avg) * beta_kj)).sum();
-    let tds = -(mm_sum / ts_ids.len() as f64).ln() / beta_kj;
-    let dg = dh_avg - tds;
-    let ki = f64::exp(dg * beta_kj) * 1e9;    // nM
+    let series = |x: &Array1<f64>| -> Vec<f64> { ts_ids.iter().map(|&t| x[t]).collect() };
+    let (dh, mm) = (series(&results.dh), series(&results.mm));
+
+    // resampled with blocks of correlated frames
+    let block_len = stat_ineff(&dh).max(stat_ineff(&mm)).ceil() as usize;
+    let samples = bootstrap_samples(ts_ids.len(), block_len);
+    let dh_stat = Stats::from_series(&dh, &samples);
+    let mm_stat = Stats::from_series(&mm, &samples);
+    let pb_stat = Stats::from_series(&series(&results.pb), &samples);
+    let sa_stat = Stats::from_series(&series(&results.sa), &samples);
+    let elec_stat = Stats::from_series(&series(&results.elec), &samples);
+    let vdw_stat = Stats::from_series(&series(&results.vdw), &samples);
+
+    // Interactive Entropy, or its second-order cumulant approximation
+    let tds_by = |method: usize, ids: &[usize]| {
+        let mm: Vec<f64> = ids.iter().map(|&i| mm[i]).collect();
+        match method {
+            0 => interaction_entropy(&mm, beta_kj),
+            _ => c2_entropy(&mm, beta_kj)
+        }
+    };
+    let tds = |ids: &[usize]| tds_by(entropy_method, ids);
+    let dg = |ids: &[usize]| ids.iter().map(|&i| dh[i]).sum::<f64>() / ids.len() as f64 - tds(ids);
+    let ki = |ids: &[usize]| f64::exp(dg(ids) * beta_kj) * 1e9;    // nM
+    let tds_ie_stat = Stats::from_estimator(ts_ids.len(), &samples, |ids| tds_by(0, ids));
+    let tds_c2_stat = Stats::from_estimator(ts_ids.len(), &samples, |ids| tds_by(1, ids));
+    let tds_stat = match entropy_method {
+        0 => &tds_ie_stat,
+        _ => &tds_c2_stat
+    };
+    let dg_stat = Stats::from_estimator(ts_ids.len(), &samples, dg);
+    let ki_stat = Stats::from_estimator(ts_ids.len(), &samples, ki);
 
     println!("\nEnergy terms summary ({}-{} ns):", results.times[ts_ids[0]], results.times[*ts_ids.last().unwrap()]);
-    println!("ΔH: {:.3} kJ/mol", dh_avg);
-    println!("ΔMM: {:.3} kJ/mol", mm_avg);
-    println!("ΔPB: {:.3} kJ/mol", pb_avg);
-    println!("ΔSA: {:.3} kJ/mol", sa_avg);
+    println!("Errors: SD, SEM by block averaging and 95% CI by bootstrap with {} frame blocks", block_len);
+    let show = |name: &str, stat: &Stats| {
+        let sd = stat.sd.map_or(String::new(), |sd| format!(", SD {:.3}", sd));
+        println!("{}: {:.3} kJ/mol{}, SEM {:.3}, 95% CI [{:.3}, {:.3}]", name, stat.value, sd, stat.sem, stat.ci[0], stat.ci[1]);
+    };
+    show("ΔH", &dh_stat);
+    show("ΔMM", &mm_stat);
+    show("ΔPB", &pb_stat);
+    show("ΔSA", &sa_stat);
     println!();
-    println!("Δelec: {:.3} kJ/mol", elec_avg);
-    println!("Δvdw: {:.3} kJ/mol", vdw_avg);
+    show("Δelec", &elec_stat);
+    show("Δvdw", &vdw_stat);
     println!();
-    println!("TΔS: {:.3} kJ/mol", tds);
-    println!("ΔG: {:.3} kJ/mol", dg);
-    println!("Ki: {:.9e} nM", ki);
+    show("TΔS (IE)", &tds_ie_stat);
+    show("TΔS (C2)", &tds_c2_stat);
+    let sigma_mm = std_dev(&mm);
+    println!("σ(ΔMM): {:.3} kJ/mol", sigma_mm);
+    if sigma_mm > SIGMA_MM_MAX {
+        println!("Warning: σ(ΔMM) exceeds {} kJ/mol, the entropy estimates may not converge, see the entropy convergence output.", SIGMA_MM_MAX);
+    }
+    let tds_qh = qh_binding_entropy(results, ts_ids, temperature);
+    match tds_qh {
+        Some(tds_qh) => println!("TΔS (QH): {:.3} kJ/mol, configurational entropy by quasi-harmonic analysis, not included in ΔG", tds_qh),
+        None => println!("TΔS (QH): not available, receptor, ligand, atom masses and at least 3 frames are needed")
+    }
+    println!("ΔG is calculated with TΔS by {}:", ENTROPY_METHODS[entropy_method]);
+    show("ΔG", &dg_stat);
+    println!("Ki: {:.9e} nM, SEM {:.3e}, 95% CI [{:.3e}, {:.3e}]", ki_stat.value, ki_stat.sem, ki_stat.ci[0], ki_stat.ci[1]);
 
     let def_name = format!("MMPBSA_{}.csv", sys_name);
     println!("Writing binding energy terms...");
     let mut energy_sum = fs::File::create(wd.join(&def_name)).unwrap();
-    write!(energy_sum, "Energy Term,value,info ({}-{} ns)\n", results.times[ts_ids[0]], results.times[*ts_ids.last().unwrap()]).unwrap();
-    write!(energy_sum, "ΔH,{:.3},ΔH=ΔMM+ΔPB+ΔSA (kJ/mol)\n", dh_avg).unwrap();
-    write!(energy_sum, "ΔMM,{:.3},ΔMM=Δelec+ΔvdW (kJ/mol)\n", mm_avg).unwrap();
-    write!(energy_sum, "ΔPB,{:.3},(kJ/mol)\n", pb_avg).unwrap();
-    write!(energy_sum, "ΔSA,{:.3},(kJ/mol)\n", sa_avg).unwrap();
-    write!(energy_sum, "\n").unwrap();
-    write!(energy_sum, "Δelec,{:.3},(kJ/mol)\n", elec_avg).unwrap();
-    write!(energy_sum, "ΔvdW,{:.3},(kJ/mol)\n", vdw_avg).unwrap();
-    write!(energy_sum, "\n").unwrap();
-    write!(energy_sum, "TΔS,{:.3},(kJ/mol)\n", tds).unwrap();
-    write!(energy_sum, "ΔG,{:.3},ΔG=ΔH-TΔS (kJ/mol)\n", dg).unwrap();
-    write!(energy_sum, "Ki,{:.9e},Ki=exp(ΔG/RT) (nM)\n", ki).unwrap();
+    writeln!(energy_sum, "Energy Term,value,info ({}-{} ns),SD,SEM,95% CI low,95% CI high", results.times[ts_ids[0]], results.times[*ts_ids.last().unwrap()]).unwrap();
+    let write_term = |f: &mut File, name: &str, info: &str, stat: &Stats| {
+        let sd = stat.sd.map_or(String::new(), |sd| format!("{:.3}", sd));
+        writeln!(f, "{},{:.3},{},{},{:.3},{:.3},{:.3}", name, stat.value, info, sd, stat.sem, stat.ci[0], stat.ci[1]).unwrap();
+    };
+    write_term(&mut energy_sum, "ΔH", "ΔH=ΔMM+ΔPB+ΔSA (kJ/mol)", &dh_stat);
+    write_term(&mut energy_sum, "ΔMM", "ΔMM=Δelec+ΔvdW (kJ/mol)", &mm_stat);
+    write_term(&mut energy_sum, "ΔPB", "(kJ/mol)", &pb_stat);
+    write_term(&mut energy_sum, "ΔSA", "(kJ/mol)", &sa_stat);
+    writeln!(energy_sum).unwrap();
+    write_term(&mut energy_sum, "Δelec", "(kJ/mol)", &elec_stat);
+    write_term(&mut energy_sum, "ΔvdW", "(kJ/mol)", &vdw_stat);
+    writeln!(energy_sum).unwrap();
+    write_term(&mut energy_sum, "TΔS", &format!("TΔS by {} (kJ/mol)", ENTROPY_METHODS[entropy_method]), tds_stat);
+    write_term(&mut energy_sum, "TΔS (IE)", "interaction entropy (kJ/mol)", &tds_ie_stat);
+    write_term(&mut energy_sum, "TΔS (C2)", "second-order cumulant (kJ/mol)", &tds_c2_stat);
+    writeln!(energy_sum, "σ(ΔMM),{:.3},(kJ/mol)", sigma_mm).unwrap();
+    if let Some(tds_qh) = tds_qh {
+        writeln!(energy_sum, "TΔS (QH),{:.3},quasi-harmonic configurational entropy (kJ/mol),,,,", tds_qh).unwrap();
+    }
+    write_term(&mut energy_sum, "ΔG", "ΔG=ΔH-TΔS (kJ/mol)", &dg_stat);
+    writeln!(energy_sum, "Ki,{:.9e},Ki=exp(ΔG/RT) (nM),,{:.9e},{:.9e},{:.9e}", ki_stat.value, ki_stat.sem, ki_stat.ci[0], ki_stat.ci[1]).unwrap();
     println!("Binding energy terms have been writen to {}", &def_name);
 }
 
-fn analyze_traj(results: &SMResult, wd: &Path, sys_name: &String) {
+// -TΔS = kT ln<exp(βΔE_int)>, with ΔE_int the fluctuation of MM interaction energy
+fn interaction_entropy(mm: &[f64], beta_kj: f64) -> f64 {
+    let mm_avg = mm.iter().sum::<f64>() / mm.len() as f64;
+    let mm_sum: f64 = mm.iter().map(|&mm| f64::exp((mm - mm_avg) * beta_kj)).sum();
+    -(mm_sum / mm.len() as f64).ln() / beta_kj
+}
+
+// TΔS = T(S_com - S_rec - S_lig) by quasi-harmonic entropies of the fitted coordinates
+fn qh_binding_entropy(results: &SMResult, ts_ids: &[usize], temperature: f64) -> Option<f64> {
+    let n_atoms = results.atom_res.len();
+    let ndx_rec: Vec<usize> = (0..n_atoms).filter(|i| !results.ndx_lig.contains(i)).collect();
+    if results.atom_masses.len() != n_atoms || ndx_rec.is_empty() || ts_ids.len() < 3 {
+        return None;
+    }
+    let ndx_com: Vec<usize> = (0..n_atoms).collect();
+    let s = |atoms: &[usize]| qh_entropy(&results.coord, ts_ids, atoms, &results.atom_masses, temperature);
+    Some(temperature * (s(&ndx_com) - s(&ndx_rec) - s(&results.ndx_lig)) / 1000.0)
+}
+
+// -TΔS = β<ΔE_int^2>/2
+fn c2_entropy(mm: &[f64], beta_kj: f64) -> f64 {
+    let mm_avg = mm.iter().sum::<f64>() / mm.len() as f64;
+    let var = mm.iter().map(|&mm| (mm - mm_avg).powi(2)).sum::<f64>() / mm.len() as f64;
+    -beta_kj * var / 2.0
+}
+
+// ΔG = <ΔH> - TΔS of the frames
+fn binding_dg(dh: &[f64], mm: &[f64], ids: &[usize], beta_kj: f64, entropy_method: usize) -> f64 {
+    let dh = ids.iter().map(|&i| dh[i]).sum::<f64>() / ids.len() as f64;
+    let mm: Vec<f64> = ids.iter().map(|&i| mm[i]).collect();
+    let tds = match entropy_method {
+        0 => interaction_entropy(&mm, beta_kj),
+        _ => c2_entropy(&mm, beta_kj)
+    };
+    dh - tds
+}
+
+// ΔΔG = ΔG(mutant) - ΔG(WT) sorted as hot-spot table, and coupling energy of multiple mutations,
+// ΔΔG(multiple) - ΣΔΔG(single), with errors by bootstrap of the same frames
+pub fn analyze_mutations(results: &[SMResult], temperature: f64, wd: &Path, sys_name: &String, ts_ids: &[usize],
+                     entropy_method: usize, settings: &Settings) {
+    if results.len() < 2 {
+        println!("No mutations calculated for {}.", sys_name);
+        return;
+    }
+    let result_wt = &results[0];
+    let ts_ids: Vec<usize> = ts_ids.iter().filter(|&&t| results.iter().all(|r| r.dh[t].is_finite())).cloned().collect();
+    if ts_ids.is_empty() {
+        println!("No valid frames of {} to compare mutations.", sys_name);
+        return;
+    }
+    let beta_kj = 1000.0 / 8.314462618 / temperature;
+    // ΔH and ΔMM of the frames, renumbered from 0 for resampling
+    let dh: Vec<Vec<f64>> = results.iter().map(|r| ts_ids.iter().map(|&t| r.dh[t]).collect()).collect();
+    let mm: Vec<Vec<f64>> = results.iter().map(|r| ts_ids.iter().map(|&t| r.mm[t]).collect()).collect();
+    let samples = bootstrap_samples(ts_ids.len(), stat_ineff(&dh[0]).ceil() as usize);
+    let dg = |k: usize, ids: &[usize]| binding_dg(&dh[k], &mm[k], ids, beta_kj, entropy_method);
+    let ddg = |k: usize, ids: &[usize]| dg(k, ids) - dg(0, ids);
+
+    // ΔΔH, ΔΔG of each mutation
+    let mut table: Vec<(usize, Stats, Stats)> = (1..results.len()).map(|k| {
+        let ddh: Vec<f64> = dh[k].iter().zip(&dh[0]).map(|(m, w)| m - w).collect();
+        (k, Stats::from_series(&ddh, &samples), Stats::from_estimator(ts_ids.len(), &samples, |ids| ddg(k, ids)))
+    }).collect();
+    table.sort_by(|a, b| b.2.value.partial_cmp(&a.2.value).unwrap_or(std::cmp::Ordering::Equal));
+
+    println!("\nΔΔG of mutations ({}-{} ns), TΔS by {}:", result_wt.times[ts_ids[0]], result_wt.times[*ts_ids.last().unwrap()], ENTROPY_METHODS[entropy_method]);
+    println!("Hot spots: ΔΔG >= {} kJ/mol, warm spots: ΔΔG >= {} kJ/mol", settings.hot_spot, settings.warm_spot);
+    let spot = |ddg: f64| match ddg {
+        v if v >= settings.hot_spot => "hot",
+        v if v >= settings.warm_spot => "warm",
+        _ => "null"
+    };
+    let def_name = format!("MMPBSA_{}_ΔΔG.csv", sys_name);
+    let mut f = fs::File::create(wd.join(&def_name)).unwrap();
+    writeln!(f, "Mutation,ΔΔH (kJ/mol),ΔΔG (kJ/mol),SEM,95% CI low,95% CI high,Spot").unwrap();
+    for (k, ddh, ddg) in &table {
+        println!("{}: ΔΔH {:.3} kJ/mol, ΔΔG {:.3} kJ/mol, SEM {:.3}, 95% CI [{:.3}, {:.3}], {} spot",
+            results[*k].mutation, ddh.value, ddg.value, ddg.sem, ddg.ci[0], ddg.ci[1], spot(ddg.value));
+        writeln!(f, "{},{:.3},{:.3},{:.3},{:.3},{:.3},{}", results[*k].mutation, ddh.value, ddg.value, ddg.sem, ddg.ci[0], ddg.ci[1], spot(ddg.value)).unwrap();
+    }
+
+    // multiple mutations with all the single ones calculated
+    let find = |label: &str| results.iter().position(|r| r.mutation == label);
+    let multiples: Vec<(usize, Vec<usize>)> = (1..results.len()).filter_map(|k| {
+        let labels: Vec<&str> = results[k].mutation.split('+').collect();
+        let singles: Option<Vec<usize>> = labels.iter().map(|l| find(l)).collect();
+        match labels.len() > 1 {
+            true => singles.map(|singles| (k, singles)),
+            false => None
+        }
+    }).collect();
+    if !multiples.is_empty() {
+        println!("\nCoupling energy of multiple mutations, ΔΔG(multiple) - ΣΔΔG(single):");
+        writeln!(f, "\nMultiple mutation,ΔΔG (kJ/mol),ΣΔΔG of single (kJ/mol),Coupling energy (kJ/mol),SEM,95% CI low,95% CI high").unwrap();
+        for (k, singles) in multiples {
+            let sum_ddg = |ids: &[usize]| singles.iter().map(|&s| ddg(s, ids)).sum::<f64>();
+            let all: Vec<usize> = (0..ts_ids.len()).collect();
+            let coupling = Stats::from_estimator(ts_ids.len(), &samples, |ids| ddg(k, ids) - sum_ddg(ids));
+            println!("{}: ΔΔG {:.3} kJ/mol, ΣΔΔG {:.3} kJ/mol, coupling {:.3} kJ/mol, SEM {:.3}, 95% CI [{:.3}, {:.3}]",
+                results[k].mutation, ddg(k, &all), sum_ddg(&all), coupling.value, coupling.sem, coupling.ci[0], coupling.ci[1]);
+            writeln!(f, "{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}", results[k].mutation, ddg(k, &all), sum_ddg(&all),
+                coupling.value, coupling.sem, coupling.ci[0], coupling.ci[1]).unwrap();
+        }
+    }
+    println!("ΔΔG of mutations writen to {}", def_name);
+
+    // bars with SEM, colored by spot class
+    let def_name = format!("MMPBSA_{}_ΔΔG.png", sys_name);
+    let labels: Vec<&str> = table.iter().map(|(k, _, _)| results[*k].mutation.as_str()).collect();
+    let values: Vec<f64> = table.iter().map(|(_, _, ddg)| ddg.value).collect();
+    let errors: Vec<String> = table.iter().map(|(_, _, ddg)| format!("{:.3}", ddg.sem)).collect();
+    let colors: Vec<&str> = values.iter().map(|&v| match spot(v) {
+        "hot" => "red",
+        "warm" => "orange",
+        _ => "gray"
+    }).collect();
+    let mut bar = Barplot::new();
+    bar.set_colors(&colors).set_extra(&format!("yerr=[{}],capsize=4", errors.join(",")));
+    bar.draw(&(0..labels.len()).map(|a| a as f64).collect(), &values);
+    let mut plot = Plot::new();
+    if cfg!(windows) {
+        plot.set_python_exe("python");
+    }
+    let xticks: Vec<usize> = (0..labels.len()).collect();
+    match plot.add(&bar)
+            .extra(&format!("plt.axhline({}, color='red', ls='--', lw=1)\nplt.axhline({}, color='orange', ls='--', lw=1)\n", settings.hot_spot, settings.warm_spot))
+            .set_figure_size_inches((labels.len() as f64 * 0.64).max(6.4), 4.8)
+            .set_ticks_x_labels(&xticks, &labels)
+            .set_rotation_ticks_x(45.0)
+            .grid_and_labels("Mutation", "ΔΔG (kJ/mol)")
+            .set_label_x_fontsize(18.0)
+            .set_label_y_fontsize(18.0)
+            .set_ticks_x_fontsize(14.0)
+            .set_ticks_y_fontsize(14.0)
+            .save(&wd.join(&def_name)).ok() {
+        Some(_) => println!("Figure drawn to {}", &def_name),
+        None => println!("Not drawn due to the matplotlib error.")
+    }
+
+    // ΔΔG of single mutations in B-factors of WT structure, the largest one (first in table) for atoms in several mutations
+    let n_atoms = result_wt.atom_res.len();
+    let mut bf: Vec<Option<f64>> = vec![None; n_atoms];
+    for (k, _, ddg) in &table {
+        let label = &results[*k].mutation;
+        let atoms: Vec<usize> = match label.strip_prefix("del_") {
+            Some(groups) => range2list(&groups.replace('_', ",")).iter().map(|&a| a as usize - 1).collect(),
+            None if !label.contains('+') => {
+                // the residue renamed in mutant
+                let res: Vec<usize> = result_wt.residues.iter().zip(&results[*k].residues)
+                    .filter(|(w, m)| w.name != m.name).map(|(w, _)| w.id).collect();
+                (0..n_atoms).filter(|i| res.contains(&result_wt.atom_res[*i])).collect()
+            },
+            None => vec![]
+        };
+        for a in atoms.into_iter().filter(|&a| a < n_atoms) {
+            bf[a].get_or_insert(ddg.value);
+        }
+    }
+    let def_name = format!("MMPBSA_{}_ΔΔG.pdb", sys_name);
+    let mut f = fs::File::create(wd.join(&def_name)).unwrap();
+    writeln!(f, "REMARK  Generated by s_mmpbsa (https://github.com/supernova4869/s_mmpbsa)").unwrap();
+    writeln!(f, "REMARK  B-factor column filled with ΔΔG of single mutations (kJ/mol)").unwrap();
+    let t = *ts_ids.last().unwrap();
+    for (id, &res_id) in result_wt.atom_res.iter().enumerate() {
+        let name = &result_wt.atom_names[id];
+        writeln!(f, "ATOM  {:5} {:<4} {:<3} A{:4}    {:8.3}{:8.3}{:8.3}  1.00{:6.2}           {:<2}",
+            id + 1, name, result_wt.residues[res_id].name, result_wt.residues[res_id].nr,
+            result_wt.coord[[t, id, 0]], result_wt.coord[[t, id, 1]], result_wt.coord[[t, id, 2]], bf[id].unwrap_or(0.0), name.get(0..1).unwrap()).unwrap();
+    }
+    writeln!(f, "END").unwrap();
+    println!("ΔΔG of single mutations writen to B-factors of {}", def_name);
+}
+
+// TΔS by IE and C2 of the first n frames, and of each of 5 non-overlapping time windows
+pub fn analyze_entropy(results: &SMResult, temperature: f64, wd: &Path, sys_name: &String) {
+    let beta_kj = 1000.0 / 8.314462618 / temperature;
+    let ts_ids = valid_frames(results, &(0..results.times.len()).collect::<Vec<usize>>());
+    if ts_ids.len() < 2 {
+        println!("At least 2 frames are needed for entropy convergence of {}.", sys_name);
+        return;
+    }
+    let mm: Vec<f64> = ts_ids.iter().map(|&t| results.mm[t]).collect();
+    let times: Vec<f64> = ts_ids.iter().map(|&t| results.times[t]).collect();
+
+    println!("Writing entropy convergence...");
+    let def_name = format!("MMPBSA_{}_entropy.csv", sys_name);
+    let mut f = fs::File::create(wd.join(&def_name)).unwrap();
+    writeln!(f, "Frames,Time (ns),TΔS (IE),TΔS (C2)").unwrap();
+    let mut tds_ie = vec![];
+    let mut tds_c2 = vec![];
+    for n in 2..=mm.len() {
+        tds_ie.push(interaction_entropy(&mm[..n], beta_kj));
+        tds_c2.push(c2_entropy(&mm[..n], beta_kj));
+        writeln!(f, "{},{},{:.3},{:.3}", n, times[n - 1], tds_ie.last().unwrap(), tds_c2.last().unwrap()).unwrap();
+    }
+    println!("Entropy by number of frames writen to {}", &def_name);
+
+    let def_name = format!("MMPBSA_{}_entropy_window.csv", sys_name);
+    let mut f = fs::File::create(wd.join(&def_name)).unwrap();
+    writeln!(f, "Start (ns),End (ns),Frames,TΔS (IE),TΔS (C2),σ(ΔMM)").unwrap();
+    for w in 0..5 {
+        let (st, ed) = (w * mm.len() / 5, (w + 1) * mm.len() / 5);
+        if ed - st < 2 {
+            continue;
+        }
+        let mm_w = &mm[st..ed];
+        writeln!(f, "{},{},{},{:.3},{:.3},{:.3}", times[st], times[ed - 1], mm_w.len(),
+                 interaction_entropy(mm_w, beta_kj), c2_entropy(mm_w, beta_kj), std_dev(mm_w)).unwrap();
+    }
+    println!("Entropy by time windows writen to {}", &def_name);
+
+    // TΔS curves by the end time of frames used
+    let mut curve_ie = Curve::new();
+    curve_ie.set_line_width(2.0).set_label("IE");
+    curve_ie.draw(&times[1..].to_vec(), &tds_ie);
+    let mut curve_c2 = Curve::new();
+    curve_c2.set_line_width(2.0).set_label("C2");
+    curve_c2.draw(&times[1..].to_vec(), &tds_c2);
+    let mut plot = Plot::new();
+    if cfg!(windows) {
+        plot.set_python_exe("python");
+    }
+    let def_name = format!("MMPBSA_{}_entropy.png", sys_name);
+    match plot.add(&curve_ie)
+        .add(&curve_c2)
+        .legend()
+        .grid_and_labels("Time (ns)", "TΔS (kJ/mol)")
+        .set_label_x_fontsize(18.0)
+        .set_label_y_fontsize(18.0)
+        .set_ticks_x_fontsize(14.0)
+        .set_ticks_y_fontsize(14.0)
+        .save(&wd.join(&def_name)).ok() {
+            Some(_) => println!("Figure drawn to {}", &def_name),
+            None => println!("Not drawn due to the matplotlib error.")
+        };
+}
+
+pub fn analyze_traj(results: &SMResult, wd: &Path, sys_name: &String) {
     println!("Writing binding energy terms...");
     let def_name = format!("MMPBSA_{}_traj.csv", sys_name);
     let mut energy_sum = fs::File::create(wd.join(&def_name)).unwrap();
@@ -322,84 +583,20 @@ fn analyze_traj(results: &SMResult, wd: &Path, sys_name: &String) {
     println!("Binding energy terms writen to {}", &def_name);
 }
 
-fn select_res_by_range(results: &SMResult) -> (String, Vec<usize>) {
-    println!("Determine the residue range to output:");
-    println!(" 1 Ligand and receptor residues by: CA within 4 A");
-    println!(" 2 Ligand and receptor residues by: CA within 6 A");
-    println!(" 3 Ligand and receptor residues by: CA within 8 A");
-    println!(" 4 Ligand and receptor residues by: CA within a specified distance");
-    println!(" 5 Self-defined residue range");
-    // 残基范围确定
-    let i = get_input_selection().unwrap();
-    let mut range_des = String::from("4A");
-    let target_res = match i {
-        1 => {
-            get_residue_range_from_results(results, 4.0)
-        },
-        2 => {
-            range_des = String::from("6A");
-            get_residue_range_from_results(results, 6.0)
-        },
-        3 => {
-            range_des = String::from("8A");
-            get_residue_range_from_results(results, 8.0)
-        },
-        4 => {
-            println!("Input the cut-off distance you want to expand from ligand, default: 4");
-            let cutoff = get_input(4.0);
-            range_des = format!("{:.1}A", cutoff);
-            get_residue_range_from_results(results, cutoff)
-        },
-        5 => {
-            let mut res_range = String::new();
-            loop {
-                println!("Input the residue range you want to output (e.g., 1-3, 5), default: all");
-                println!("Input \"?\" to view the residues list");
-                res_range = get_input(res_range);
-                if res_range.eq("?") {
-                    results.residues.iter().enumerate().for_each(|(i, r)| {
-                        print!("{}{}, ", r.nr, r.name);
-                        if (i + 1) % 5 == 0 {
-                            println!();
-                        }
-                    });
-                    println!();
-                } else {
-                    break;
-                }
-            }
-            range_des = res_range.to_string();
-            let res_range: Vec<i32> = match res_range.len() {
-                0 => {
-                    range_des = "all".to_string();
-                    results.residues.iter().map(|r| r.nr).collect()
-                },
-                _ => range2list(&res_range)
-            };
-            results.atom_res
-                .iter()
-                .filter(|&&i| res_range.contains(&(results.residues[i].nr)))    // 用户筛选用nr
-                .map(|&i| results.residues[i].id)     // 索引用id
-                .collect()
-        },
-        _ => vec![],
-    };
-    (range_des, target_res)
-}
-
-fn analyze_res(results: &SMResult, wd: &Path, sys_name: &String, ts_ids: &Vec<usize>, range_des: &String, target_res: &Vec<usize>) {
+pub fn analyze_res(results: &SMResult, wd: &Path, sys_name: &String, ts_ids: &Vec<usize>, range_des: &String, target_res: &Vec<usize>) {
     let def_name = format!("MMPBSA_{}_res_{}.csv", sys_name, range_des);
     let (tar_res_nr, tar_res_name, tar_res_energy) = get_target_res_data(results, ts_ids, target_res);
     write_res_csv(&tar_res_nr, &tar_res_name, &tar_res_energy, wd, &def_name);
     plot_res_csv(&tar_res_nr, &tar_res_name, &tar_res_energy, wd, &format!("MMPBSA_{}_res_{}.png", sys_name, range_des));
 }
 
-fn analyze_atom(results: &SMResult, wd: &Path, sys_name: &String) {
+pub fn analyze_atom(results: &SMResult, wd: &Path, sys_name: &String) {
     let def_name = format!("MMPBSA_{}_ligand.pdb", sys_name);
-    write_pdb_with_bf(results, &def_name, &vec![0], wd, &results.ndx_lig, false);
+    write_pdb_with_bf(results, &def_name, &[0], wd, &results.ndx_lig, false);
 }
 
-fn get_target_res_data(results: &SMResult, ts_ids: &Vec<usize>, target_res: &Vec<usize>) -> (Vec<i32>, Vec<String>, [Vec<f64>; 6]) {
+fn get_target_res_data(results: &SMResult, ts_ids: &[usize], target_res: &Vec<usize>) -> (Vec<i32>, Vec<String>, [Vec<f64>; 6]) {
+    let ts_ids = &valid_frames(results, ts_ids);
     let res_nr: Vec<i32> = results.residues.iter().filter_map(|res| if target_res.contains(&res.id) {
         Some(res.nr)
     } else {
@@ -483,120 +680,142 @@ fn plot_res_csv(tar_res_nr: &Vec<i32>, tar_res_name: &Vec<String>, tar_res_energ
     }
 }
 
-fn get_residue_range_from_results(results: &SMResult, cutoff: f64) -> Vec<usize> {
+// receptor residue x ligand residue (or atom) MM energy averaged over frames, as csv matrices and heatmap of ΔMM
+pub fn analyze_pairwise(results: &SMResult, wd: &Path, sys_name: &String, ts_ids: &[usize]) {
+    let pairwise = match &results.pairwise {
+        Some(pairwise) => pairwise,
+        None => {
+            println!("No pairwise decomposition in results of {}, set pairwise before MM/PB-SA calculation.", sys_name);
+            return;
+        }
+    };
+    let ts_ids = valid_frames(results, ts_ids);
+    if ts_ids.is_empty() {
+        println!("No valid frames of {} to output.", sys_name);
+        return;
+    }
+    let elec = pairwise.elec.select(Axis(0), &ts_ids).mean_axis(Axis(0)).unwrap();
+    let vdw = pairwise.vdw.select(Axis(0), &ts_ids).mean_axis(Axis(0)).unwrap();
+    let mm = &elec + &vdw;
+    let row_labels: Vec<String> = pairwise.rows.iter()
+        .map(|&r| format!("{}{}", results.residues[r].name, results.residues[r].nr)).collect();
+    let col_labels: Vec<String> = pairwise.cols.iter().map(|&c| match pairwise.by_atom {
+        true => format!("{}({})", results.atom_names[c], c + 1),
+        false => format!("{}{}", results.residues[c].name, results.residues[c].nr)
+    }).collect();
+
+    println!("Writing pairwise energy matrices...");
+    for (term, m) in [("mm", &mm), ("elec", &elec), ("vdw", &vdw)] {
+        let def_name = format!("MMPBSA_{}_pairwise_{}.csv", sys_name, term);
+        let mut f = fs::File::create(wd.join(&def_name)).unwrap();
+        writeln!(f, "Residue,{}", col_labels.join(",")).unwrap();
+        for (i, row) in m.rows().into_iter().enumerate() {
+            let values: Vec<String> = row.iter().map(|v| format!("{:.3}", v)).collect();
+            writeln!(f, "{},{}", row_labels[i], values.join(",")).unwrap();
+        }
+        println!("Pairwise {} energy (kJ/mol) writen to {}", term, &def_name);
+    }
+
+    let shown: Vec<usize> = (0..pairwise.rows.len()).filter(|&i| mm.row(i).iter().any(|v| v.abs() >= PAIR_PLOT_MIN)).collect();
+    if shown.is_empty() {
+        println!("No receptor residue with pair energy over {} kJ/mol to draw.", PAIR_PLOT_MIN);
+        return;
+    }
+    let data: Vec<Vec<f64>> = shown.iter().map(|&i| mm.row(i).to_vec()).collect();
+    let v_max = data.iter().flatten().fold(0.0, |m: f64, v| m.max(v.abs()));
+    let mut img = Image::new();
+    img.set_colormap_name("bwr").set_extra(&format!("vmin={},vmax={},aspect='auto'", -v_max, v_max));
+    img.draw(&data);
+    let mut plot = Plot::new();
+    if cfg!(windows) {
+        plot.set_python_exe("python");
+    }
+    let def_name = format!("MMPBSA_{}_pairwise.png", sys_name);
+    let xticks: Vec<usize> = (0..col_labels.len()).collect();
+    let yticks: Vec<usize> = (0..shown.len()).collect();
+    let ytick_labels: Vec<String> = shown.iter().map(|&i| row_labels[i].to_string()).collect();
+    match plot.add(&img)
+            .extra("plt.colorbar(label='ΔMM (kJ/mol)')\n")
+            .set_figure_size_inches(4.0 + col_labels.len() as f64 * 0.32, 2.0 + shown.len() as f64 * 0.32)
+            .set_ticks_x_labels(&xticks, &col_labels)
+            .set_ticks_y_labels(&yticks, &ytick_labels)
+            .set_rotation_ticks_x(90.0)
+            .set_label_x(if pairwise.by_atom { "Ligand atom" } else { "Ligand residue" })
+            .set_label_y("Receptor residue")
+            .save(&wd.join(&def_name)).ok() {
+        Some(_) => println!("Figure drawn to {}", &def_name),
+        None => println!("Not drawn due to the matplotlib error.")
+    }
+}
+
+pub fn get_residue_range_from_results(results: &SMResult, cutoff: f64) -> Vec<usize> {
     let last_frame = results.times.len() - 1;
     get_residue_range_ca(&results.coord.slice(s![last_frame, .., ..]).to_owned(), 
         &results.ndx_lig, cutoff, &results.atom_res, &results.atom_names, &results.residues)
 }
 
 
-// fn analyze_dh_res_traj(results: &SMResult, wd: &Path, def_name: &String) {
-//     println!("Writing binding energy terms...");
-//     let mut energy_res = fs::File::create(wd.join(&def_name)).unwrap();
-//     energy_res.write_all("Time (ns)".as_bytes()).unwrap();
-//     for res in &results.residues {
-//         energy_res.write_all(format!(",{}#{}", res.nr, res.name).as_bytes()).unwrap();
-//     }
-//     for i in 0..results.times.len() {
-//         energy_res.write_all(format!("\n{}", results.times[i]).as_bytes()).unwrap();
-//         for dh in &results.dh_atom.row(i) {
-//             energy_res.write_all(format!(",{:.3}", dh).as_bytes()).unwrap();
-//         }
-//     }
-//     energy_res.write_all("\n".as_bytes()).unwrap();
-//     println!("Binding energy terms have been writen to {}", &def_name);
-// }
-
-// fn analyze_mm_res_traj(results: &SMResult, wd: &Path, def_name: &String) {
-//     println!("Writing binding energy terms...");
-//     let mut energy_res = fs::File::create(wd.join(&def_name)).unwrap();
-//     energy_res.write_all("Time (ns)".as_bytes()).unwrap();
-//     for res in &results.residues {
-//         energy_res.write_all(format!(",{}#{}", res.nr, res.name).as_bytes()).unwrap();
-//     }
-//     for i in 0..results.times.len() {
-//         energy_res.write_all(format!("\n{}", results.times[i]).as_bytes()).unwrap();
-//         for mm in &results.mm_atom.row(i) {
-//             energy_res.write_all(format!(",{:.3}", mm).as_bytes()).unwrap();
-//         }
-//     }
-//     energy_res.write_all("\n".as_bytes()).unwrap();
-//     println!("Binding energy terms have been writen to {}", &def_name);
-// }
-
-// fn analyze_pb_res_traj(results: &SMResult, wd: &Path, def_name: &String) {
-//     println!("Writing binding energy terms...");
-//     let mut energy_res = fs::File::create(wd.join(&def_name)).unwrap();
-//     energy_res.write_all("Time (ns)".as_bytes()).unwrap();
-//     for res in &results.residues {
-//         energy_res.write_all(format!(",{}#{}", res.nr, res.name).as_bytes()).unwrap();
-//     }
-//     for i in 0..results.times.len() {
-//         energy_res.write_all(format!("\n{}", results.times[i]).as_bytes()).unwrap();
-//         for pb in &results.pb_atom.row(i) {
-//             energy_res.write_all(format!(",{:.3}", pb).as_bytes()).unwrap();
-//         }
-//     }
-//     energy_res.write_all("\n".as_bytes()).unwrap();
-//     println!("Binding energy terms have been writen to {}", &def_name);
-// }
-
-// fn analyze_sa_res_traj(results: &SMResult, wd: &Path, def_name: &String) {
-//     println!("Writing binding energy terms...");
-//     let mut energy_res = fs::File::create(wd.join(&def_name)).unwrap();
-//     energy_res.write_all("Time (ns)".as_bytes()).unwrap();
-//     for res in &results.residues {
-//         energy_res.write_all(format!(",{}#{}", res.nr, res.name).as_bytes()).unwrap();
-//     }
-//     for i in 0..results.times.len() {
-//         energy_res.write_all(format!("\n{}", results.times[i]).as_bytes()).unwrap();
-//         for sa in &results.sa_atom.row(i) {
-//             energy_res.write_all(format!(",{:.3}", sa).as_bytes()).unwrap();
-//         }
-//     }
-//     energy_res.write_all("\n".as_bytes()).unwrap();
-//     println!("Binding energy terms have been writen to {}", &def_name);
-// }
-
-// fn analyze_elec_res_traj(results: &SMResult, wd: &Path, def_name: &String) {
-//     println!("Writing binding energy terms...");
-//     let mut energy_res = fs::File::create(wd.join(&def_name)).unwrap();
-//     energy_res.write_all("Time (ns)".as_bytes()).unwrap();
-//     for res in &results.residues {
-//         energy_res.write_all(format!(",{}#{}", res.nr, res.name).as_bytes()).unwrap();
-//     }
-//     for i in 0..results.times.len() {
-//         energy_res.write_all(format!("\n{}", results.times[i]).as_bytes()).unwrap();
-//         for elec in &results.elec_atom.row(i) {
-//             energy_res.write_all(format!(",{:.3}", elec).as_bytes()).unwrap();
-//         }
-//     }
-//     energy_res.write_all("\n".as_bytes()).unwrap();
-//     println!("Binding energy terms have been writen to {}", &def_name);
-// }
-
-// fn analyze_vdw_res_traj(results: &SMResult, wd: &Path, def_name: &String) {
-//     println!("Writing binding energy terms...");
-//     let mut energy_res = fs::File::create(wd.join(def_name)).unwrap();
-//     energy_res.write_all("Time (ns)".as_bytes()).unwrap();
-//     for res in &results.residues {
-//         energy_res.write_all(format!(",{}#{}", res.nr, res.name).as_bytes()).unwrap();
-//     }
-//     for i in 0..results.times.len() {
-//         energy_res.write_all(format!("\n{}", results.times[i]).as_bytes()).unwrap();
-//         for vdw in &results.vdw_atom.row(i) {
-//             energy_res.write_all(format!(",{:.3}", vdw).as_bytes()).unwrap();
-//         }
-//     }
-//     energy_res.write_all("\n".as_bytes()).unwrap();
-//     println!("Binding energy terms have been writen to {}", def_name);
-// }
-
-// pub fn output_all_details(results: &SMResult, wd: &Path, sys_name: &String) {
-//     analyze_dh_res_traj(results, wd, &format!("MMPBSA_{}_res_ΔH.csv", sys_name));
-//     analyze_mm_res_traj(results, wd, &format!("MMPBSA_{}_res_ΔMM.csv", sys_name));
-//     analyze_pb_res_traj(results, wd, &format!("MMPBSA_{}_res_ΔPB.csv", sys_name));
-//     analyze_sa_res_traj(results, wd, &format!("MMPBSA_{}_res_ΔSA.csv", sys_name));
-//     analyze_elec_res_traj(results, wd, &format!("MMPBSA_{}_res_Δelec.csv", sys_name));
-//     analyze_vdw_res_traj(results, wd, &format!("MMPBSA_{}_res_ΔvdW.csv", sys_name));
-// }
+// energy terms of the target residues by frame, as residues x frames csv of each term and time-residue heatmap of ΔH
+pub fn analyze_res_traj(results: &SMResult, wd: &Path, sys_name: &String, range_des: &String, target_res: &[usize]) {
+    let res_ids: Vec<usize> = results.residues.iter().filter(|r| target_res.contains(&r.id)).map(|r| r.id).collect();
+    if res_ids.is_empty() {
+        println!("No residues in range {} to output.", range_des);
+        return;
+    }
+    let res_labels: Vec<String> = res_ids.iter()
+        .map(|&r| format!("{}{}", results.residues[r].name, results.residues[r].nr)).collect();
+    let res_atoms: Vec<Vec<usize>> = res_ids.iter().map(|&r| results.atom_res.iter().enumerate()
+        .filter_map(|(i, &a)| if a == r { Some(i) } else { None }).collect()).collect();
+    // residues x frames
+    let res_energy = |arr: &Array2<f64>| -> Array2<f64> {
+        let mut e: Array2<f64> = Array2::zeros((res_ids.len(), results.times.len()));
+        for (i, atoms) in res_atoms.iter().enumerate() {
+            e.row_mut(i).assign(&arr.select(Axis(1), atoms).sum_axis(Axis(1)));
+        }
+        e
+    };
+
+    println!("Writing residue-wised energy by time...");
+    let times: Vec<String> = results.times.iter().map(|t| t.to_string()).collect();
+    let mut dh_res = Array2::zeros((0, 0));
+    for (term, arr) in [("ΔH", &results.dh_atom), ("ΔMM", &results.mm_atom), ("ΔPB", &results.pb_atom),
+                        ("ΔSA", &results.sa_atom), ("Δelec", &results.elec_atom), ("ΔvdW", &results.vdw_atom)] {
+        let e = res_energy(arr);
+        let def_name = format!("MMPBSA_{}_res_{}_{}.csv", sys_name, range_des, term);
+        let mut f = fs::File::create(wd.join(&def_name)).unwrap();
+        writeln!(f, "Residue,{}", times.join(",")).unwrap();
+        for (i, row) in e.rows().into_iter().enumerate() {
+            let values: Vec<String> = row.iter().map(|v| format!("{:.3}", v)).collect();
+            writeln!(f, "{},{}", res_labels[i], values.join(",")).unwrap();
+        }
+        println!("Residue-wised {} by time writen to {}", term, &def_name);
+        if term == "ΔH" {
+            dh_res = e;
+        }
+    }
+
+    // time on x axis, each row a residue
+    let data: Vec<Vec<f64>> = dh_res.rows().into_iter().map(|r| r.to_vec()).collect();
+    let v_max = data.iter().flatten().filter(|v| v.is_finite()).fold(0.0, |m: f64, v| m.max(v.abs()));
+    let (t0, t1) = (results.times[0], *results.times.last().unwrap());
+    let mut img = Image::new();
+    img.set_colormap_name("bwr").set_extra(&format!("vmin={},vmax={},aspect='auto',interpolation='nearest',extent=[{},{},{},-0.5]",
+                                                    -v_max, v_max, t0, t1.max(t0 + 1e-3), res_ids.len() as f64 - 0.5));
+    img.draw(&data);
+    let mut plot = Plot::new();
+    if cfg!(windows) {
+        plot.set_python_exe("python");
+    }
+    let def_name = format!("MMPBSA_{}_res_{}_ΔH_traj.png", sys_name, range_des);
+    let yticks: Vec<usize> = (0..res_ids.len()).collect();
+    match plot.add(&img)
+            .extra("plt.colorbar(label='ΔH (kJ/mol)')\n")
+            .set_figure_size_inches(9.6, 2.0 + res_ids.len() as f64 * 0.32)
+            .set_ticks_y_labels(&yticks, &res_labels)
+            .set_label_x("Time (ns)")
+            .set_label_y("Residue")
+            .save(&wd.join(&def_name)).ok() {
+        Some(_) => println!("Figure drawn to {}", &def_name),
+        None => println!("Not drawn due to the matplotlib error.")
+    }
+}
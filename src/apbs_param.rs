@@ -5,6 +5,7 @@ use std::io::Write;
 use std::marker::Copy;
 use std::path::Path;
 use serde::{Serialize, Deserialize};
+use crate::error::SmError;
 
 #[derive(Serialize, Deserialize)]
 pub struct PBESet {
@@ -68,10 +69,10 @@ impl PBESet {
         return new_pbe_set;
     }
 
-    pub fn load_params<T: AsRef<Path>>(file: T) -> PBESet {
-        let pbe_set = fs::read_to_string(&file).expect("Read PB parameters file error.");
-        let pbe_set: PBESet = serde_yaml::from_str(pbe_set.as_str()).expect("Error format with PB parameters file.");
-        pbe_set
+    pub fn load_params<T: AsRef<Path>>(file: T) -> Result<PBESet, SmError> {
+        let file = file.as_ref().display().to_string();
+        let pbe_set = fs::read_to_string(&file).map_err(|e| SmError::io(&file, e))?;
+        serde_yaml::from_str(pbe_set.as_str()).map_err(|e| SmError::parse(&file, e.location().map_or(0, |l| l.line()), &e.to_string()))
     }
 
     pub fn save_params<T: AsRef<Path>>(&self, file: T) {
@@ -123,7 +124,7 @@ impl Clone for PBESet {
 pub struct Ion {
     pub charge: f64,
     pub conc: f64,
-    radius: f64,
+    pub radius: f64,
 }
 
 impl fmt::Display for Ion {
@@ -145,15 +146,25 @@ pub struct PBASet {
     temp: f64,
     srfm: String,
     swin: f64,
-    srad: f64,
+    pub srad: f64,
     pub gamma: f64,
     press: f64,
     bconc: f64,
-    sdens: f64,
+    pub sdens: f64,
     dpos: f64,
     grid: (f64, f64, f64),
     calc_force: bool,
     calc_energy: String,
+    // SA energy = surften * area + surfoff, not passed to apbs
+    #[serde(default = "default_surften")]
+    pub surften: f64,
+    #[serde(default)]
+    pub surfoff: f64,
+}
+
+// From AMBER-PB4, the surface extension constant γ=0.0072 kcal/(mol·Å2)=0.030125 kJ/(mol·Å^2)
+fn default_surften() -> f64 {
+    0.030125
 }
 
 impl PBASet {
@@ -171,6 +182,8 @@ impl PBASet {
             grid: (0.1, 0.1, 0.1),
             calc_force: false,
             calc_energy: "total".to_string(),
+            surften: default_surften(),
+            surfoff: 0.0,
         }
     }
 
@@ -188,13 +201,15 @@ impl PBASet {
             grid: pba_set.grid,
             calc_force: pba_set.calc_force,
             calc_energy: pba_set.calc_energy.to_string(),
+            surften: pba_set.surften,
+            surfoff: pba_set.surfoff,
         }
     }
 
-    pub fn load_params<T: AsRef<Path>>(file: T) -> PBASet {
-        let pba_set = fs::read_to_string(&file).expect("Read SA parameters file error.");
-        let pba_set: PBASet = serde_yaml::from_str(pba_set.as_str()).expect("Error format with SA parameters file.");
-        pba_set
+    pub fn load_params<T: AsRef<Path>>(file: T) -> Result<PBASet, SmError> {
+        let file = file.as_ref().display().to_string();
+        let pba_set = fs::read_to_string(&file).map_err(|e| SmError::io(&file, e))?;
+        serde_yaml::from_str(pba_set.as_str()).map_err(|e| SmError::parse(&file, e.location().map_or(0, |l| l.line()), &e.to_string()))
     }
 
     pub fn save_params<T: AsRef<Path>>(&self, file: T) {
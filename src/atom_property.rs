@@ -21,6 +21,7 @@ pub struct AtomProperty {
     pub name: String,
     pub resname: String,
     pub resid: usize,
+    pub mass: f64,
 }
 
 impl AtomProperty {
@@ -30,6 +31,7 @@ impl AtomProperty {
             self.name = new_name.to_string();
             let radii_table = get_radii_map(radius_type);
             self.radius = get_radii(&radii_table, new_name);
+            self.mass = 1.008;      // truncated side chains end with hydrogens
         }
     }
 }
@@ -51,9 +53,8 @@ impl AtomProperties {
         let mut cur_atom_id = 0;
         let mut resid_offset = 0;      // residues number that has been overpast
 
-        // HashMap to store the first occurrence index of each string
+        // LJ type id of each atom type name
         let mut at_map: HashMap<String, usize> = HashMap::new();
-        let mut index = 0;
         for mol in &tpr.molecules {
             for _ in 0..tpr.molecule_types[mol.molecule_type_id].molecules_num {
                 for atom in &mol.atoms {
@@ -66,12 +67,9 @@ impl AtomProperties {
                             name: atom.name.to_string(),
                             resname: mol.residues[atom.resind].name.to_string(),
                             resid: atom.resind + resid_offset,
+                            mass: atom.mass,
                         });
-                        if !at_map.contains_key(&atom.at_type) {
-                            // If the string is not in the map, insert it with the current index
-                            at_map.insert(atom.at_type.to_string(), index);
-                            index += 1;
-                        }
+                        at_map.entry(atom.at_type.to_string()).or_insert(atom.type_id);
                     }
                     cur_atom_id += 1;
                 }
@@ -5,6 +5,9 @@ use std::path::Path;
 use crate::atom_property::AtomProperties;
 use crate::parse_tpr::TPR;
 
+// kinds of radius types
+pub const RADIUS_TYPES: [&str; 5] = ["ff", "amber", "Bondi", "mBondi", "mBondi2"];
+
 impl AtomProperties {
     // ff_radius would not be used
     pub fn apply_radius(&mut self, radius_type: usize, at_list: &Vec<String>, radius_types: &Vec<&str>, wd: &Path) {
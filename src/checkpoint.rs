@@ -0,0 +1,128 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use ndarray::{Array2, Array3, ArrayView1};
+use serde::{Deserialize, Serialize};
+use crate::atom_property::AtomProperties;
+use crate::settings::Settings;
+use crate::error::SmError;
+
+// per-frame energy terms saved in temp directory, so that an interrupted calculation can be resumed
+pub struct Checkpoint {
+    dir: PathBuf,
+    key: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FrameResult {
+    key: u64,
+    elec: Vec<f64>,
+    vdw: Vec<f64>,
+    pb: Vec<f64>,
+    sa: Vec<f64>,
+}
+
+impl Checkpoint {
+    pub fn new(temp_dir: &Path, sys_name: &str, key: u64) -> Result<Checkpoint, SmError> {
+        let dir = temp_dir.join(format!("_checkpoint_{}", sys_name));
+        fs::create_dir_all(&dir).map_err(|e| SmError::io(&dir.display().to_string(), e))?;
+        Ok(Checkpoint { dir, key })
+    }
+
+    // fill rows of finished frames with the same key, return which frames are finished
+    pub fn load(&self, elec_atom: &mut Array2<f64>, vdw_atom: &mut Array2<f64>,
+                pb_atom: &mut Array2<f64>, sa_atom: &mut Array2<f64>) -> Vec<bool> {
+        let atoms_num = elec_atom.shape()[1];
+        (0..elec_atom.shape()[0]).map(|frame| {
+            let result: Option<FrameResult> = File::open(self.frame_file(frame)).ok()
+                .and_then(|f| serde_pickle::from_reader(f, serde_pickle::DeOptions::new()).ok());
+            match result {
+                Some(r) if r.key == self.key && r.elec.len() == atoms_num => {
+                    elec_atom.row_mut(frame).assign(&ArrayView1::from(&r.elec));
+                    vdw_atom.row_mut(frame).assign(&ArrayView1::from(&r.vdw));
+                    pb_atom.row_mut(frame).assign(&ArrayView1::from(&r.pb));
+                    sa_atom.row_mut(frame).assign(&ArrayView1::from(&r.sa));
+                    true
+                }
+                _ => false
+            }
+        }).collect()
+    }
+
+    // written to a temporary file first, so that a killed run never leaves a broken frame
+    pub fn save(&self, frame: usize, elec: ArrayView1<f64>, vdw: ArrayView1<f64>, pb: ArrayView1<f64>, sa: ArrayView1<f64>)
+                -> Result<(), SmError> {
+        let result = FrameResult {
+            key: self.key,
+            elec: elec.to_vec(),
+            vdw: vdw.to_vec(),
+            pb: pb.to_vec(),
+            sa: sa.to_vec(),
+        };
+        let tmp_file = self.dir.join(format!("{}.tmp", frame));
+        let tmp_name = tmp_file.display().to_string();
+        let mut f = File::create(&tmp_file).map_err(|e| SmError::io(&tmp_name, e))?;
+        serde_pickle::to_writer(&mut f, &result, serde_pickle::SerOptions::new())
+            .map_err(|e| SmError::Input(format!("Cannot write checkpoint {}: {}", tmp_name, e)))?;
+        fs::rename(&tmp_file, self.frame_file(frame)).map_err(|e| SmError::io(&tmp_name, e))
+    }
+
+    fn frame_file(&self, frame: usize) -> PathBuf {
+        self.dir.join(format!("{}.ckpt", frame))
+    }
+}
+
+// FNV-1a hash of everything the energy terms depend on
+// pbsa_params is the PB and SA settings as written to paras_pbsa.txt
+pub fn input_key(time_list: &[f64], coordinates: &Array3<f64>, aps: &AtomProperties,
+                 ndx_rec: &[usize], ndx_lig: &[usize], pbsa_params: &str, settings: &Settings) -> u64 {
+    let mut hash = Fnv::new();
+    time_list.iter().for_each(|t| hash.write(&t.to_le_bytes()));
+    coordinates.iter().for_each(|x| hash.write(&x.to_le_bytes()));
+    aps.c6.iter().chain(aps.c12.iter()).for_each(|x| hash.write(&x.to_le_bytes()));
+    for ap in &aps.atom_props {
+        hash.write(&ap.charge.to_le_bytes());
+        hash.write(&ap.radius.to_le_bytes());
+        hash.write(&ap.type_id.to_le_bytes());
+    }
+    ndx_rec.iter().chain(ndx_lig.iter()).for_each(|i| hash.write(&i.to_le_bytes()));
+    hash.write(pbsa_params.as_bytes());
+    hash.write(format!("{} {} {:?} {} {} {}", settings.r_cutoff, settings.elec_screen, settings.pbsa_kernel,
+                       settings.cfac, settings.fadd, settings.df).as_bytes());
+    hash.finish()
+}
+
+// FNV-1a hashes of the trajectory (times and coordinates) and topology (atom properties and groups), kept in result files
+pub fn system_hashes(time_list: &[f64], coordinates: &Array3<f64>, aps: &AtomProperties,
+                     ndx_rec: &[usize], ndx_lig: &[usize]) -> (u64, u64) {
+    let mut traj = Fnv::new();
+    time_list.iter().for_each(|t| traj.write(&t.to_le_bytes()));
+    coordinates.iter().for_each(|x| traj.write(&x.to_le_bytes()));
+    let mut top = Fnv::new();
+    aps.c6.iter().chain(aps.c12.iter()).for_each(|x| top.write(&x.to_le_bytes()));
+    for ap in &aps.atom_props {
+        top.write(&ap.charge.to_le_bytes());
+        top.write(&ap.radius.to_le_bytes());
+        top.write(&ap.type_id.to_le_bytes());
+        top.write(ap.name.as_bytes());
+    }
+    ndx_rec.iter().chain(ndx_lig.iter()).for_each(|i| top.write(&i.to_le_bytes()));
+    (traj.finish(), top.finish())
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
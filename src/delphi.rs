@@ -0,0 +1,142 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use ndarray::{Array1, ArrayView2};
+use rayon::ThreadPoolBuilder;
+use crate::apbs_param::{PBASet, PBESet};
+use crate::atom_property::AtomProperties;
+use crate::prepare_apbs::{gen_mesh_params, mesh_params};
+use crate::sasa::calc_sa;
+use crate::settings::Settings;
+use crate::error::SmError;
+use crate::solvation_kernel::{Frame, SolvationKernel};
+
+const R: f64 = 8.314462618e-3;      // kJ mol^-1 K^-1
+
+// DelPhi kernel, with built-in SASA since DelPhi has no per-atom surface area
+pub struct Delphi<'a> {
+    program: &'a str,
+    pbe_set: &'a PBESet,
+    pba_set: &'a PBASet,
+    n_threads: usize,
+    settings: &'a Settings,
+}
+
+impl SolvationKernel for Delphi<'_> {
+    fn run(&self, frame: &Frame) -> Result<(Array1<f64>, Array1<f64>), SmError> {
+        let de_pb = self.calc_pb(frame.ndx_rec, frame.ndx_lig, &frame.coord, frame.aps, frame.temp_dir, &frame.f_name)?;
+        let pool = ThreadPoolBuilder::new().num_threads(self.n_threads).build().unwrap();
+        let de_sa = pool.install(|| calc_sa(frame.ndx_rec, frame.ndx_lig, &frame.coord, frame.aps, self.pba_set));
+        Ok((de_pb, de_sa))
+    }
+}
+
+impl<'a> Delphi<'a> {
+    pub fn new(program: &'a str, pbe_set: &'a PBESet, pba_set: &'a PBASet, n_threads: usize, settings: &'a Settings) -> Delphi<'a> {
+        Delphi { program, pbe_set, pba_set, n_threads, settings }
+    }
+
+    // Per-atom polar solvation energies (kJ/mol) of com - rec - lig, or of rec if no ligand
+    // pqr files of the frame should have been written by prepare_pqr in temp_dir
+    fn calc_pb(&self, ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, coord: &ArrayView2<f64>,
+               aps: &AtomProperties, temp_dir: &Path, f_name: &str) -> Result<Array1<f64>, SmError> {
+        let pbe_set = self.pbe_set;
+        let atm_radius = Array1::from_iter(aps.atom_props.iter().map(|a| a.radius));
+        let (rec_box, lig_box, com_box) = gen_mesh_params(ndx_rec, ndx_lig, coord, &atm_radius);
+        let mut de_pb: Array1<f64> = Array1::zeros(aps.atom_props.len());
+        if ndx_lig[0] != ndx_rec[0] {
+            let ndx_com: Vec<usize> = (0..aps.atom_props.len()).collect();
+            let mols = [("com", &ndx_com, com_box, 1.0), ("rec", ndx_rec, rec_box, -1.0), ("lig", ndx_lig, lig_box, -1.0)];
+            for (mol, ndx, mol_box, sign) in mols {
+                let de = self.solvation(temp_dir, &format!("{}_{}", f_name, mol), ndx, aps, mol_box, pbe_set)?;
+                for (i, e) in ndx.iter().zip(de) {
+                    de_pb[*i] += sign * e;
+                }
+            }
+        } else {
+            let de = self.solvation(temp_dir, &format!("{}_rec", f_name), ndx_rec, aps, rec_box, pbe_set)?;
+            for (i, e) in ndx_rec.iter().zip(de) {
+                de_pb[*i] += e;
+            }
+        }
+        Ok(de_pb)
+    }
+
+    // 0.5 * q * reaction potential in solvent minus that in vacuum, for atoms of {name}.pqr
+    fn solvation(&self, temp_dir: &Path, name: &str, ndx: &[usize], aps: &AtomProperties, mol_box: [f64; 6],
+                 pbe_set: &PBESet) -> Result<Vec<f64>, SmError> {
+        // pqr files are written in the order of atom index
+        let mut ndx = ndx.to_vec();
+        ndx.sort();
+        let mut pbe_set0 = PBESet::from(pbe_set);
+        pbe_set0.sdie = 1.0;
+        pbe_set0.ions.clear();
+        let phi_sol = self.run_delphi(temp_dir, name, "SOL", mol_box, pbe_set)?;
+        let phi_vac = self.run_delphi(temp_dir, name, "VAC", mol_box, &pbe_set0)?;
+        if phi_sol.len() != ndx.len() || phi_vac.len() != ndx.len() {
+            return Err(SmError::parse(&temp_dir.join(format!("{}_SOL.frc", name)).display().to_string(), 0,
+                                      &format!("{} and {} atoms found in SOL and VAC results, expected {}",
+                                               phi_sol.len(), phi_vac.len(), ndx.len())));
+        }
+        let kt = R * pbe_set.temp;
+        Ok(ndx.iter().zip(phi_sol.iter().zip(phi_vac.iter()))
+            .map(|(&i, (s, v))| 0.5 * aps.atom_props[i].charge * (s - v) * kt).collect())
+    }
+
+    // reaction field potentials (kT/e) at atoms of {name}.pqr
+    fn run_delphi(&self, temp_dir: &Path, name: &str, phase: &str, mol_box: [f64; 6], pbe_set: &PBESet) -> Result<Vec<f64>, SmError> {
+        let prm_name = format!("{}_{}.prm", name, phase);
+        let frc_name = format!("{}_{}.frc", name, phase);
+        write_delphi_input(&temp_dir.join(&prm_name), name, &frc_name, mol_box, pbe_set, self.settings)?;
+        let delphi_result = Command::new(self.program).arg(&prm_name).current_dir(temp_dir)
+            .env("OMP_NUM_THREADS", self.n_threads.to_string()).output().map_err(|e| SmError::io(self.program, e))?;
+        if self.settings.debug_mode {
+            let out_path = temp_dir.join(format!("{}_{}.out", name, phase)).display().to_string();
+            File::create(&out_path).and_then(|mut f| f.write_all(&delphi_result.stdout).and(f.write_all(&delphi_result.stderr)))
+                .map_err(|e| SmError::io(&out_path, e))?;
+        }
+        if !delphi_result.status.success() {
+            return Err(SmError::program(&format!("delphi of {}_{}", name, phase), &String::from_utf8_lossy(&delphi_result.stderr)));
+        }
+        let frc_path = temp_dir.join(&frc_name);
+        let frc = fs::read_to_string(&frc_path).map_err(|e| SmError::io(&frc_path.display().to_string(), e))?;
+        Ok(parse_frc(&frc))
+    }
+}
+
+// single cubic grid covering the fine grid of apbs, with the same spacing
+fn write_delphi_input(prm_file: &Path, name: &str, frc_name: &str, mol_box: [f64; 6], pbe_set: &PBESet,
+                      settings: &Settings) -> Result<(), SmError> {
+    let (dime, _, fglen, center) = mesh_params(mol_box[0], mol_box[3], mol_box[1], mol_box[4],
+                                               mol_box[2], mol_box[5], settings);
+    let gsize = *dime.iter().max().unwrap();
+    let scale = (gsize - 1) as f64 / fglen.iter().fold(0.0, |m: f64, &l| m.max(l));
+    // 1:1 salt of the same ionic strength
+    let salt: f64 = pbe_set.ions.iter().map(|ion| 0.5 * ion.conc * ion.charge * ion.charge).sum();
+    let ion_radius = pbe_set.ions.iter().map(|ion| ion.radius).fold(0.0, f64::max);
+    let bndcon = match pbe_set.bcfl.as_str() {
+        "zero" => 1,
+        "sdh" => 2,
+        _ => 4
+    };
+    let nonit = match pbe_set.pb_solver.as_str() {
+        "npbe" if salt > 0.0 => 500,
+        _ => 0
+    };
+    let prm = format!("gsize={}\nscale={:.6}\nacenter({:.3},{:.3},{:.3})\n", gsize, scale, center[0], center[1], center[2])
+        + &format!("in(modpdb4,file=\"{}.pqr\",format=pqr)\n", name)
+        + &format!("indi={}\nexdi={}\nprbrad={}\nsalt={:.6}\nionrad={}\nbndcon={}\nmaxc=0.0001\nnonit={}\n",
+                   pbe_set.pdie, pbe_set.sdie, pbe_set.srad, salt, ion_radius, bndcon, nonit)
+        + &format!("energy(s)\nsite(a,r)\nin(frc,file=\"self\")\nout(frc,file=\"{}\")\n", frc_name);
+    fs::write(prm_file, prm).map_err(|e| SmError::io(&prm_file.display().to_string(), e))
+}
+
+// atom lines follow the "ATOM DESCRIPTOR" header, with reaction potential as the last column
+fn parse_frc(frc: &str) -> Vec<f64> {
+    frc.lines().skip_while(|l| !l.contains("DESCRIPTOR")).skip(1)
+        .map_while(|l| match l.trim().to_lowercase().starts_with("total") {
+            true => None,
+            false => l.split_whitespace().last().and_then(|v| v.parse::<f64>().ok())
+        }).collect()
+}
@@ -0,0 +1,47 @@
+use std::fmt::{self, Formatter};
+use std::io;
+
+// errors of parsers and external programs, printed as one message instead of panicking
+#[derive(Debug)]
+pub enum SmError {
+    Io { path: String, source: io::Error },
+    Parse { file: String, line: usize, msg: String },      // line starts at 1, 0 if unknown
+    Program { program: String, stderr: String },
+    Input(String),                                          // invalid selection or parameter
+}
+
+impl SmError {
+    pub fn io(path: &str, source: io::Error) -> SmError {
+        SmError::Io { path: path.to_string(), source }
+    }
+
+    pub fn parse(file: &str, line: usize, msg: &str) -> SmError {
+        SmError::Parse { file: file.to_string(), line, msg: msg.to_string() }
+    }
+
+    pub fn program(program: &str, stderr: &str) -> SmError {
+        SmError::Program { program: program.to_string(), stderr: stderr.trim().to_string() }
+    }
+}
+
+impl fmt::Display for SmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SmError::Io { path, source } => write!(f, "Cannot access {}: {}", path, source),
+            SmError::Parse { file, line: 0, msg } => write!(f, "Failed to parse {}: {}", file, msg),
+            SmError::Parse { file, line, msg } => write!(f, "Failed to parse {} at line {}: {}", file, line, msg),
+            SmError::Program { program, stderr } if stderr.is_empty() => write!(f, "{} failed", program),
+            SmError::Program { program, stderr } => write!(f, "{} failed with:\n{}", program, stderr),
+            SmError::Input(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SmError::Io { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
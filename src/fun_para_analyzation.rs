@@ -0,0 +1,203 @@
+// interactive menu of analyzation, the analyzation itself is done by s_mmpbsa::analyzation
+use std::path::Path;
+use std::process::exit;
+use s_mmpbsa::analyzation::{analyze_atom, analyze_entropy, analyze_mutations, analyze_pairwise, analyze_res, analyze_res_traj,
+                             analyze_summary, analyze_traj, get_residue_range_from_results, get_time_index, write_pdb_with_bf,
+                             write_pml, SMResult, ENTROPY_METHODS};
+use s_mmpbsa::settings::Settings;
+use s_mmpbsa::utils::{self, get_input, get_input_selection, range2list};
+
+pub fn analyze_controller(result_wt: &SMResult, result_as: &Vec<SMResult>, temperature: f64, sys_name: &String, wd: &Path, settings: &Settings) {
+    println!("\nTime range: {} - {} ns, step = {} ns", result_wt.times[0], result_wt.times.last().unwrap(), if result_wt.times.len() > 1 {
+        result_wt.times[1] - result_wt.times[0]
+    } else {
+        0.0
+    });
+    let mut results = vec![result_wt.clone()];
+    results.extend_from_slice(result_as);
+    let mut entropy_method = settings.entropy_method;
+    loop {
+        println!("\n                 ************ MM-PBSA analyzation ************");
+        println!("-1 Write residue-wised binding energy at specific time to pdb file");
+        println!(" 0 Exit program");
+        println!(" 1 View binding energy summary");
+        println!(" 2 Output binding energy by trajectory");
+        println!(" 3 Output binding energy by residue at specific time");
+        println!(" 4 Output ligand binding energy by atom at specific time");
+        println!(" 5 Output entropy convergence by frames and time windows");
+        println!(" 6 Toggle entropy method for ΔG, current: {}", ENTROPY_METHODS[entropy_method]);
+        println!(" 7 Output pairwise residue energy matrix at specific time");
+        println!(" 8 Output ΔΔG hot spots of mutations and coupling of multiple mutations");
+        println!("10 Output residue-wised binding energy by time");
+        let sel_fun = get_input_selection();
+        match sel_fun {
+            Ok(-1) => {
+                println!("Input the time point (in ns) to output (default: average):");
+                let ts_ids = get_time_range(result_wt);
+                if ts_ids.is_empty() {
+                    println!("Not valid time.");
+                    continue;
+                }
+                println!("Writing pdb and pml file(s)...");
+                for result in &results {
+                    let def_name = format!("MMPBSA_binding_energy_{}.pdb", sys_name);
+                    write_pdb_with_bf(result, &def_name, &ts_ids, wd, &(0..result.atom_res.len()).collect(), true);
+                    let pml_name = format!("MMPBSA_binding_energy_{}.pml", sys_name);
+                    let png_name = format!("MMPBSA_binding_energy_{}", sys_name);
+                    write_pml(&pml_name, &def_name, &png_name, wd, settings);
+                }
+                println!("Finished writing pdb file(s) with binding energy information.");
+                println!("Finished drawing figures with pml file(s) by PyMOL.");
+            },
+            Ok(0) => exit(0),
+            Ok(1) => {
+                println!("Input the time point (in ns) to output (default: average):");
+                let ts_ids = get_time_range(result_wt);
+                if ts_ids.is_empty() {
+                    println!("Not valid time.");
+                    continue;
+                }
+                for result in &results {
+                    analyze_summary(result, temperature, wd, &format!("{}-{}", sys_name, result.mutation), &ts_ids, entropy_method)
+                }
+            },
+            Ok(2) => {
+                for result in &results {
+                    analyze_traj(result, wd, &format!("{}-{}", sys_name, result.mutation))
+                }
+            },
+            Ok(3) => {
+                println!("Input the time point (in ns) to output (default: average):");
+                let ts_ids = get_time_range(result_wt);
+                if ts_ids.is_empty() {
+                    println!("Not valid time.");
+                    continue;
+                }
+                let (range_des, target_res) = select_res_by_range(result_wt);
+                println!("Writing energy file(s)...");
+                for result in &results {
+                    analyze_res(result, wd, &format!("{}-{}", sys_name, result.mutation), &ts_ids, &range_des, &target_res);
+                }
+                println!("Finished writing residue-wised binding energy file(s).");
+            },
+            Ok(4) => {
+                for result in &results {
+                    analyze_atom(result, wd, &format!("{}-{}", sys_name, result.mutation))
+                }
+                println!("Finished writing atom-wised binding energy pdb file(s) for ligand.");
+            },
+            Ok(5) => {
+                for result in &results {
+                    analyze_entropy(result, temperature, wd, &format!("{}-{}", sys_name, result.mutation))
+                }
+            },
+            Ok(6) => {
+                entropy_method = (entropy_method + 1) % ENTROPY_METHODS.len();
+            },
+            Ok(7) => {
+                println!("Input the time point (in ns) to output (default: average):");
+                let ts_ids = get_time_range(result_wt);
+                if ts_ids.is_empty() {
+                    println!("Not valid time.");
+                    continue;
+                }
+                for result in &results {
+                    analyze_pairwise(result, wd, &format!("{}-{}", sys_name, result.mutation), &ts_ids);
+                }
+            },
+            Ok(8) => {
+                println!("Input the time point (in ns) to output (default: average):");
+                let ts_ids = get_time_range(result_wt);
+                if ts_ids.is_empty() {
+                    println!("Not valid time.");
+                    continue;
+                }
+                analyze_mutations(&results, temperature, wd, sys_name, &ts_ids, entropy_method, settings);
+            },
+            Ok(10) => {
+                let (range_des, target_res) = select_res_by_range(result_wt);
+                for result in &results {
+                    analyze_res_traj(result, wd, &format!("{}-{}", sys_name, result.mutation), &range_des, &target_res);
+                }
+                println!("Finished writing residue-wised energy by time.");
+            },
+            Ok(_) => {},
+            Err(_) => {}
+        }
+    }
+}
+
+fn get_time_range(result: &SMResult) -> Vec<usize> {
+    let ts = get_input("".to_string());
+    if !ts.trim().is_empty() {
+        let ts: Vec<f64> = utils::range2list(&ts).iter().map(|&i| i as f64).collect();
+        get_time_index(&ts, result)
+    } else {
+        (0..result.times.len()).collect()
+    }
+}
+
+fn select_res_by_range(results: &SMResult) -> (String, Vec<usize>) {
+    println!("Determine the residue range to output:");
+    println!(" 1 Ligand and receptor residues by: CA within 4 A");
+    println!(" 2 Ligand and receptor residues by: CA within 6 A");
+    println!(" 3 Ligand and receptor residues by: CA within 8 A");
+    println!(" 4 Ligand and receptor residues by: CA within a specified distance");
+    println!(" 5 Self-defined residue range");
+    // 残基范围确定
+    let i = get_input_selection().unwrap();
+    let mut range_des = String::from("4A");
+    let target_res = match i {
+        1 => {
+            get_residue_range_from_results(results, 4.0)
+        },
+        2 => {
+            range_des = String::from("6A");
+            get_residue_range_from_results(results, 6.0)
+        },
+        3 => {
+            range_des = String::from("8A");
+            get_residue_range_from_results(results, 8.0)
+        },
+        4 => {
+            println!("Input the cut-off distance you want to expand from ligand, default: 4");
+            let cutoff = get_input(4.0);
+            range_des = format!("{:.1}A", cutoff);
+            get_residue_range_from_results(results, cutoff)
+        },
+        5 => {
+            let mut res_range = String::new();
+            loop {
+                println!("Input the residue range you want to output (e.g., 1-3, 5), default: all");
+                println!("Input \"?\" to view the residues list");
+                res_range = get_input(res_range);
+                if res_range.eq("?") {
+                    results.residues.iter().enumerate().for_each(|(i, r)| {
+                        print!("{}{}, ", r.nr, r.name);
+                        if (i + 1) % 5 == 0 {
+                            println!();
+                        }
+                    });
+                    println!();
+                } else {
+                    break;
+                }
+            }
+            range_des = res_range.to_string();
+            let res_range: Vec<i32> = match res_range.len() {
+                0 => {
+                    range_des = "all".to_string();
+                    results.residues.iter().map(|r| r.nr).collect()
+                },
+                _ => range2list(&res_range)
+            };
+            results.atom_res
+                .iter()
+                .filter(|&&i| res_range.contains(&(results.residues[i].nr)))    // 用户筛选用nr
+                .map(|&i| results.residues[i].id)     // 索引用id
+                .collect()
+        },
+        _ => vec![],
+    };
+    (range_des, target_res)
+}
@@ -1,12 +1,15 @@
 use std::io::stdin;
 use std::path::Path;
-use crate::settings::Settings;
-use crate::utils::{append_new_name, get_input, get_input_selection, make_ndx};
-use crate::{confirm_file_validity, convert_cur_dir, set_program};
+use s_mmpbsa::settings::Settings;
+use s_mmpbsa::utils::{append_new_name, get_input, get_input_selection, make_ndx};
+use s_mmpbsa::{convert_cur_dir, set_program};
+use crate::{confirm_file_validity, ExitOnError};
 use crate::fun_para_system::{set_para_trj, set_para_trj_pdbqt};
-use crate::parse_tpr::TPR;
+use s_mmpbsa::parse_tpr::TPR;
+use s_mmpbsa::gb::GB_MODELS;
 
 fn list_basic_programs(settings: &mut Settings) {
+    println!(" -6 Set number of frames calculated in parallel, current: {}", settings.parallel_frames);
     println!(" -5 Set number of parallel kernels, current: {}", settings.nkernels);
     println!(" -4 Set delphi path, current: {}", match &settings.delphi_path {
         Some(s) => s.to_string(),
@@ -29,7 +32,7 @@ fn set_basic_programs(opt: i32, settings: &mut Settings) {
         -2 => {
             println!("Input PBSA kernel (if empty, means not to do PBSA calculation):");
             let s: String = get_input_selection().unwrap();
-            if s.eq("apbs") || s.eq("delphi") {
+            if s.eq("apbs") || s.eq("delphi") || s.eq("builtin") || GB_MODELS.contains(&s.as_str()) {
                 settings.pbsa_kernel = Some(s)
             } else {
                 settings.pbsa_kernel = None
@@ -55,6 +58,10 @@ fn set_basic_programs(opt: i32, settings: &mut Settings) {
             println!("Input number of parallel kernels (default: 16):");
             settings.nkernels = get_input(16);
         }
+        -6 => {
+            println!("Input number of frames calculated in parallel (default: 1):");
+            settings.parallel_frames = get_input(1);
+        }
         _ => {}
     }
 }
@@ -62,7 +69,7 @@ fn set_basic_programs(opt: i32, settings: &mut Settings) {
 pub fn set_para_basic_tpr(tpr_path: &String, wd: &Path, settings: &mut Settings) {
     let mut trj = String::new();
     let mut ndx = String::new();
-    let mut tpr = TPR::from(&tpr_path, &settings);
+    let mut tpr = TPR::from(&tpr_path, &settings).unwrap_or_else(|e| e.exit());
     println!("\nFinished loading input file.");
 
     loop {
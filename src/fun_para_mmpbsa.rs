@@ -2,27 +2,34 @@ use std::io::stdin;
 use std::path::Path;
 use ndarray::Array3;
 
-use crate::utils::{self, get_input, get_input_selection, get_residue_range_ca};
-use crate::index_parser::Index;
-use crate::settings::Settings;
-use crate::apbs_param::{PBASet, PBESet};
+use s_mmpbsa::utils::{self, get_input, get_input_selection, get_residue_range_ca};
+use s_mmpbsa::index_parser::Index;
+use s_mmpbsa::settings::Settings;
+use s_mmpbsa::apbs_param::{PBASet, PBESet};
 use std::io::Write;
 use std::fs::{File, self};
-use crate::atom_property::AtomProperties;
-use crate::parse_tpr::{Residue, TPR};
-use crate::mmpbsa;
-use crate::analyzation;
+use s_mmpbsa::atom_property::AtomProperties;
+use s_mmpbsa::atom_radius::RADIUS_TYPES;
+use s_mmpbsa::parse_tpr::{Residue, TPR};
+use s_mmpbsa::mmpbsa;
+use s_mmpbsa::mutation::Mutation;
+use crate::fun_para_analyzation;
+use s_mmpbsa::solvation_kernel::check_kernel;
+use crate::ExitOnError;
 
 pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>, 
                        tpr: &TPR, ndx: &Index, wd: &Path, aps: &mut AtomProperties,
                        ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>,
                        receptor_grp: usize, ligand_grp: Option<usize>,
-                       residues: &Vec<Residue>, settings: &mut Settings) {
-    // kinds of radius types
-    let radius_types = vec!["ff", "amber", "Bondi", "mBondi", "mBondi2"];
+                       residues: &[Residue], settings: &mut Settings) {
+    let radius_types = RADIUS_TYPES.to_vec();
     let mut pbe_set = PBESet::new(tpr.temp);
     let mut pba_set = PBASet::new(tpr.temp);
-    let mut ala_list: Vec<i32> = vec![];
+    let mut scan_list: Vec<i32> = vec![];
+    let mut lig_scan_list: Vec<i32> = vec![];
+    let mut scan_target = String::from("ALA");
+    let mut group_list: Vec<String> = vec![];
+    let mut mutation_list: Vec<String> = vec![];
     loop {
         println!("\n                 ************ MM/PB-SA Parameters ************");
         println!("-10 Return");
@@ -35,7 +42,7 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
             2 => "Supernova's method",
             _ => "None"
         });
-        println!("  2 Select residues list for alanine scanning, current: {:?}", ala_list);
+        println!("  2 Select residues list for mutation scanning, current: {:?}, ligand: {:?}, mutations: {:?}", scan_list, lig_scan_list, mutation_list);
         println!("  3 Select atom radius type, current: {}", radius_types[settings.radius_type]);
         println!("  4 Input atom distance cutoff for MM calculation (A), current: {}", settings.r_cutoff);
         println!("  5 Input coarse grid expand factor (cfac), current: {}", settings.cfac);
@@ -43,6 +50,13 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
         println!("  7 Input fine mesh spacing (df), current: {} A", settings.df);
         println!("  8 Prepare PB parameters for APBS");
         println!("  9 Prepare SA parameters for APBS");
+        println!(" 10 Choose pairwise decomposition of receptor residues with, current: {}", match settings.pairwise {
+            1 => "ligand residues",
+            2 => "ligand atoms",
+            _ => "None"
+        });
+        println!(" 11 Choose target residue for mutation scanning, current: {}", scan_target);
+        println!(" 12 Input ligand atom groups for deletion scanning, current: {:?}", group_list);
         let i = get_input_selection();
         match i {
             Ok(-10) => return,
@@ -95,9 +109,14 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                 paras.write_all(format!("Fine mesh spacing (df): {} A\n\n", settings.df).as_bytes()).unwrap();
                 paras.write_all(format!("PB settings:\n{}\n\n", pbe_set).as_bytes()).unwrap();
                 paras.write_all(format!("SA settings:\n{}\n", pba_set).as_bytes()).unwrap();
+                paras.write_all(format!("SA energy = {} * SASA + {} (kJ/mol)\n", pba_set.surften, pba_set.surfoff).as_bytes()).unwrap();
                 println!("PBSA parameters have been written to paras_pbsa.txt");
             }
             Ok(0) => {
+                if let Err(e) = check_kernel(settings) {
+                    println!("Error: {}", e);
+                    continue;
+                }
                 // Apply atom radius
                 println!("Applying {} radius...", radius_types[settings.radius_type]);
                 aps.apply_radius(settings.radius_type, &tpr.get_at_list(), &radius_types, wd);
@@ -111,10 +130,12 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                     sys_name = input.trim().to_string();
                 }
                 let temp_dir = wd.join(&sys_name);
-                if let Some(_) = settings.apbs_path.as_ref() {
+                if settings.pbsa_kernel.is_some() {
                     println!("Temporary files will be placed at {}/", temp_dir.display());
                     if !temp_dir.is_dir() {
                         fs::create_dir(&temp_dir).expect(format!("Failed to create temp directory: {}.", &sys_name).as_str());
+                    } else if settings.resume {
+                        println!("Directory {}/ exists, will resume from its checkpoints.", temp_dir.display());
                     } else {
                         println!("Directory {}/ not empty. Clear? [Y/n]", temp_dir.display());
                         let mut input = String::from("");
@@ -125,14 +146,19 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                         }
                     }
                 } else {
-                    println!("Note: Since APBS not found, solvation energy will not be calculated.");
+                    println!("Note: Since no PBSA kernel selected, solvation energy will not be calculated.");
                 };
                 
                 // run MM/PB-SA calculations
-                let (result_wt, result_as) = mmpbsa::fun_mmpbsa_calculations(&time_list, &coordinates, &temp_dir, &sys_name, &aps,
-                                                                &ndx_rec, &ndx_lig, &ala_list, &residues, wd,
-                                                                &pbe_set, &pba_set, settings);
-                analyzation::analyze_controller(&result_wt, &result_as, pbe_set.temp, &sys_name, wd, settings);
+                let mut mutations: Vec<Mutation> = scan_list.iter().filter_map(|&nr| Mutation::new(nr, &scan_target, false)).collect();
+                mutations.extend(lig_scan_list.iter().filter_map(|&nr| Mutation::new(nr, &scan_target, true)));
+                mutations.extend(group_list.iter().filter_map(|g| Mutation::group(g)));
+                mutations.extend(mutation_list.iter().filter_map(|m| Mutation::from(m)));
+                let (result_wt, result_as) = mmpbsa::fun_mmpbsa_calculations(time_list, coordinates, &temp_dir, &sys_name, aps,
+                                                                ndx_rec, ndx_lig, &mutations, residues, wd,
+                                                                &pbe_set, &pba_set, settings)
+                                                                .unwrap_or_else(|e| e.exit());
+                fun_para_analyzation::analyze_controller(&result_wt, &result_as, pbe_set.temp, &sys_name, wd, settings);
             }
             Ok(1) => {
                 println!("Input the electrostatic screening method:");
@@ -140,32 +166,33 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                 settings.elec_screen = get_input(1);
             }
             Ok(2) => {
-                println!("Select the residues for alanine scanning:");
+                println!("Select the residues for mutation scanning:");
                 println!(" 1 Select the residues within the first layer (0-4 A)");
                 println!(" 2 Select the residues within the second layer (4-6 A)");
                 println!(" 3 Select the residues within the third layer (6-8 A)");
                 println!(" 4 Select the residues within specific distance");
                 println!(" 5 Directly input the resudues list");
+                println!(" 6 Select the ligand residues within specific distance of receptor");
+                println!(" 7 Directly input the ligand resudues list");
+                println!(" 8 Directly input the mutations, e.g. Y42G,D25A+I50A");
                 let i: i32 = get_input_selection().unwrap();
-                let receptor_res: Vec<Residue> = residues.iter().filter_map(|r| if r.id != aps.atom_props[ndx_lig[0]].resid {
-                    Some(r.clone())
-                } else {
-                    None
-                }).collect();
-                let atom_res = &aps.atom_props.iter().map(|a| a.resid).collect();
-                let atom_names = &aps.atom_props.iter().map(|a| a.name.to_string()).collect();
+                let lig_res: Vec<usize> = ndx_lig.iter().map(|&i| aps.atom_props[i].resid).collect();
+                let (ligand_res, receptor_res): (Vec<Residue>, Vec<Residue>) = residues.iter().cloned()
+                    .partition(|r| lig_res.contains(&r.id));
+                let atom_res: Vec<usize> = aps.atom_props.iter().map(|a| a.resid).collect();
+                let atom_names: Vec<String> = aps.atom_props.iter().map(|a| a.name.to_string()).collect();
                 match i {
                     1 => {
                         let rs = get_residue_range_ca(&tpr.coordinates, ndx_lig, 4.0, 
                             &atom_res, &atom_names, &receptor_res);
-                        ala_list = rs.iter().filter_map(|&i| Some(residues[i].nr)).collect();
+                        scan_list = rs.iter().filter_map(|&i| Some(residues[i].nr)).collect();
                     },
                     2 => {
                         let rs = get_residue_range_ca(&tpr.coordinates, ndx_lig, 6.0, 
                             &atom_res, &atom_names, &receptor_res);
                         let inner_rs = get_residue_range_ca(&tpr.coordinates, ndx_lig, 4.0, 
                             &atom_res, &atom_names, &receptor_res);
-                        ala_list = rs.iter().filter_map(|&i| if !inner_rs.contains(&i) {
+                        scan_list = rs.iter().filter_map(|&i| if !inner_rs.contains(&i) {
                             Some(residues[i].nr)
                         } else {
                             None
@@ -176,7 +203,7 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                             &atom_res, &atom_names, &receptor_res);
                         let inner_rs = get_residue_range_ca(&tpr.coordinates, ndx_lig, 6.0, 
                             &atom_res, &atom_names, &receptor_res);
-                        ala_list = rs.iter().filter_map(|&i| if !inner_rs.contains(&i) {
+                        scan_list = rs.iter().filter_map(|&i| if !inner_rs.contains(&i) {
                             Some(residues[i].nr)
                         } else {
                             None
@@ -187,12 +214,36 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                         let cutoff = get_input(4.0);
                         let rs = get_residue_range_ca(&tpr.coordinates, ndx_lig, cutoff, 
                             &atom_res, &atom_names, &receptor_res);
-                        ala_list = rs.iter().filter_map(|&i| Some(residues[i].nr)).collect();
+                        scan_list = rs.iter().filter_map(|&i| Some(residues[i].nr)).collect();
                     },
                     5 => {
-                        println!("Input the residues list for alanine scanning:");
+                        println!("Input the residues list for mutation scanning:");
                         let rs = get_input("".to_string());
-                        ala_list = utils::range2list(rs.as_str());
+                        scan_list = utils::range2list(rs.as_str());
+                    },
+                    6 => {
+                        println!("Input the cut-off distance you want to expand from receptor, default: 4 A");
+                        let cutoff = get_input(4.0);
+                        let rs = get_residue_range_ca(&tpr.coordinates, ndx_rec, cutoff, 
+                            &atom_res, &atom_names, &ligand_res);
+                        lig_scan_list = rs.iter().map(|&i| residues[i].nr).collect();
+                    },
+                    7 => {
+                        println!("Input the ligand residues list for mutation scanning:");
+                        let rs = get_input("".to_string());
+                        lig_scan_list = utils::range2list(rs.as_str());
+                    },
+                    8 => {
+                        println!("Input the mutations, with + joining multiple mutations:");
+                        let ms: String = get_input("".to_string());
+                        mutation_list = ms.split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect();
+                        mutation_list.retain(|m| match Mutation::from(m) {
+                            Some(_) => true,
+                            None => {
+                                println!("Warning: invalid mutation {} ignored.", m);
+                                false
+                            }
+                        });
                     },
                     _ => {}
                 }
@@ -270,7 +321,10 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                 println!("PB parameters have been wrote to {0}.\n\
                     Edit it and input its path to reload (default: {0}).", &pb_fpath.to_str().unwrap());
                 let pb_fpath = get_input(pb_fpath.to_str().unwrap().to_string());
-                pbe_set = PBESet::load_params(pb_fpath);
+                match PBESet::load_params(pb_fpath) {
+                    Ok(p) => pbe_set = p,
+                    Err(e) => println!("Error: {}, parameters not changed.", e)
+                }
             }
             Ok(9) => {
                 let sa_fpath = wd.join("SA_settings.yaml");
@@ -278,7 +332,38 @@ pub fn set_para_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
                 println!("SA parameters have been wrote to {0}.\n\
                     Edit it and input its path to reload (default: {0}).", &sa_fpath.to_str().unwrap());
                 let sa_fpath = get_input(sa_fpath.to_str().unwrap().to_string());
-                pba_set = PBASet::load_params(sa_fpath);
+                match PBASet::load_params(sa_fpath) {
+                    Ok(p) => pba_set = p,
+                    Err(e) => println!("Error: {}, parameters not changed.", e)
+                }
+            }
+            Ok(10) => {
+                println!("Input the pairwise decomposition mode:");
+                println!("0: none\n1: receptor residues with ligand residues\n2: receptor residues with ligand atoms");
+                settings.pairwise = get_input(0).min(2);
+            }
+            Ok(11) => {
+                println!("Input the target residue for mutation scanning (e.g. ALA, GLY, F), default: ALA");
+                let target: String = get_input("ALA".to_string());
+                match Mutation::new(0, &target, false) {
+                    Some(Mutation::Residue { target, .. }) => scan_target = target,
+                    _ => println!("Residue {} not valid.", target)
+                }
+            }
+            Ok(12) => {
+                println!("Input the ligand atom groups to delete one by one, e.g. 3849-3853,3860, atom ids as in ligand pdb file.");
+                println!("Press Enter without input to finish:");
+                group_list.clear();
+                loop {
+                    let g: String = get_input("".to_string());
+                    if g.is_empty() {
+                        break;
+                    }
+                    match Mutation::group(&g) {
+                        Some(_) => group_list.push(g),
+                        None => println!("Atom group {} not valid.", g)
+                    }
+                }
             }
             _ => {}
         }
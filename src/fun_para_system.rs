@@ -1,19 +1,21 @@
-use std::process::{exit, Command, Stdio};use std::env::{self, current_exe};
+use std::process::{exit, Command, Stdio};
+use std::env::{self, current_exe};
 use std::path::Path;
 use std::fs::{self, File};
 use std::io::Write;
 
-use crate::{dump_tpr, parse_mol2::MOL2};
-use crate::parse_pdb::{PDBModel, PDB};
-use crate::settings::Settings;
-use crate::utils::{self, append_new_name, get_input_selection, make_ndx, multiwfn, sobtop, trajectory};
+use s_mmpbsa::{dump_tpr, parse_mol2::MOL2};
+use s_mmpbsa::parse_pdb::{PDBModel, PDB};
+use s_mmpbsa::settings::Settings;
+use s_mmpbsa::utils::{self, append_new_name, get_input_selection, make_ndx, multiwfn, sobtop, trajectory};
 use crate::fun_para_mmpbsa::set_para_mmpbsa;
-use crate::index_parser::{Index, IndexGroup};
-use crate::parse_tpr::TPR;
-use crate::atom_property::AtomProperties;
-use crate::parse_tpr::Residue;
-use crate::utils::{convert_tpr, convert_trj, trjconv, pdb2gmx, grompp};
-use crate::parse_xvg::read_coord_xvg;
+use s_mmpbsa::index_parser::{Index, IndexGroup};
+use s_mmpbsa::parse_tpr::TPR;
+use s_mmpbsa::atom_property::AtomProperties;
+use s_mmpbsa::utils::{pdb2gmx, grompp};
+use s_mmpbsa::parse_xvg::read_coord_xvg;
+use s_mmpbsa::system::{get_residues_tpr, prepare_system_tpr};
+use crate::ExitOnError;
 
 pub fn set_para_trj(trj: &String, tpr: &mut TPR, ndx_name: &String, wd: &Path, tpr_name: &str, settings: &mut Settings) {
     let mut receptor_grp: Option<usize> = None;
@@ -22,7 +24,7 @@ pub fn set_para_trj(trj: &String, tpr: &mut TPR, ndx_name: &String, wd: &Path, t
     let mut et: f64 = tpr.dt * tpr.nsteps as f64;           // ps
     let mut dt = 1000.0;                               // ps
     let unit_dt: f64 = tpr.dt * tpr.nstxout as f64;         // ps
-    let ndx = Index::from(ndx_name);
+    let ndx = Index::from(ndx_name).unwrap_or_else(|e| e.exit());
     loop {
         println!("\n                 ************ Trajectory Parameters ************");
         println!("-10 Return");
@@ -41,7 +43,10 @@ pub fn set_para_trj(trj: &String, tpr: &mut TPR, ndx_name: &String, wd: &Path, t
             }
             Ok(0) => {
                 if let Some(receptor_grp) = receptor_grp {
-                    prepare_system_tpr(receptor_grp, ligand_grp, trj, tpr, &ndx, tpr_name, ndx_name, bt, et, dt, wd, settings);
+                    let (time_list, coordinates, mut aps, ndx_rec, ndx_lig, residues) = 
+                        prepare_system_tpr(receptor_grp, ligand_grp, trj, tpr, &ndx, tpr_name, ndx_name, bt, et, dt, wd, settings)
+                        .unwrap_or_else(|e| e.exit());
+                    set_para_mmpbsa(&time_list, &coordinates, tpr, &ndx, wd, &mut aps, &ndx_rec, &ndx_lig, receptor_grp, ligand_grp, &residues, settings);
                 } else {
                     println!("Please select receptor groups.");
                 };
@@ -138,8 +143,8 @@ pub fn set_para_trj_pdbqt(receptor_path: &String, ligand_path: &String, flex_pat
     prepare_system_tpr_pdb(rec_name, lig_name, &flex_name, ff, method, basis, total_charge, multiplicity, temp_dir, settings);
     dump_tpr(&wd.join("md.tpr").display().to_string(), 
         &wd.join("md.dump").display().to_string(), 
-        settings.gmx_path.as_ref().unwrap());
-    let tpr = TPR::from(wd.join("md.dump").to_str().unwrap(), settings);
+        settings.gmx_path.as_ref().unwrap()).unwrap_or_else(|e| e.exit());
+    let tpr = TPR::from(wd.join("md.dump").to_str().unwrap(), settings).unwrap_or_else(|e| e.exit());
 
     // fake trj
     let (pdb, rec_atoms_num, lig_atoms_num) = prepare_pymol_complex_pdb(rec_name, lig_name, temp_dir);
@@ -149,7 +154,7 @@ pub fn set_para_trj_pdbqt(receptor_path: &String, ligand_path: &String, flex_pat
     pdb.to_pdb(&trj_path.to_str().unwrap());
     trajectory(&vec!["0"], wd, settings, &trj_path.to_str().unwrap(), "md.tpr", 
         &temp_dir.join("MMPBSA_index.ndx").to_str().unwrap(), wd.join("_MMPBSA_coord.xvg").to_str().unwrap());
-    let (_, coordinates) = read_coord_xvg(wd.join("_MMPBSA_coord.xvg").to_str().unwrap());
+    let (_, coordinates) = read_coord_xvg(wd.join("_MMPBSA_coord.xvg").to_str().unwrap()).unwrap_or_else(|e| e.exit());
     let time_list = (0..coordinates.shape()[0]).map(|t| (t + 1) as f64 * 1000.0).collect();
 
     // fake ndx
@@ -211,65 +216,6 @@ pub fn set_para_trj_pdbqt(receptor_path: &String, ligand_path: &String, flex_pat
     }
 }
 
-// convert rec and lig to begin at 0 and continous
-pub fn normalize_index(ndx_rec: &Vec<usize>, ndx_lig: Option<&Vec<usize>>) -> (Vec<usize>, Vec<usize>) {
-    if let Some(ndx_lig) = ndx_lig {
-        let mut ndx_rec_norm = ndx_rec.clone();
-        let mut ndx_lig_norm = ndx_lig.clone();
-        let last_atom = ndx_rec.len() + ndx_lig.len() - 1;
-        for cur_atom_id in 0..=last_atom {
-            if !ndx_lig_norm.contains(&cur_atom_id) && !ndx_rec_norm.contains(&cur_atom_id) {
-                let ndx_lig_norm2 = ndx_lig_norm.clone();
-                let ndx_rec_norm2 = ndx_rec_norm.clone();
-                let next_edge_lig = ndx_lig_norm2.iter().find(|&&i| i > cur_atom_id);
-                let next_edge_rec = ndx_rec_norm2.iter().find(|&&i| i > cur_atom_id);
-                let offset = if next_edge_lig.is_none() {
-                    if next_edge_rec.is_none() {
-                        0
-                    } else {
-                        next_edge_rec.unwrap() - cur_atom_id
-                    }
-                } else {
-                    if next_edge_rec.is_none() {
-                        next_edge_lig.unwrap() - cur_atom_id
-                    } else {
-                        next_edge_lig.unwrap().min(next_edge_rec.unwrap()) - cur_atom_id
-                    }
-                };
-                ndx_lig_norm.iter_mut().for_each(|i| if *i > cur_atom_id { *i -= offset } );
-                ndx_rec_norm.iter_mut().for_each(|i| if *i > cur_atom_id { *i -= offset } );
-            }
-        }
-        (ndx_rec_norm, ndx_lig_norm)
-    } else {
-        ((0..ndx_rec.len()).collect(), (0..ndx_rec.len()).collect())
-    }
-}
-
-pub fn get_residues_tpr(tpr: &TPR, ndx_com: &Vec<usize>) -> Vec<Residue> {
-    let mut residues: Vec<Residue> = vec![];
-    let mut idx = 0;
-    let mut resind_offset = 0;
-    
-    for mol in &tpr.molecules {
-        for _ in 0..tpr.molecule_types[mol.molecule_type_id].molecules_num {
-            for atom in &mol.atoms {
-                idx += 1;
-                if ndx_com.contains(&idx) && residues.len() <= atom.resind + resind_offset {
-                    let mut cur_res = mol.residues[atom.resind].to_owned();
-                    let prev_resnr_list : Vec<i32> = residues.iter().map(|r| r.nr).collect();
-                    if prev_resnr_list.contains(&cur_res.nr) {
-                        cur_res.nr = *prev_resnr_list.last().unwrap() + 1;
-                    }
-                    residues.push(cur_res);
-                }
-            }
-            resind_offset += mol.residues.len();
-        }
-    }
-    residues
-}
-
 fn show_grp(grp_id: Option<usize>, ndx: &Index) -> String {
     if let Some(grp_id) = grp_id {
         if let Some(grp) = ndx.groups.get(grp_id) {
@@ -282,123 +228,6 @@ fn show_grp(grp_id: Option<usize>, ndx: &Index) -> String {
     }
 }
 
-fn prepare_system_tpr(receptor_grp: usize, ligand_grp: Option<usize>, 
-                  trj: &String, tpr: &mut TPR, ndx: &Index, 
-                  tpr_name: &str, ndx_name: &String, 
-                  bt: f64, et: f64, dt: f64, 
-                  wd: &Path, settings: &mut Settings) {
-    // atom indexes
-    println!("Preparing atom indexes...");
-    let ndx_lig = match ligand_grp {
-        Some(ligand_grp) => Some(&ndx.groups[ligand_grp].indexes),
-        None => None
-    };
-    let ndx_rec = &ndx.groups[receptor_grp].indexes;
-    let ndx_com = match ndx_lig {
-        Some(ndx_lig) => {
-            match ndx_lig[0] > ndx_rec[0] {
-                true => {
-                    let mut ndx_com = ndx_rec.to_vec();
-                    ndx_com.extend(ndx_lig);
-                    ndx_com
-                }
-                false => {
-                    let mut ndx_com = ndx_lig.to_vec();
-                    ndx_com.extend(ndx_rec);
-                    ndx_com
-                }
-            }
-        }
-        None => ndx_rec.to_vec()
-    };
-
-    // atom properties
-    println!("Parsing atom properties...");
-    let mut aps = AtomProperties::from_tpr(tpr, &ndx_com);
-    println!("Collecting residues list...");
-    let residues = get_residues_tpr(tpr, &ndx_com);
-
-    // pre-treat trajectory: fix pbc
-    let trj_mmpbsa = append_new_name(trj, ".xtc", "_MMPBSA_"); // get trj output file name
-    let tpr_name = append_new_name(tpr_name, ".tpr", ""); // fuck the passed tpr name is dump
-    
-    // step 1: generate new index
-    println!("Generating Index...");
-    // gmx make_ndx -f md.tpr -n index.idx -o md_trj_whole.xtc -pbc whole
-    let ndx_whole = append_new_name(ndx_name, "_whole.ndx", "_MMPBSA_"); // get extracted index file name
-    if let Some(ligand_grp) = ligand_grp {
-        make_ndx(&vec![
-            format!("{} | {}", receptor_grp, ligand_grp).as_str(),
-            format!("name {} Complex", ndx.groups.len()).as_str(),
-            format!("name {} Receptor", receptor_grp).as_str(),
-            format!("name {} Ligand", ligand_grp).as_str(),
-            "q"
-        ], wd, settings, &tpr_name, ndx_name, &ndx_whole);
-    } else {
-        make_ndx(&vec![
-            // complex is receptor
-            format!("name {} Complex", receptor_grp).as_str(),
-            "q"
-        ], wd, settings, &tpr_name, ndx_name, &ndx_whole);
-    }
-    
-    // step 2: extract new trj with old tpr and new index
-    println!("Extracting trajectory, be patient...");
-    trjconv(&vec!["Complex"], wd, settings, &trj, &tpr_name, &ndx_whole, &trj_mmpbsa, 
-        &vec!["-t0", "0", "-dt", &dt.to_string(), "-b", &bt.to_string(), "-e", &et.to_string()]);
-    
-    // step 3: extract new tpr from old tpr
-    let tpr_mmpbsa = append_new_name(&tpr_name, ".tpr", "_MMPBSA_"); // get extracted tpr file name
-    convert_tpr(&vec!["Complex"], wd, settings, &tpr_name, &ndx_whole, &tpr_mmpbsa);
-    if !settings.debug_mode {
-        fs::remove_file(&ndx_whole).unwrap();
-    }
-    
-    // step 4: generate new index with new tpr
-    println!("Normalizing index...");
-    let (ndx_rec, ndx_lig) = 
-        normalize_index(&ndx.groups[receptor_grp].indexes, match ligand_grp {
-            Some(ligand_grp) => Some(&ndx.groups[ligand_grp].indexes),
-            None => None
-        });
-    // 需要处理一下atom_properties的id
-    aps.atom_props.iter_mut().enumerate().for_each(|(i, ap)| ap.id = i);
-    
-    // extract index file
-    let ndx_mmpbsa = match ligand_grp {
-        Some(_) => {
-            Index::new(vec![
-                IndexGroup::new("Complex", &ndx_rec.iter().chain(ndx_lig.iter()).cloned().collect()), 
-                IndexGroup::new("Receptor", &ndx_rec),
-                IndexGroup::new("Ligand", &ndx_lig)
-            ])
-        },
-        None => {
-            Index::new(vec![
-                IndexGroup::new("Complex", &ndx_rec)
-            ])
-        }
-    };
-    ndx_mmpbsa.to_ndx(wd.join("_MMPBSA_index.ndx").to_str().unwrap());
-    let ndx_mmpbsa = wd.join("_MMPBSA_index.ndx");
-    let ndx_mmpbsa = ndx_mmpbsa.to_str().unwrap();
-    // 在这里 remove pbc, convert-trj有bug, 不能处理不完整蛋白, 故先trjconv再convert-trj
-    if settings.fix_pbc {
-        let other_params = vec!["-rmpbc", "-select", "Complex"];
-        let pbc_name = append_new_name(&trj_mmpbsa, "_pbc.xtc", "");
-        convert_trj(&vec![], wd, settings, &trj_mmpbsa, &tpr_mmpbsa, &ndx_mmpbsa, &pbc_name, &other_params);
-        println!("Loading trajectory coordinates...");
-        trajectory(&vec!["Complex"], wd, settings, &pbc_name, &tpr_mmpbsa, &ndx_mmpbsa, "_MMPBSA_coord.xvg");
-    } else {
-        println!("Loading trajectory coordinates...");
-        trajectory(&vec!["Complex"], wd, settings, &trj_mmpbsa, &tpr_mmpbsa, &ndx_mmpbsa, "_MMPBSA_coord.xvg");
-    }
-
-    let (time_list, coordinates) = read_coord_xvg(wd.join("_MMPBSA_coord.xvg").to_str().unwrap());
-
-    set_para_mmpbsa(&time_list, &coordinates, tpr, &ndx, wd, &mut aps, &ndx_rec, &ndx_lig, receptor_grp, ligand_grp, &residues, settings);
-}
-
 fn pdbqt2pdb(rec_name: &str, lig_name: &str, flex_name: &Option<&str>, temp_dir: &Path, settings: &Settings) {
     let out_rec_name = append_new_name(rec_name, ".pdb", "MMPBSA_docking_");
     let out_lig_name = append_new_name(lig_name, ".pdb", "MMPBSA_docking_");
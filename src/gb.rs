@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use ndarray::{Array1, ArrayView2};
+use rayon::prelude::*;
+use crate::apbs_param::PBESet;
+use crate::atom_property::AtomProperties;
+use crate::atom_radius::{get_radii, get_radii_map};
+use crate::coefficients::Coefficients;
+
+const COULOMB: f64 = 1389.35457520287;  // e^2/(4πε0), unit: kJ mol^-1 A e^-2
+const OFFSET: f64 = 0.09;               // dielectric offset of radius (A)
+const RW: f64 = 1.4;                    // solvent probe radius for GBn neck (A)
+
+// GB models as in AMBER: hct (igb=1), obc1 (igb=2), obc2 (igb=5), gbn (igb=7)
+pub const GB_MODELS: [&str; 4] = ["hct", "obc1", "obc2", "gbn"];
+
+// intrinsic radii of a GB model, with GBn neck parameters of all their pairs, prepared once for all frames
+pub struct GBModel<'a> {
+    name: &'a str,
+    radii_table: HashMap<String, f64>,
+    necks: HashMap<(u64, u64), (f64, f64)>,
+}
+
+impl<'a> GBModel<'a> {
+    pub fn new(name: &'a str) -> GBModel<'a> {
+        // intrinsic radii sets recommended by AMBER
+        let radii_table = get_radii_map(match name {
+            "hct" => "mBondi",
+            "gbn" => "Bondi",
+            _ => "mBondi2"
+        });
+        let necks = match name {
+            "gbn" => neck_params(radii_table.values().cloned()),
+            _ => HashMap::new()
+        };
+        GBModel { name, radii_table, necks }
+    }
+}
+
+// Per-atom polar solvation energies (kJ/mol) of com - rec - lig, or of rec if no ligand, by generalized Born model
+pub fn calc_gb(ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, coord: &ArrayView2<f64>, aps: &AtomProperties,
+               model: &GBModel, pbe_set: &PBESet) -> Array1<f64> {
+    let atoms: Vec<GBAtom> = aps.atom_props.iter().enumerate().map(|(i, a)| {
+        let name = a.name.to_uppercase();
+        GBAtom {
+            x: [coord[[i, 0]], coord[[i, 1]], coord[[i, 2]]],
+            q: a.charge,
+            rho: get_radii(&model.radii_table, &name),
+            screen: screen_factor(&name, model.name),
+        }
+    }).collect();
+
+    let mut de_gb: Array1<f64> = Array1::zeros(aps.atom_props.len());
+    if ndx_lig[0] != ndx_rec[0] {
+        let ndx_com: Vec<usize> = (0..aps.atom_props.len()).collect();
+        for (i, e) in ndx_com.iter().zip(gb_energy(&ndx_com, &atoms, model, pbe_set)) {
+            de_gb[*i] += e;
+        }
+        for ndx in [ndx_rec, ndx_lig] {
+            for (i, e) in ndx.iter().zip(gb_energy(ndx, &atoms, model, pbe_set)) {
+                de_gb[*i] -= e;
+            }
+        }
+    } else {
+        for (i, e) in ndx_rec.iter().zip(gb_energy(ndx_rec, &atoms, model, pbe_set)) {
+            de_gb[*i] += e;
+        }
+    }
+    de_gb
+}
+
+struct GBAtom {
+    x: [f64; 3],
+    q: f64,
+    rho: f64,
+    screen: f64,
+}
+
+// descreening scale factors of AMBER, by element from atom name
+fn screen_factor(name: &str, model: &str) -> f64 {
+    let element = match name.get(0..2) {
+        Some("CL") | Some("BR") | Some("SI") => "*",
+        _ => name.get(0..1).unwrap_or("*")
+    };
+    match model {
+        "gbn" => match element {
+            "H" => 1.09085413633,
+            "C" => 0.48435382330,
+            "N" => 0.700147318409,
+            "O" => 1.06557401132,
+            "S" => 0.602256336067,
+            _ => 0.5
+        },
+        _ => match element {
+            "H" => 0.85,
+            "C" => 0.72,
+            "N" => 0.79,
+            "O" => 0.85,
+            "F" => 0.88,
+            "P" => 0.86,
+            "S" => 0.96,
+            _ => 0.8
+        }
+    }
+}
+
+fn distance(a: &GBAtom, b: &GBAtom) -> f64 {
+    ((a.x[0] - b.x[0]).powi(2) + (a.x[1] - b.x[1]).powi(2) + (a.x[2] - b.x[2]).powi(2)).sqrt()
+}
+
+// Still's formula with salt screening, pair energies split equally to both atoms
+fn gb_energy(ndx: &[usize], atoms: &[GBAtom], model: &GBModel, pbe_set: &PBESet) -> Vec<f64> {
+    let born = born_radii(ndx, atoms, model);
+    // A^-1, no screening without ions
+    let lambda_d = Coefficients::new(pbe_set).lambda_d;
+    let kappa = if lambda_d.is_finite() { 0.1 / lambda_d } else { 0.0 };
+    ndx.par_iter().zip(born.par_iter()).map(|(&i, &ri)| {
+        let e: f64 = ndx.iter().zip(born.iter()).map(|(&j, &rj)| {
+            let r2 = match i == j {
+                true => 0.0,
+                false => distance(&atoms[i], &atoms[j]).powi(2)
+            };
+            let f = (r2 + ri * rj * (-r2 / (4.0 * ri * rj)).exp()).sqrt();
+            atoms[j].q / f * (1.0 / pbe_set.pdie - (-kappa * f).exp() / pbe_set.sdie)
+        }).sum();
+        -0.5 * COULOMB * atoms[i].q * e
+    }).collect()
+}
+
+// effective Born radii by pairwise descreening integrals of HCT, rescaled as OBC, plus neck integrals for GBn
+fn born_radii(ndx: &[usize], atoms: &[GBAtom], model: &GBModel) -> Vec<f64> {
+    let (alpha, beta, gamma) = match model.name {
+        "obc1" => (0.8, 0.0, 2.909125),
+        "obc2" => (1.0, 0.8, 4.85),
+        "gbn" => (1.09511284, 1.90792938, 2.50798245),
+        _ => (0.0, 0.0, 0.0)
+    };
+    let neck_scale = 0.361825;
+    ndx.par_iter().map(|&i| {
+        let rho_i = atoms[i].rho - OFFSET;
+        let mut integral = 0.0;
+        for &j in ndx {
+            if i == j {
+                continue;
+            }
+            let r = distance(&atoms[i], &atoms[j]);
+            integral += 0.5 * hct_integral(rho_i, atoms[j].screen * (atoms[j].rho - OFFSET), r);
+            if model.name == "gbn" && r < atoms[i].rho + atoms[j].rho + 2.0 * RW {
+                let (m0, d0) = model.necks[&(atoms[i].rho.to_bits(), atoms[j].rho.to_bits())];
+                integral += neck_scale * m0 / (1.0 + 100.0 * (r - d0).powi(2) + 0.3e6 * (r - d0).powi(6));
+            }
+        }
+        let inv_r = match model.name {
+            "hct" => 1.0 / rho_i - integral,
+            _ => {
+                let psi = integral * rho_i;
+                1.0 / rho_i - (alpha * psi - beta * psi * psi + gamma * psi.powi(3)).tanh() / atoms[i].rho
+            }
+        };
+        // as AMBER, Born radius not larger than 30 A
+        1.0 / inv_r.max(1.0 / 30.0)
+    }).collect()
+}
+
+// integral of 1/r^4 over sphere j (radius s, at distance r) outside sphere i (radius rho), divided by 4π
+fn hct_integral(rho: f64, s: f64, r: f64) -> f64 {
+    if rho >= r + s {
+        return 0.0;
+    }
+    let l = rho.max((r - s).abs());
+    let u = r + s;
+    let mut h = 1.0 / l - 1.0 / u + r / 4.0 * (1.0 / (u * u) - 1.0 / (l * l)) + (l / u).ln() / (2.0 * r)
+        + s * s / (4.0 * r) * (1.0 / (l * l) - 1.0 / (u * u));
+    if rho < s - r {
+        h += 2.0 * (1.0 / rho - 1.0 / l);
+    }
+    h
+}
+
+// maximum m0 of neck integral and its position d0 for each pair of radii, used by GBn (Mongan et al., JCTC 2007, 3, 156)
+fn neck_params<I: Iterator<Item = f64>>(radii: I) -> HashMap<(u64, u64), (f64, f64)> {
+    let mut radii: Vec<f64> = radii.collect();
+    radii.sort_by(|a, b| a.total_cmp(b));
+    radii.dedup();
+    let pairs: Vec<(f64, f64)> = radii.iter().flat_map(|&a| radii.iter().map(move |&b| (a, b))).collect();
+    pairs.par_iter().map(|&(a, b)| {
+        // coarse scan then golden section search around the maximum
+        let r_min = a.max(b);
+        let r_max = a + b + 2.0 * RW;
+        let step = 0.02;
+        let r_best = (0..((r_max - r_min) / step) as usize).map(|k| r_min + k as f64 * step)
+            .map(|r| (neck_integral(a, b, r), r))
+            .fold((0.0, r_min), |best, cur| if cur.0 > best.0 { cur } else { best }).1;
+        let (mut lo, mut hi) = (r_best - step, r_best + step);
+        let g = (5f64.sqrt() - 1.0) / 2.0;
+        while hi - lo > 1e-4 {
+            let (x1, x2) = (hi - g * (hi - lo), lo + g * (hi - lo));
+            if neck_integral(a, b, x1) > neck_integral(a, b, x2) {
+                hi = x2;
+            } else {
+                lo = x1;
+            }
+        }
+        let d0 = (lo + hi) / 2.0;
+        ((a.to_bits(), b.to_bits()), (neck_integral(a, b, d0), d0))
+    }).collect()
+}
+
+// integral of 1/r^4 over the neck region between sphere a (at origin) and sphere b (at distance r), divided by 4π,
+// the neck is bounded by the two spheres and the probe touching both, integrated in cylindrical coordinates
+fn neck_integral(a: f64, b: f64, r: f64) -> f64 {
+    let (pa, pb) = (a + RW, b + RW);
+    if r >= pa + pb || r <= 0.0 {
+        return 0.0;
+    }
+    let zp = (r * r + pa * pa - pb * pb) / (2.0 * r);
+    let rp2 = pa * pa - zp * zp;
+    if rp2 <= 0.0 {
+        return 0.0;
+    }
+    let rp = rp2.sqrt();
+    let dz = 0.002;
+    let (z_start, z_end) = (zp.min(0.0), zp.max(r));
+    let mut sum = 0.0;
+    for k in 0..((z_end - z_start) / dz).ceil() as usize {
+        let z = z_start + (k as f64 + 0.5) * dz;
+        if z <= 0.0 {
+            continue;
+        }
+        // within the triangle of two atom centers and the probe center, outside the probe and both spheres
+        let mut hi = match z <= zp {
+            true => rp * z / zp,
+            false => rp * (r - z) / (r - zp)
+        };
+        if (z - zp).abs() < RW {
+            hi = hi.min(rp - (RW * RW - (z - zp).powi(2)).sqrt());
+        }
+        let mut lo: f64 = 0.0;
+        if z < a {
+            lo = lo.max((a * a - z * z).sqrt());
+        }
+        if (z - r).abs() < b {
+            lo = lo.max((b * b - (z - r).powi(2)).sqrt());
+        }
+        if hi > lo {
+            sum += (1.0 / (z * z + lo * lo) - 1.0 / (z * z + hi * hi)) * dz;
+        }
+    }
+    sum / 4.0
+}
+
@@ -2,6 +2,7 @@ use std::{fs::{self, File}, io::Write};
 use regex::Regex;
 use std::fmt::Formatter;
 use std::fmt;
+use crate::error::SmError;
 
 #[derive(Clone)]
 pub struct IndexGroup {
@@ -33,39 +34,28 @@ impl Index {
         Index{ groups }
     }
 
-    pub fn from(index_file: &String) -> Index {
-        let ndx = fs::read_to_string(index_file).expect("Failed reading index file");
+    pub fn from(index_file: &String) -> Result<Index, SmError> {
+        let ndx = fs::read_to_string(index_file).map_err(|e| SmError::io(index_file, e))?;
         let re = Regex::new(r"\[\s*(.+?)\s*]").unwrap();
-        let mut group_names: Vec<String> = vec![];          // name of each group
-        for cap in re.captures_iter(&ndx) {
-            group_names.push((&cap[1]).to_string());
-        }
-        let mut group_atoms: Vec<Vec<usize>> = vec![];         // atoms of each group
-        let ndx = re.replace_all(&ndx, "[]");
-        let ndx = Regex::new(r"\s+").unwrap().replace_all(&ndx, " ");
-        let ndx: Vec<&str> = ndx.split("[]").collect();
-        for i in 0..ndx.len() {
-            if ndx[i].len() != 0 {
-                let atom_list: Vec<&str> = ndx[i].trim().split(" ").collect();
-                let mut at_list: Vec<usize> = vec![];
-                for atom in atom_list {
-                    let at: usize = atom.parse().expect("Failed to get index atom");
-                    // gromacs index file starts at 1, but we need 0 as index slice
-                    at_list.push(at - 1);
+        let mut groups: Vec<IndexGroup> = vec![];
+        for (l, line) in ndx.lines().enumerate() {
+            if let Some(cap) = re.captures(line) {
+                groups.push(IndexGroup { name: cap[1].to_string(), indexes: vec![] });
+                continue;
+            }
+            for atom in line.split_whitespace() {
+                // gromacs index file starts at 1, but we need 0 as index slice
+                match (groups.last_mut(), atom.parse::<usize>()) {
+                    (Some(group), Ok(at)) if at > 0 => group.indexes.push(at - 1),
+                    (None, _) => return Err(SmError::parse(index_file, l + 1, "atoms found before the first group name")),
+                    _ => return Err(SmError::parse(index_file, l + 1, &format!("invalid atom index {}", atom)))
                 }
-                at_list.sort();
-                group_atoms.push(at_list);
             }
         }
-        let mut groups: Vec<IndexGroup> = vec![];
-        while group_names.len() > 0 {
-            groups.insert(0, IndexGroup {
-                name: group_names.pop().expect("Error reading index file."),
-                indexes: group_atoms.pop().expect("Error reading index file."),
-            });
+        for group in groups.iter_mut() {
+            group.indexes.sort();
         }
-        let index = Index { groups };
-        return index;
+        Ok(Index { groups })
     }
 
     pub fn list_groups(&self) {
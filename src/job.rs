@@ -0,0 +1,300 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use serde::Deserialize;
+
+use crate::analyzation;
+use crate::apbs_param::{PBASet, PBESet};
+use crate::atom_radius::RADIUS_TYPES;
+use crate::error::SmError;
+use crate::system::prepare_system_tpr;
+use crate::gb::GB_MODELS;
+use crate::solvation_kernel::check_kernel;
+use crate::index_parser::Index;
+use crate::mmpbsa;
+use crate::mutation::Mutation;
+use crate::parse_tpr::TPR;
+use crate::settings::Settings;
+use crate::utils;
+
+// Job file for `s_mmpbsa run job.toml`, relative paths are resolved from the job file directory
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    tpr: String,
+    trajectory: String,
+    index: String,
+    receptor: String,
+    ligand: Option<String>,
+    begin: Option<f64>,                 // ns
+    end: Option<f64>,                   // ns
+    interval: Option<f64>,              // ns
+    system_name: Option<String>,
+    radius_type: Option<String>,
+    screen_method: Option<usize>,
+    r_cutoff: Option<f64>,
+    fix_pbc: Option<bool>,
+    pbsa_kernel: Option<String>,
+    cfac: Option<f64>,
+    fadd: Option<f64>,
+    df: Option<f64>,
+    apbs_retries: Option<usize>,
+    n_kernels: Option<i32>,
+    parallel_frames: Option<usize>,
+    resume: Option<bool>,
+    skip_failed: Option<bool>,
+    pb_settings: Option<String>,
+    sa_settings: Option<String>,
+    alanine_scanning: Option<String>,
+    ligand_scanning: Option<String>,
+    scan_to: Option<String>,
+    mutations: Option<String>,
+    group_deletion: Option<Vec<String>>,
+    temperature: Option<f64>,
+    res_cutoff: Option<f64>,
+    entropy_method: Option<String>,
+    pairwise: Option<String>,
+    hot_spot: Option<f64>,
+    warm_spot: Option<f64>,
+}
+
+impl Job {
+    pub fn from(job_file: &Path) -> Result<Job, SmError> {
+        let content = fs::read_to_string(job_file).map_err(|e| SmError::io(&job_file.display().to_string(), e))?;
+        toml::from_str(&content).map_err(|e| {
+            let line = e.line_col().map(|(l, _)| l + 1).unwrap_or(0);
+            SmError::parse(&job_file.display().to_string(), line, &e.to_string())
+        })
+    }
+}
+
+pub fn run_job(job_file: &str, settings: &mut Settings) -> Result<(), SmError> {
+    run(Path::new(job_file), settings)
+}
+
+fn run(job_file: &Path, settings: &mut Settings) -> Result<(), SmError> {
+    println!("Loading job file: {}", job_file.display());
+    let job = Job::from(job_file)?;
+    // the temp directory <wd>/<system_name> is cleared before the run unless resuming, and removed after it without debug_mode
+    let sys_name = job.system_name.clone().unwrap_or("system".to_string());
+    let mut components = Path::new(&sys_name).components();
+    if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+        return Err(SmError::Input(format!("Invalid system name: \"{}\", should be a plain file name", sys_name)));
+    }
+    let job_file = fs::canonicalize(job_file).map_err(|e| SmError::io(&job_file.display().to_string(), e))?;
+    let job_dir = job_file.parent().unwrap_or(Path::new("."));
+
+    // check input files
+    let tpr_path = resolve_file(job_dir, &job.tpr, "tpr", &["tpr"])?;
+    let trj_path = resolve_file(job_dir, &job.trajectory, "trajectory", &["xtc", "trr"])?;
+    let ndx_path = resolve_file(job_dir, &job.index, "index", &["ndx"])?;
+    let pb_path = match &job.pb_settings {
+        Some(p) => Some(resolve_file(job_dir, p, "PB settings", &["yaml", "yml", "txt"])?),
+        None => None
+    };
+    let sa_path = match &job.sa_settings {
+        Some(p) => Some(resolve_file(job_dir, p, "SA settings", &["yaml", "yml", "txt"])?),
+        None => None
+    };
+    let pbe_set = match pb_path {
+        Some(p) => Some(PBESet::load_params(p)?),
+        None => None
+    };
+    let pba_set = match sa_path {
+        Some(p) => Some(PBASet::load_params(p)?),
+        None => None
+    };
+
+    // check groups
+    let ndx = Index::from(&ndx_path)?;
+    let receptor_grp = find_group(&ndx, &job.receptor)?;
+    let ligand_grp = match &job.ligand {
+        Some(ligand) => Some(find_group(&ndx, ligand)?),
+        None => None
+    };
+    if ligand_grp == Some(receptor_grp) {
+        return Err(SmError::Input(format!("Receptor and ligand are the same group: {}", job.receptor)));
+    }
+
+    // check parameters
+    if let Some(radius_type) = &job.radius_type {
+        settings.radius_type = RADIUS_TYPES.iter().position(|&r| r.eq_ignore_ascii_case(radius_type))
+            .ok_or_else(|| SmError::Input(format!("Radius type {} not supported, should be one of {:?}", radius_type, RADIUS_TYPES)))?;
+    }
+    if let Some(screen_method) = job.screen_method {
+        if screen_method > 2 {
+            return Err(SmError::Input(format!("Electrostatic screening method {} not supported, should be 0, 1 or 2", screen_method)));
+        }
+        settings.elec_screen = screen_method;
+    }
+    if let Some(pbsa_kernel) = &job.pbsa_kernel {
+        settings.pbsa_kernel = match pbsa_kernel.as_str() {
+            "apbs" | "delphi" | "builtin" | "hct" | "obc1" | "obc2" | "gbn" => Some(pbsa_kernel.to_string()),
+            "" => None,
+            other => return Err(SmError::Input(format!("PBSA kernel {} not supported, should be \"apbs\", \"delphi\", \"builtin\", \
+                                         GB model {:?} or \"\"", other, GB_MODELS)))
+        };
+    }
+    check_kernel(settings)?;
+    if let Some(r_cutoff) = job.r_cutoff {
+        settings.r_cutoff = if r_cutoff == 0.0 { f64::INFINITY } else { r_cutoff };
+    }
+    if let Some(fix_pbc) = job.fix_pbc {
+        settings.fix_pbc = fix_pbc;
+    }
+    if let Some(cfac) = job.cfac {
+        settings.cfac = cfac;
+    }
+    if let Some(fadd) = job.fadd {
+        settings.fadd = fadd;
+    }
+    if let Some(df) = job.df {
+        settings.df = df;
+    }
+    if let Some(entropy_method) = &job.entropy_method {
+        settings.entropy_method = analyzation::ENTROPY_METHODS.iter().position(|m| m.eq_ignore_ascii_case(entropy_method))
+            .ok_or_else(|| SmError::Input(format!("Entropy method {} not supported, should be one of {:?}",
+                                                  entropy_method, analyzation::ENTROPY_METHODS)))?;
+    }
+    if let Some(hot_spot) = job.hot_spot {
+        settings.hot_spot = hot_spot;
+    }
+    if let Some(warm_spot) = job.warm_spot {
+        settings.warm_spot = warm_spot;
+    }
+    if let Some(pairwise) = &job.pairwise {
+        settings.pairwise = mmpbsa::PAIRWISE_MODES.iter().position(|m| m.eq_ignore_ascii_case(pairwise))
+            .ok_or_else(|| SmError::Input(format!("Pairwise decomposition {} not supported, should be one of {:?}",
+                                                  pairwise, mmpbsa::PAIRWISE_MODES)))?;
+    }
+    if let Some(apbs_retries) = job.apbs_retries {
+        settings.apbs_retries = apbs_retries;
+    }
+    if let Some(n_kernels) = job.n_kernels {
+        settings.nkernels = n_kernels;
+    }
+    if let Some(parallel_frames) = job.parallel_frames {
+        if parallel_frames == 0 {
+            return Err(SmError::Input("Number of parallel frames should be at least 1".to_string()));
+        }
+        settings.parallel_frames = parallel_frames;
+    }
+    if let Some(resume) = job.resume {
+        settings.resume = resume;
+    }
+    if let Some(skip_failed) = job.skip_failed {
+        settings.skip_failed = skip_failed;
+    }
+    let scan_to = job.scan_to.as_deref().unwrap_or("ALA");
+    let mut mutations: Vec<Mutation> = vec![];
+    for (rs, ligand) in [(&job.alanine_scanning, false), (&job.ligand_scanning, true)] {
+        if let Some(rs) = rs {
+            for nr in utils::range2list(rs) {
+                mutations.push(Mutation::new(nr, scan_to, ligand)
+                    .ok_or(SmError::Input(format!("Invalid residue to scan to: {}", scan_to)))?);
+            }
+        }
+    }
+    if let Some(ms) = &job.mutations {
+        for m in ms.split(',').filter(|m| !m.trim().is_empty()) {
+            mutations.push(Mutation::from(m)
+                .ok_or(SmError::Input(format!("Invalid mutation: {}", m.trim())))?);
+        }
+    }
+    if let Some(groups) = &job.group_deletion {
+        for g in groups {
+            mutations.push(Mutation::group(g)
+                .ok_or(SmError::Input(format!("Invalid atom group: {}", g)))?);
+        }
+    }
+
+    // load tpr
+    let wd = Path::new(&tpr_path).parent().unwrap_or(Path::new(".")).to_path_buf();
+    let tpr = TPR::from(&tpr_path, settings)?;
+
+    // check time range, in ps
+    let total_t = tpr.dt * tpr.nsteps as f64;
+    let unit_dt = tpr.dt * tpr.nstxout as f64;
+    let bt = job.begin.unwrap_or(0.0) * 1000.0;
+    let et = match job.end {
+        Some(et) => et * 1000.0,
+        None => total_t
+    };
+    let dt = job.interval.unwrap_or(1.0) * 1000.0;
+    if bt < 0.0 || bt > total_t {
+        return Err(SmError::Input(format!("Start time {} ns not in trajectory (0-{} ns)", bt / 1000.0, total_t / 1000.0)));
+    }
+    if et < bt || et > total_t {
+        return Err(SmError::Input(format!("End time {} ns not in trajectory ({}-{} ns)", et / 1000.0, bt / 1000.0, total_t / 1000.0)));
+    }
+    if dt <= 0.0 || (unit_dt > 0.0 && ((dt / unit_dt).round() - dt / unit_dt).abs() > 1e-6) {
+        return Err(SmError::Input(format!("Time interval {} ns should be divisible of {} ps", dt / 1000.0, unit_dt)));
+    }
+
+    // prepare system
+    let (time_list, coordinates, mut aps, ndx_rec, ndx_lig, residues) =
+        prepare_system_tpr(receptor_grp, ligand_grp, &trj_path, &tpr, &ndx, &tpr_path, &ndx_path,
+                           bt, et, dt, &wd, settings)?;
+    let pbe_set = pbe_set.unwrap_or_else(|| PBESet::new(tpr.temp));
+    let pba_set = pba_set.unwrap_or_else(|| PBASet::new(tpr.temp));
+    let radius_types = RADIUS_TYPES.to_vec();
+    println!("Applying {} radius...", radius_types[settings.radius_type]);
+    aps.apply_radius(settings.radius_type, &tpr.get_at_list(), &radius_types, &wd);
+
+    // temp directory for PBSA
+    let temp_dir = wd.join(&sys_name);
+    if settings.pbsa_kernel.is_some() {
+        println!("Temporary files will be placed at {}/", temp_dir.display());
+        if temp_dir.is_dir() && !settings.resume {
+            if !is_temp_dir(&temp_dir) {
+                return Err(SmError::Input(format!("{} exists and is not a temporary directory of s_mmpbsa, \
+                                                   choose another system_name or remove it", temp_dir.display())));
+            }
+            fs::remove_dir_all(&temp_dir).map_err(|e| SmError::io(&temp_dir.display().to_string(), e))?;
+        }
+        fs::create_dir_all(&temp_dir).map_err(|e| SmError::io(&temp_dir.display().to_string(), e))?;
+    } else {
+        println!("Note: Since no PBSA kernel selected, solvation energy will not be calculated.");
+    }
+
+    // run MM/PB-SA calculations
+    let (result_wt, result_as) = mmpbsa::fun_mmpbsa_calculations(&time_list, &coordinates, &temp_dir, &sys_name, &aps,
+                                                    &ndx_rec, &ndx_lig, &mutations, &residues, &wd,
+                                                    &pbe_set, &pba_set, settings)?;
+    let temperature = job.temperature.unwrap_or(pbe_set.temp);
+    analyzation::analyze_all(&result_wt, &result_as, temperature, &sys_name, &wd, job.res_cutoff.unwrap_or(4.0), settings);
+    Ok(())
+}
+
+fn resolve_file(job_dir: &Path, file: &str, kind: &str, ext_list: &[&str]) -> Result<String, SmError> {
+    let path = match Path::new(file).is_absolute() {
+        true => PathBuf::from(file),
+        false => job_dir.join(file)
+    };
+    if !path.is_file() {
+        return Err(SmError::Input(format!("The {} file {} not found", kind, path.display())));
+    }
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext_list.contains(&ext) => Ok(path.display().to_string()),
+        _ => Err(SmError::Input(format!("The {} file {} should be one of {:?}", kind, path.display(), ext_list)))
+    }
+}
+
+// empty, or with checkpoints or PBSA input files of frames written by an earlier run
+fn is_temp_dir(dir: &Path) -> bool {
+    let entries = |dir: &Path| -> Vec<PathBuf> {
+        fs::read_dir(dir).into_iter().flatten().filter_map(|e| e.ok().map(|e| e.path())).collect()
+    };
+    let is_ckpt = |p: &PathBuf| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("_checkpoint_"));
+    let is_input = |p: &PathBuf| p.extension().and_then(|e| e.to_str()).is_some_and(|e| e == "apbs" || e == "pqr");
+    let top = entries(dir);
+    top.is_empty() || top.iter().any(|p| is_ckpt(p) || is_input(p) || p.is_dir() && entries(p).iter().any(is_input))
+}
+
+fn find_group(ndx: &Index, name: &str) -> Result<usize, SmError> {
+    ndx.groups.iter().position(|g| g.name.eq(name))
+        .or_else(|| ndx.groups.iter().position(|g| g.name.eq_ignore_ascii_case(name)))
+        .ok_or_else(|| {
+            let names: Vec<&str> = ndx.groups.iter().map(|g| g.name.as_str()).collect();
+            SmError::Input(format!("Group {} not found in index file, available groups: {}", name, names.join(", ")))
+        })
+}
@@ -0,0 +1,196 @@
+// s_mmpbsa as a library, the interactive program in main.rs is a front-end of it
+// typical usage: TPR::from -> prepare_system_tpr (coordinates and AtomProperties) -> calc_mm and SolvationKernel per frame
+// -> SMResult::new -> result_file::save_results / load_results
+pub mod index_parser;
+pub mod mmpbsa;
+pub mod parse_tpr;
+pub mod parse_tpx;
+pub mod parse_xvg;
+pub mod parse_xtc;
+pub mod parse_pdb;
+pub mod parse_mol2;
+pub mod analyzation;
+pub mod system;
+pub mod atom_radius;
+pub mod apbs_param;
+pub mod prepare_apbs;
+pub mod settings;
+pub mod atom_property;
+pub mod coefficients;
+pub mod utils;
+pub mod pbc;
+pub mod neighbor;
+pub mod checkpoint;
+pub mod builtin_pb;
+pub mod gb;
+pub mod sasa;
+pub mod delphi;
+pub mod solvation_kernel;
+pub mod job;
+pub mod error;
+pub mod statistics;
+pub mod quasi_harmonic;
+pub mod mutation;
+pub mod result_file;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use settings::Settings;
+
+pub use analyzation::SMResult;
+pub use error::SmError;
+pub use apbs_param::{PBASet, PBESet};
+pub use atom_property::AtomProperties;
+pub use coefficients::Coefficients;
+pub use system::{prepare_system_tpr, SystemData};
+pub use index_parser::Index;
+pub use mmpbsa::{calc_mm, fun_mmpbsa_calculations};
+pub use parse_tpr::TPR;
+pub use result_file::{load_results, load_system, save_results};
+pub use parse_xtc::read_xtc;
+pub use solvation_kernel::{get_kernel, Frame, SolvationKernel};
+
+fn get_built_in_gmx() -> String {
+    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
+        .parent().expect("Cannot get current s_mmpbsa program directory.")
+        .join("programs").join("gmx")
+        .join(if cfg!(windows) {"win"} else {"linux"}).join("gmx")
+        .display().to_string()
+}
+
+fn get_built_in_apbs() -> String {
+    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
+        .parent()
+        .expect("Cannot get current s_mmpbsa program directory.")
+        .join("programs").join("apbs")
+        .join(if cfg!(windows) {"win"} else {"linux"}).join("apbs")
+        .display().to_string()
+}
+
+fn get_built_in_delphi() -> String {
+    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
+        .parent()
+        .expect("Cannot get current s_mmpbsa program directory.")
+        .join("programs").join("delphi")
+        .join(if cfg!(windows) {"win"} else {"linux"}).join("delphi")
+        .display().to_string()
+}
+
+fn get_built_in_antechamber() -> String {
+    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
+        .parent()
+        .expect("Cannot get current s_mmpbsa program directory.")
+        .join("programs").join("amber")
+        .join(if cfg!(windows) {"win"} else {"linux"})
+        .join("bin").join("antechamber")
+        .display().to_string()
+}
+
+fn get_built_in_sobtop() -> String {
+    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
+        .parent()
+        .expect("Cannot get current s_mmpbsa program directory.")
+        .join("programs").join("sobtop")
+        .join("sobtop")
+        .display().to_string()
+}
+
+pub fn set_program(p: &Option<String>, name: &str, settings: &Settings) -> Option<String> {
+    if let Some(p) = p {
+        let p = if p.eq("built-in") {
+            match name {
+                "gromacs" => get_built_in_gmx(),
+                "apbs" => get_built_in_apbs(),
+                "delphi" => get_built_in_delphi(),
+                "antechamber" => get_built_in_antechamber(),
+                "sobtop" => get_built_in_sobtop(),
+                _ => String::from("")
+            }
+        } else {
+            p.to_string()
+        };
+        if !p.is_empty() {
+            if settings.debug_mode {
+                println!("Checking {} validity...", name);
+            }
+            match check_program_validity(p.as_str()) {
+                Ok(p) => {
+                    println!("Using {}: {}", name, p);
+                    if name.eq("apbs") {
+                        if Path::new("io.mc").is_file() {
+                            fs::remove_file("io.mc").ok();
+                        }
+                    }
+                    Some(p)
+                }
+                Err(_) => {
+                    println!("Warning: no valid {} program in use.", name);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+    else {
+        None
+    }
+}
+
+fn check_program_validity(program: &str) -> Result<String, ()> {
+    let output = Command::new(program).arg("--version").output();
+    match output {
+        Ok(output) => {
+            match output.status.code() {
+                Some(0) => Ok(program.to_string()),
+                Some(13) => Ok(program.to_string()),    // Fuck APBS cannot return 0 without input
+                Some(1) => Ok(program.to_string()),    // Currently do not know delphi's test command
+                Some(24) => Ok(program.to_string()),    // Fuck sobtop do not have test command
+                Some(69) => Ok(program.to_string()),    // Fuck sobtop do not have test command
+                _ => {
+                    // println!("{}", output.status.code().unwrap());
+                    Err(())
+                }
+            }
+        }
+        Err(_) => Err(())
+    }
+}
+
+pub fn convert_cur_dir(p: &String, tpr_path: &str) -> String {
+    if p.starts_with('?') {
+        Path::new(tpr_path).parent()
+            .expect("Cannot get path of tpr file.")
+            .join(p[1..].to_string()).to_str()
+            .expect("Path of tpr file not valid unicode.").to_string()
+    } else {
+        p.to_string()
+    }
+}
+
+pub fn get_dump(tpr_path: &String, settings: &Settings) -> Result<String, SmError> {
+    // get dumpped tpr
+    let tpr_dump_path = fs::canonicalize(Path::new(tpr_path)).map_err(|e| SmError::io(tpr_path, e))?;
+    let tpr_dump_name = tpr_dump_path.file_stem().unwrap().to_str().unwrap();
+    let tpr_dir = tpr_dump_path.parent().expect("Failed to get tpr parent path");
+    let dump_path = tpr_dir.join(tpr_dump_name.to_string() + ".dump");
+    let gmx = settings.gmx_path.as_ref()
+        .ok_or_else(|| SmError::Input("GROMACS not found, which is needed to dump the tpr file.".to_string()))?;
+    let dump_to = dump_path.to_str().unwrap().to_string();
+    dump_tpr(tpr_path, &dump_to, gmx)?;
+    Ok(dump_to)
+}
+
+pub fn dump_tpr(tpr: &String, dump_to: &String, gmx: &str) -> Result<(), SmError> {
+    let tpr_dump = Command::new(gmx).arg("dump").arg("-s").arg(tpr).output().map_err(|e| SmError::io(gmx, e))?;
+    if !tpr_dump.status.success() {
+        return Err(SmError::program("gmx dump", &String::from_utf8_lossy(&tpr_dump.stderr)));
+    }
+    let mut outfile = fs::File::create(dump_to).map_err(|e| SmError::io(dump_to, e))?;
+    outfile.write_all(&tpr_dump.stdout).map_err(|e| SmError::io(dump_to, e))?;
+    println!("Dumped tpr file to {}", dump_to);
+    Ok(())
+}
@@ -1,31 +1,31 @@
-mod index_parser;
-mod mmpbsa;
-mod parse_tpr;
-mod parse_xvg;
-mod parse_pdb;
-mod parse_mol2;
-mod analyzation;
-mod fun_para_basic;
-mod fun_para_system;
-mod fun_para_mmpbsa;
-mod atom_radius;
-mod apbs_param;
-mod prepare_apbs;
-mod settings;
-mod atom_property;
-mod coefficients;
-mod utils;
-
 use std::{fs, io};
 use std::env;
 use std::fs::File;
 use std::io::{stdin, Write};
 use std::path::Path;
-use std::process::{exit, Command};
-use analyzation::SMResult;
+use std::process::exit;
 use regex::Regex;
-use settings::{Settings, get_base_settings, get_settings_in_use};
-use utils::get_input;
+use s_mmpbsa::{convert_cur_dir, job, result_file, set_program, utils, SmError};
+use s_mmpbsa::settings::{Settings, get_base_settings, get_settings_in_use};
+use s_mmpbsa::utils::get_input;
+
+// interactive menus, the calculations are done by the s_mmpbsa library
+mod fun_para_basic;
+mod fun_para_system;
+mod fun_para_mmpbsa;
+mod fun_para_analyzation;
+
+// errors end the program with a message, instead of a panic
+trait ExitOnError {
+    fn exit(&self) -> !;
+}
+
+impl ExitOnError for SmError {
+    fn exit(&self) -> ! {
+        eprintln!("Error: {}", self);
+        exit(1);
+    }
+}
 
 fn main() {
     let version = 0.6;
@@ -37,6 +37,10 @@ fn main() {
     }
 
     let args: Vec<String> = env::args().collect();
+    if args.len() == 3 && args[1].eq("run") {
+        job::run_job(&args[2], &mut settings).unwrap_or_else(|e| e.exit());
+        return;
+    }
     let mut input: String = String::new();
     let mut ligand = String::new();
     match args.len() {
@@ -76,7 +80,7 @@ fn main() {
         let in_file = confirm_file_validity(&input, vec!["tpr", "pdbqt"], &input);
         change_settings_last_opened(&mut settings, &in_file);
         if in_file.ends_with("tpr") {
-            let in_file = get_dump(&in_file, &settings);
+            let in_file = get_tpr_path(&in_file);
             fun_para_basic::set_para_basic_tpr(&in_file, &Path::new(&in_file).parent().unwrap(), &mut settings);
         } else { // pdbqt
             let wd = fs::canonicalize(Path::new(&in_file)).unwrap();
@@ -102,19 +106,8 @@ fn main() {
             println!("Please input system name (default: system):");
             let sys_name = get_input("system".to_string());
             println!("Loading MM/PB-SA results...");
-            let result_wt = sm_list.iter().find(|f| {
-                let f_name = Path::new(f).file_name().unwrap().to_str().unwrap();
-                f_name.starts_with(&format!("_MMPBSA_{}", sys_name)) && f_name.ends_with("_WT.sm")
-            }).ok_or_else(|| {
-                println!("The required _MMPBSA_{}_WT.sm file not found. Please check.", sys_name);
-                exit(0);
-            });
-            let result_wt = SMResult::from(result_wt.unwrap());
-            let result_as: Vec<SMResult> = sm_list.iter().filter(|&f| {
-                let f_name = Path::new(f).file_name().unwrap().to_str().unwrap();
-                f_name.starts_with(&format!("_MMPBSA_{}", sys_name)) && !f_name.ends_with("_WT.sm")
-            }).map(|f| SMResult::from(f)).collect();
-            analyzation::analyze_controller(&result_wt, &result_as, temperature, &sys_name, wd, &settings);
+            let (result_wt, result_as) = result_file::load_system(wd, &sys_name).unwrap_or_else(|e| e.exit());
+            fun_para_analyzation::analyze_controller(&result_wt, &result_as, temperature, &sys_name, wd, &settings);
         } else {
             println!("There is no MM/PB-SA results at {}. Please run MM/PB-SA calculations first.", &input);
         }
@@ -125,20 +118,6 @@ fn main() {
     }
 }
 
-fn welcome(version: &str, today: &str) {
-    println!("\
-        ========================================================================\n\
-        | s_mmpbsa: Supernova's tool of calculating binding free energy using  |\n\
-        | molecular mechanics Poisson-Boltzmann surface area (MM/PB-SA) method |\n\
-        ========================================================================\n\
-        Website: https://github.com/supernova4869/s_mmpbsa\n\
-        Developed by Supernova (zhangjiaxing7137@tju.edu.cn), Tianjin University.\n\
-        Version {}, first release: 2022-Oct-17, current release: {}\n", version, today);
-    println!("Usage 1: run `s_mmpbsa` and follow the prompts.\n\
-        Usage 2: run `s_mmpbsa Haibara_Ai.tpr` to load MD tpr file.\n\
-        Usage 3: run `s_mmpbsa Miyano_Shiho.pdbqt Kudo_Shinichi.pdbqt` (receptor first) to load docking results.\n");
-}
-
 pub fn confirm_file_validity(file_name: &String, ext_list: Vec<&str>, tpr_path: &str) -> String {
     let mut f_name = String::from(file_name);
     loop {
@@ -166,122 +145,19 @@ pub fn confirm_file_validity(file_name: &String, ext_list: Vec<&str>, tpr_path:
     }
 }
 
-fn get_built_in_gmx() -> String {
-    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
-        .parent().expect("Cannot get current s_mmpbsa program directory.")
-        .join("programs").join("gmx")
-        .join(if cfg!(windows) {"win"} else {"linux"}).join("gmx")
-        .display().to_string()
-}
-
-fn get_built_in_apbs() -> String {
-    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
-        .parent()
-        .expect("Cannot get current s_mmpbsa program directory.")
-        .join("programs").join("apbs")
-        .join(if cfg!(windows) {"win"} else {"linux"}).join("apbs")
-        .display().to_string()
-}
-
-fn get_built_in_delphi() -> String {
-    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
-        .parent()
-        .expect("Cannot get current s_mmpbsa program directory.")
-        .join("programs").join("delphi")
-        .join(if cfg!(windows) {"win"} else {"linux"}).join("delphi")
-        .display().to_string()
-}
-
-fn get_built_in_antechamber() -> String {
-    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
-        .parent()
-        .expect("Cannot get current s_mmpbsa program directory.")
-        .join("programs").join("amber")
-        .join(if cfg!(windows) {"win"} else {"linux"})
-        .join("bin").join("antechamber")
-        .display().to_string()
-}
-
-fn get_built_in_sobtop() -> String {
-    env::current_exe().expect("Cannot get current s_mmpbsa program path.")
-        .parent()
-        .expect("Cannot get current s_mmpbsa program directory.")
-        .join("programs").join("sobtop")
-        .join("sobtop")
-        .display().to_string()
-}
-
-fn set_program(p: &Option<String>, name: &str, settings: &Settings) -> Option<String> {
-    if let Some(p) = p {
-        let p = if p.eq("built-in") {
-            match name {
-                "gromacs" => get_built_in_gmx(),
-                "apbs" => get_built_in_apbs(),
-                "delphi" => get_built_in_delphi(),
-                "antechamber" => get_built_in_antechamber(),
-                "sobtop" => get_built_in_sobtop(),
-                _ => String::from("")
-            }
-        } else {
-            p.to_string()
-        };
-        if !p.is_empty() {
-            if settings.debug_mode {
-                println!("Checking {} validity...", name);
-            }
-            match check_program_validity(p.as_str()) {
-                Ok(p) => {
-                    println!("Using {}: {}", name, p);
-                    if name.eq("apbs") {
-                        if Path::new("io.mc").is_file() {
-                            fs::remove_file("io.mc").ok();
-                        }
-                    }
-                    Some(p)
-                }
-                Err(_) => {
-                    println!("Warning: no valid {} program in use.", name);
-                    None
-                }
-            }
-        } else {
-            None
-        }
-    }
-    else {
-        None
-    }
-}
-
-fn check_program_validity(program: &str) -> Result<String, ()> {
-    let output = Command::new(program).arg("--version").output();
-    match output {
-        Ok(output) => {
-            match output.status.code() {
-                Some(0) => Ok(program.to_string()),
-                Some(13) => Ok(program.to_string()),    // Fuck APBS cannot return 0 without input
-                Some(1) => Ok(program.to_string()),    // Currently do not know delphi's test command
-                Some(24) => Ok(program.to_string()),    // Fuck sobtop do not have test command
-                Some(69) => Ok(program.to_string()),    // Fuck sobtop do not have test command
-                _ => {
-                    // println!("{}", output.status.code().unwrap());
-                    Err(())
-                }
-            }
-        }
-        Err(_) => Err(())
-    }
-}
-
-pub fn convert_cur_dir(p: &String, tpr_path: &str) -> String {
-    if p.starts_with('?') {
-        Path::new(tpr_path).parent()
-            .expect("Cannot get path of tpr file.")
-            .join(p[1..].to_string()).to_str()
-            .expect("Path of tpr file not valid unicode.").to_string()
-    } else {
-        p.to_string()
-    }
+fn welcome(version: &str, today: &str) {
+    println!("\
+        ========================================================================\n\
+        | s_mmpbsa: Supernova's tool of calculating binding free energy using  |\n\
+        | molecular mechanics Poisson-Boltzmann surface area (MM/PB-SA) method |\n\
+        ========================================================================\n\
+        Website: https://github.com/supernova4869/s_mmpbsa\n\
+        Developed by Supernova (zhangjiaxing7137@tju.edu.cn), Tianjin University.\n\
+        Version {}, first release: 2022-Oct-17, current release: {}\n", version, today);
+    println!("Usage 1: run `s_mmpbsa` and follow the prompts.\n\
+        Usage 2: run `s_mmpbsa Haibara_Ai.tpr` to load MD tpr file.\n\
+        Usage 3: run `s_mmpbsa Miyano_Shiho.pdbqt Kudo_Shinichi.pdbqt` (receptor first) to load docking results.\n\
+        Usage 4: run `s_mmpbsa run Edogawa_Conan.toml` to run MD binding energy calculation non-interactively with a job file.\n");
 }
 
 fn change_settings_last_opened(settings: &mut Settings, tpr: &String) {
@@ -299,25 +175,11 @@ fn change_settings_last_opened(settings: &mut Settings, tpr: &String) {
     }
 }
 
-fn get_dump(tpr_path: &String, settings: &Settings) -> String {
-    // get dumpped tpr
-    let tpr_dump_path = fs::canonicalize(Path::new(&tpr_path)).expect("Cannot get absolute tpr path.");
-    let tpr_dump_name = tpr_dump_path.file_stem().unwrap().to_str().unwrap();
-    let tpr_dir = tpr_dump_path.parent().expect("Failed to get tpr parent path");
-    let dump_path = tpr_dir.join(tpr_dump_name.to_string() + ".dump");
-    println!("Currently working at path: {}", Path::new(&tpr_dir).display());
-    let gmx = settings.gmx_path.as_ref().unwrap();
-    let dump_to = dump_path.to_str().unwrap().to_string();
-    dump_tpr(&tpr_path, &dump_to, gmx);
-    dump_to
-}
-
-fn dump_tpr(tpr: &String, dump_to: &String, gmx: &str) {
-    let tpr_dump = Command::new(gmx).arg("dump").arg("-s").arg(tpr).output().expect("gmx dump failed.");
-    let tpr_dump = String::from_utf8(tpr_dump.stdout).expect("Getting dump output failed.");
-    let mut outfile = fs::File::create(dump_to).expect("Cannot create md.dump.");
-    outfile.write(tpr_dump.as_bytes()).expect("Cannot write md.dump.");
-    println!("Dumped tpr file to {}", dump_to);
+fn get_tpr_path(tpr_path: &String) -> String {
+    let tpr_path = fs::canonicalize(Path::new(&tpr_path)).expect("Cannot get absolute tpr path.");
+    let tpr_dir = tpr_path.parent().expect("Failed to get tpr parent path");
+    println!("Currently working at path: {}", tpr_dir.display());
+    tpr_path.to_str().unwrap().to_string()
 }
 
 fn env_check() -> Settings {
@@ -1,157 +1,109 @@
-use std::fs::{self, File};
-use std::io::Write;
+use std::fs;
 use std::path::{Path, PathBuf};
-use crate::fun_para_system::normalize_index;
+use std::sync::Mutex;
 use crate::settings::Settings;
 use crate::utils;
-use ndarray::parallel::prelude::*;
-use ndarray::{s, Array1, Array2, Array3, ArrayView2, Axis};
-use std::process::Command;
-use std::env;
+use ndarray::{Array1, Array2, Array3, ArrayView2, Zip};
+use rayon::ThreadPoolBuilder;
 use indicatif::{ProgressBar, ProgressStyle};
 use chrono::{Local, Duration};
 use crate::coefficients::{self, Coefficients};
-use crate::analyzation::SMResult;
+use crate::analyzation::{PairwiseMM, SMResult};
 use crate::parse_tpr::Residue;
 use crate::apbs_param::{PBASet, PBESet};
-use crate::atom_property::{AtomProperties, AtomProperty};
-use crate::prepare_apbs::{prepare_pqr, write_apbs_input};
+use crate::atom_property::AtomProperties;
+use crate::neighbor::for_each_pair;
+use crate::checkpoint::{input_key, system_hashes, Checkpoint};
+use crate::solvation_kernel::{get_kernel, Frame, SolvationKernel};
+use crate::error::SmError;
+use crate::mutation::{ForceField, Mutation};
+use crate::result_file::{save_results, Header};
+
+// pairwise decomposition of MM energy by settings.pairwise
+pub const PAIRWISE_MODES: [&str; 3] = ["none", "residue", "atom"];
 
 pub fn fun_mmpbsa_calculations(time_list: &Vec<f64>, coordinates: &Array3<f64>, temp_dir: &PathBuf,
                                sys_name: &String, aps: &AtomProperties,
                                ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, 
-                               ala_list: &Vec<i32>, residues: &Vec<Residue>, wd: &Path,
+                               mutations: &Vec<Mutation>, residues: &[Residue], wd: &Path,
                                pbe_set: &PBESet, pba_set: &PBASet, settings: &Settings)
-                               -> (SMResult, Vec<SMResult>) {
+                               -> Result<(SMResult, Vec<SMResult>), SmError> {
     println!("Running MM/PB-SA calculations of {}...", sys_name);
-    if ala_list.len() > 0 {
-        let as_res: Vec<String> = residues.iter().filter_map(
-            |r| if ala_list.contains(&r.nr) && r.name.ne("GLY") && r.name.ne("ALA") {
-                match utils::resname_3to1(&r.name) {
-                    Some(mutation) => Some(format!("{}{}A", mutation, r.nr)),
-                    None => Some(format!("{}{}A", r.name.to_string(), r.nr))
-                }
-            } else {
-                None
-            }).collect();
-        println!("Mutations for alanine scanning: {}", as_res.join(", "));
+    // mutations found in the system, not to the same residue, with the single ones of multiple mutations for additivity
+    let mut scans: Vec<(&Mutation, String)> = vec![];
+    for m in mutations {
+        let singles: Vec<&Mutation> = match m {
+            Mutation::Multiple(ms) => ms.iter().collect(),
+            _ => vec![]
+        };
+        for m in singles.into_iter().chain([m]) {
+            match m.label(aps, ndx_lig, residues) {
+                Ok(Some(label)) => if !scans.iter().any(|(_, l)| *l == label) {
+                    scans.push((m, label));
+                },
+                Ok(None) => println!("Warning: {} skipped, the residue is mutated to itself.", m),
+                Err(e) => println!("Warning: {} skipped. {}", m, e)
+            }
+        }
+    }
+    let mutations = scans;
+    if !mutations.is_empty() {
+        let labels: Vec<&str> = mutations.iter().map(|(_, label)| label.as_str()).collect();
+        println!("Mutations for scanning: {}", labels.join(", "));
     }
 
     // calculate MM and PBSA
+    let kernel = get_kernel(pbe_set, pba_set, settings)?;
     println!("Calculating binding energy for {}...", sys_name);
-    let result_wt = calculate_mmpbsa(&time_list, &coordinates, aps, &temp_dir, &ndx_rec, &ndx_lig, residues,
-        sys_name, "WT", pbe_set, pba_set, settings);
-    result_wt.to_bin(&wd.join(format!("_MMPBSA_{}_{}.sm", sys_name, "WT").as_str()));
-
-    let mut result_ala_scan: Vec<SMResult> = vec![];
-    if ala_list.len() > 0 {
-        // main chain atoms number
-        let as_res: Vec<&Residue> = residues.iter().filter(|&r| ala_list.contains(&r.nr) 
-            && r.name.ne("GLY") && r.name.ne("ALA")).collect();     // gly not contain CB, ala no need to mutate
-        let mut new_coordinates = coordinates.clone();
-        let exclude_list = ["N", "CA", "C", "O", "CB", "HN", "HCA", "HCB"];
-        for asr in as_res {
-            let mut new_aps = aps.clone();
-            let as_atoms: Vec<AtomProperty> = aps.atom_props.iter().filter_map(|a| if a.resid == asr.id {
-                Some(a.clone())
-            } else {
-                None
-            }).collect();
-            let mut sc_out: Vec<&AtomProperty> = as_atoms.iter().filter(|&a| !exclude_list.contains(&a.name.as_str())).collect();
-            let xgs: Vec<AtomProperty> = as_atoms.iter().filter_map(|a| {
-                if a.name.eq("CG") || a.name.eq("CG1") || a.name.eq("CG2") || a.name.eq("SG") || a.name.eq("OG") || a.name.eq("OG1") {
-                    Some(a.clone())
-                } else {
-                    None
-                }}).collect();
-            // 通过CB定位新的HB
-            let cb: Vec<AtomProperty> = as_atoms.iter().filter_map(|a| {
-                if a.name.eq("CB") {
-                    Some(a.clone())
-                } else {
-                    None
-                }}).collect();
-            for xg in xgs.iter() {
-                new_aps.atom_props[xg.id].change_atom(aps.at_map.get("HC"), "HC", &aps.radius_type);
-                // 获取新的HB坐标
-                for layer in 0..new_coordinates.shape()[0] {
-                    let cb_coords: Array1<f64> = new_coordinates.slice(s![layer, cb[0].id, ..]).to_owned();
-                    let hg_coords: Array1<f64> = new_coordinates.slice(s![layer, xg.id, ..]).to_owned();
-                    let new_hg_coord: Array1<f64> = transform_coordinate(&cb_coords, &hg_coords, 1.09);
-                    new_coordinates[[layer, xg.id, 0]] = new_hg_coord[0];
-                    new_coordinates[[layer, xg.id, 1]] = new_hg_coord[1];
-                    new_coordinates[[layer, xg.id, 2]] = new_hg_coord[2];
-                }
-            }
-            // 脯氨酸需要把CD改成H
-            // 通过N定位新的HN
-            if asr.name.eq("PRO") {
-                let n: Vec<AtomProperty> = as_atoms.iter().filter_map(|a| {
-                    if a.name.eq("N") {
-                        Some(a.clone())
-                    } else {
-                        None
-                    }}).collect();
-                sc_out.retain(|&a| a.name.ne("CD"));
-                let cd = as_atoms.iter().find(|&a| a.name == "CD").unwrap();
-                new_aps.atom_props[cd.id].change_atom(aps.at_map.get("H"), "HN", &aps.radius_type);
-                // 获取新的HN坐标
-                for layer in 0..new_coordinates.shape()[0] {
-                    let n_coords: Array1<f64> = new_coordinates.slice(s![layer, n[0].id, ..]).to_owned();
-                    let hn_coords: Array1<f64> = new_coordinates.slice(s![layer, cd.id, ..]).to_owned();
-                    let new_hn_coord = transform_coordinate(&n_coords, &hn_coords, 1.07);
-                    new_coordinates[[layer, cd.id, 0]] = new_hn_coord[0];
-                    new_coordinates[[layer, cd.id, 1]] = new_hn_coord[1];
-                    new_coordinates[[layer, cd.id, 2]] = new_hn_coord[2];
+    let result_wt = calculate_mmpbsa(time_list, coordinates, aps, temp_dir, ndx_rec, ndx_lig, residues,
+        sys_name, "WT", pbe_set, pba_set, kernel.as_ref(), settings)?;
+    let sm_file = wd.join(format!("_MMPBSA_{}.sm", sys_name));
+    let header = Header::new(pbe_set, pba_set, settings, system_hashes(time_list, coordinates, aps, ndx_rec, ndx_lig));
+    save_results(&sm_file, &header, &result_wt, &[], &[])?;
+
+    let mut result_mutations: Vec<SMResult> = vec![];
+    let mut atom_maps: Vec<Vec<usize>> = vec![];
+    if !mutations.is_empty() {
+        let ff = match mutations.iter().any(|(m, _)| !matches!(m, Mutation::Group(_))) {
+            true => Some(ForceField::load()?),
+            false => None
+        };
+        for (m, mutation) in mutations {
+            let mutant = match m.apply(aps, coordinates, ndx_rec, ndx_lig, residues, ff.as_ref()) {
+                Ok(mutant) => mutant,
+                Err(e) => {
+                    println!("Warning: {} skipped. {}", mutation, e);
+                    continue;
                 }
-            }
-            
-            // delete other atoms in the scanned residue
-            let del_list: Vec<usize> = sc_out.iter().map(|a| a.id).collect();
-            let xg_list: Vec<usize> = xgs.iter().map(|a| a.id).collect();
-            new_aps.atom_props.retain(|a| !del_list.contains(&a.id) || xg_list.contains(&a.id));
-            let retain_id: Vec<usize> = new_aps.atom_props.iter().map(|a| a.id).collect();
-            // 每次删除原子后重新排序剩余原子id
-            for (i, ap) in new_aps.atom_props.iter_mut().enumerate() {
-                ap.id = i;
             };
-            let new_coordinates: Array3<f64> = new_coordinates.select(Axis(1), &retain_id);
-            let mut new_ndx_rec = ndx_rec.clone();
-            new_ndx_rec.retain(|&x| !del_list.contains(&x) || xg_list.contains(&x));
-            let (new_ndx_rec, new_ndx_lig) = normalize_index(&new_ndx_rec, Some(ndx_lig));
-
-            // After alanine mutation
-            let mutation = match utils::resname_3to1(&asr.name) {
-                Some(mutation) => mutation,
-                None => asr.name.to_string()
-            };
-
-            let mut new_residues = residues.clone();
-            new_residues[asr.id].name = "ALA".to_string();
 
-            let mutation = format!("{}{}A", mutation, asr.nr);
             let sys_name = format!("{}-{}", sys_name, mutation);
             println!("Calculating binding energy for {}...", sys_name);
-            let result_as = calculate_mmpbsa(&time_list, &new_coordinates,
-                &new_aps, &temp_dir, &new_ndx_rec, &new_ndx_lig, &new_residues,
-                &sys_name, &mutation, pbe_set, pba_set, settings);
-            result_as.to_bin(&wd.join(format!("_MMPBSA_{}_{}.sm", sys_name, mutation).as_str()));
-            result_ala_scan.push(result_as);
+            let result_mutation = calculate_mmpbsa(time_list, &mutant.coordinates,
+                &mutant.aps, temp_dir, &mutant.ndx_rec, &mutant.ndx_lig, &mutant.residues,
+                &sys_name, &mutation, pbe_set, pba_set, kernel.as_ref(), settings)?;
+            result_mutations.push(result_mutation);
+            atom_maps.push(mutant.atom_map);
+            save_results(&sm_file, &header, &result_wt, &result_mutations, &atom_maps)?;
         }
     };
 
     // whether remove temp directory
     if !settings.debug_mode {
-        if settings.apbs_path.is_some() {
+        if temp_dir.is_dir() {
             fs::remove_dir_all(&temp_dir).expect("Remove dir failed");
         }
-        fs::remove_file(wd.join("_MMPBSA_coord.xvg")).unwrap();
+        // only docking trajectories are extracted to xvg
+        let coord_xvg = wd.join("_MMPBSA_coord.xvg");
+        if coord_xvg.is_file() {
+            fs::remove_file(coord_xvg).unwrap();
+        }
     }
 
     println!("");
     utils::show_famous_quotes();
 
-    (result_wt, result_ala_scan)
+    Ok((result_wt, result_mutations))
 }
 
 pub fn set_style(pb: &ProgressBar) {
@@ -160,11 +112,11 @@ pub fn set_style(pb: &ProgressBar) {
         .progress_chars("=>-"));
 }
 
-fn calculate_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>, 
-                    aps: &AtomProperties, temp_dir: &PathBuf,
+fn calculate_mmpbsa(time_list: &[f64], coordinates: &Array3<f64>, 
+                    aps: &AtomProperties, temp_dir: &Path,
                     ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>,
-                    residues: &Vec<Residue>, sys_name: &String, mutation: &str,
-                    pbe_set: &PBESet, pba_set: &PBASet, settings: &Settings) -> SMResult {
+                    residues: &[Residue], sys_name: &String, mutation: &str,
+                    pbe_set: &PBESet, pba_set: &PBASet, kernel: &dyn SolvationKernel, settings: &Settings) -> Result<SMResult, SmError> {
     let mut elec_atom: Array2<f64> = Array2::zeros((time_list.len(), aps.atom_props.len()));
     let mut vdw_atom: Array2<f64> = Array2::zeros((time_list.len(), aps.atom_props.len()));
     let mut pb_atom: Array2<f64> = Array2::zeros((time_list.len(), aps.atom_props.len()));
@@ -176,55 +128,158 @@ fn calculate_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
     // Time list of trajectory
     let times: Vec<f64> = time_list.iter().map(|t| t / 1000.0).collect();
 
+    // frames finished in previous runs
+    let key = input_key(time_list, coordinates, aps, ndx_rec, ndx_lig,
+                        &format!("{}\n{}\n{} {}", pbe_set, pba_set, pba_set.surften, pba_set.surfoff), settings);
+    let checkpoint = Checkpoint::new(temp_dir, sys_name, key)?;
+    let finished = match settings.resume {
+        true => checkpoint.load(&mut elec_atom, &mut vdw_atom, &mut pb_atom, &mut sa_atom),
+        false => vec![false; time_list.len()]
+    };
+    let finished_num = finished.iter().filter(|&&f| f).count();
+    if finished_num > 0 {
+        println!("Resumed {} of {} frames from checkpoint.", finished_num, time_list.len());
+    }
+
     // start calculation
-    env::set_var("OMP_NUM_THREADS", settings.nkernels.to_string());
     let t_start = Local::now();
     
     let pgb = ProgressBar::new(time_list.len() as u64);
     set_style(&pgb);
-    pgb.inc(0);
-    let mut frame_id = 0;
-    pgb.set_message(format!("at {} ns...", times[frame_id]));
-    for cur_frm in 0..time_list.len() {
-        // MM
-        let coord = coordinates.slice(s![cur_frm, .., ..]);
-        if ndx_lig[0] != ndx_rec[0] {
-            let (de_elec, de_vdw) = 
-                calc_mm(&ndx_rec, &ndx_lig, aps, &coord, &coeff, &settings);
-            elec_atom.row_mut(frame_id).assign(&de_elec);
-            vdw_atom.row_mut(frame_id).assign(&de_vdw);
-        }
+    pgb.inc(finished_num as u64);
+    pgb.set_message(format!("at {} ns...", times[0]));
 
-        // PBSA
-        if settings.pbsa_kernel.is_some() {
-            let (de_pb, de_sa) = 
-                calc_pbsa(&coord, time_list, ndx_rec, ndx_lig, cur_frm, sys_name, temp_dir, aps, pbe_set, pba_set, settings);
-            pb_atom.row_mut(frame_id).assign(&de_pb);
-            sa_atom.row_mut(frame_id).assign(&de_sa);
-        }
+    // pairwise matrices are not checkpointed, so MM of resumed frames is calculated again for them
+    let pair_index = match settings.pairwise {
+        0 => None,
+        _ if ndx_lig[0] == ndx_rec[0] => None,
+        mode => Some(PairIndex::new(aps, ndx_rec, ndx_lig, mode == 2))
+    };
+    let pair_shape = pair_index.as_ref().map_or((0, 0), |p| (p.rows.len(), p.cols.len()));
+    let mut pair_elec: Array3<f64> = Array3::zeros((time_list.len(), pair_shape.0, pair_shape.1));
+    let mut pair_vdw: Array3<f64> = Array3::zeros((time_list.len(), pair_shape.0, pair_shape.1));
+
+    // errors of frames calculated concurrently, the first one is returned
+    let error: Mutex<Option<SmError>> = Mutex::new(None);
 
-        pgb.inc(1);
-        pgb.set_message(format!("at {} ns, ΔH={:.2} kJ/mol, eta. {} s", 
-                                        times[frame_id],
-                                        vdw_atom.row(frame_id).sum() + elec_atom.row(frame_id).sum() + 
-                                        pb_atom.row(frame_id).sum() + sa_atom.row(frame_id).sum(),
-                                        pgb.eta().as_secs()));
+    // MM of all frames concurrently
+    let pool = ThreadPoolBuilder::new().num_threads(settings.nkernels.max(1) as usize).build().unwrap();
+    if ndx_lig[0] != ndx_rec[0] {
+        pool.install(|| {
+            Zip::indexed(elec_atom.rows_mut()).and(vdw_atom.rows_mut()).and(coordinates.outer_iter())
+                .and(pair_elec.outer_iter_mut()).and(pair_vdw.outer_iter_mut())
+                .par_for_each(|cur_frm, mut elec, mut vdw, coord, mut p_elec, mut p_vdw| {
+                    if finished[cur_frm] && pair_index.is_none() {
+                        return;
+                    }
+                    let (de_elec, de_vdw) = match &pair_index {
+                        Some(pair_index) => {
+                            let (de_elec, de_vdw, pe, pv) = calc_mm_pairwise(ndx_rec, ndx_lig, aps, &coord, &coeff, settings, pair_index);
+                            p_elec.assign(&pe);
+                            p_vdw.assign(&pv);
+                            (de_elec, de_vdw)
+                        },
+                        None => calc_mm(ndx_rec, ndx_lig, aps, &coord, &coeff, settings)
+                    };
+                    if finished[cur_frm] {
+                        return;
+                    }
+                    elec.assign(&de_elec);
+                    vdw.assign(&de_vdw);
+                    if settings.pbsa_kernel.is_none() {
+                        if let Err(e) = checkpoint.save(cur_frm, elec.view(), vdw.view(), pb_atom.row(cur_frm), sa_atom.row(cur_frm)) {
+                            error.lock().unwrap().get_or_insert(e);
+                            return;
+                        }
+                        pgb.inc(1);
+                        pgb.set_message(format!("at {} ns, ΔH={:.2} kJ/mol, eta. {} s", 
+                                                times[cur_frm], elec.sum() + vdw.sum(), pgb.eta().as_secs()));
+                    }
+                });
+        });
+    } else if settings.pbsa_kernel.is_none() {
+        // receptor only without PBSA, all terms are zero
+        for cur_frm in (0..time_list.len()).filter(|&i| !finished[i]) {
+            checkpoint.save(cur_frm, elec_atom.row(cur_frm), vdw_atom.row(cur_frm), pb_atom.row(cur_frm), sa_atom.row(cur_frm))?;
+            pgb.inc(1);
+        }
+    }
 
-        frame_id += 1;
+    // PBSA of parallel_frames frames concurrently, each frame in its own directory
+    // failed frames are marked as NaN if skip_failed, otherwise frames after the first failed one are skipped,
+    // failed frames are not checkpointed so that they will be calculated again when resuming
+    if settings.pbsa_kernel.is_some() {
+        let pool = ThreadPoolBuilder::new().num_threads(settings.parallel_frames.max(1)).build().unwrap();
+        pool.install(|| {
+            Zip::indexed(pb_atom.rows_mut()).and(sa_atom.rows_mut()).and(coordinates.outer_iter())
+                .par_for_each(|cur_frm, mut pb, mut sa, coord| {
+                    if finished[cur_frm] || error.lock().unwrap().is_some() {
+                        return;
+                    }
+                    let frame_dir = temp_dir.join(format!("{}_{}", sys_name, cur_frm));
+                    if let Err(e) = fs::create_dir_all(&frame_dir) {
+                        error.lock().unwrap().get_or_insert(SmError::io(&frame_dir.display().to_string(), e));
+                        return;
+                    }
+                    let warn = |w: &str| pgb.suspend(|| println!("{}", w));
+                    let frame = Frame {
+                        coord: coord.view(),
+                        ndx_rec,
+                        ndx_lig,
+                        aps,
+                        temp_dir: &frame_dir,
+                        f_name: format!("{}_{}ns", sys_name, time_list[cur_frm]),
+                        warn: &warn,
+                    };
+                    if let Err(e) = kernel.prepare(&frame) {
+                        error.lock().unwrap().get_or_insert(e);
+                        return;
+                    }
+                    let (de_pb, de_sa) = match kernel.run(&frame) {
+                        Ok(de) => de,
+                        Err(e) if settings.skip_failed => {
+                            pgb.suspend(|| println!("Warning: {}\nFrame at {} ns marked as failed.", e, times[cur_frm]));
+                            pb.fill(f64::NAN);
+                            sa.fill(f64::NAN);
+                            pgb.inc(1);
+                            return;
+                        }
+                        Err(e) => {
+                            error.lock().unwrap().get_or_insert(e);
+                            return;
+                        }
+                    };
+                    pb.assign(&de_pb);
+                    sa.assign(&de_sa);
+                    if let Err(e) = checkpoint.save(cur_frm, elec_atom.row(cur_frm), vdw_atom.row(cur_frm), pb.view(), sa.view()) {
+                        error.lock().unwrap().get_or_insert(e);
+                        return;
+                    }
+                    pgb.inc(1);
+                    pgb.set_message(format!("at {} ns, ΔH={:.2} kJ/mol, eta. {} s", 
+                                            times[cur_frm],
+                                            elec_atom.row(cur_frm).sum() + vdw_atom.row(cur_frm).sum() + pb.sum() + sa.sum(),
+                                            pgb.eta().as_secs()));
+                });
+        });
     }
     pgb.finish();
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
 
     // end calculation
     let t_end = Local::now();
     let t_spend = Duration::from(t_end - t_start).num_milliseconds();
     println!("MM/PB-SA calculation of {} finished. Total time cost: {} s", sys_name, t_spend as f64 / 1000.0);
-    env::remove_var("OMP_NUM_THREADS");
 
-    let atom_res = &aps.atom_props.iter().map(|a| a.resid).collect();
-    let atom_names = &aps.atom_props.iter().map(|a| a.name.to_string()).collect();
-    SMResult::new(
-        atom_names,
-        atom_res,
+    let atom_res: Vec<usize> = aps.atom_props.iter().map(|a| a.resid).collect();
+    let atom_names: Vec<String> = aps.atom_props.iter().map(|a| a.name.to_string()).collect();
+    let atom_masses: Vec<f64> = aps.atom_props.iter().map(|a| a.mass).collect();
+    let mut result = SMResult::new(
+        &atom_names,
+        &atom_res,
+        &atom_masses,
         residues,
         ndx_lig,
         &times,
@@ -234,41 +289,67 @@ fn calculate_mmpbsa(time_list: &Vec<f64>, coordinates: &Array3<f64>,
         &vdw_atom,
         &pb_atom,
         &sa_atom,
-    )
+    );
+    result.pairwise = pair_index.map(|p| PairwiseMM {
+        by_atom: p.by_atom,
+        rows: p.rows,
+        cols: p.cols,
+        elec: pair_elec,
+        vdw: pair_vdw,
+    });
+    Ok(result)
 }
 
-fn calc_mm(ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, aps: &AtomProperties, coord: &ArrayView2<f64>, 
-            coeff: &Coefficients, settings: &Settings) -> (Array1<f64>, Array1<f64>) {
-    let mut de_elec: Array1<f64> = Array1::zeros(aps.atom_props.len());
-    let mut de_vdw: Array1<f64> = Array1::zeros(aps.atom_props.len());
+// rows (receptor residues) and columns (ligand residues or atoms) of the pairwise decomposition
+pub struct PairIndex {
+    pub by_atom: bool,
+    pub rows: Vec<usize>,
+    pub cols: Vec<usize>,
+    ids: Vec<usize>,        // row of each receptor atom and column of each ligand atom
+}
 
-    for &i in ndx_rec {
-        let qi = aps.atom_props[i].charge;
-        let ci = aps.atom_props[i].type_id;
-        let xi = coord[[i, 0]];
-        let yi = coord[[i, 1]];
-        let zi = coord[[i, 2]];
+impl PairIndex {
+    pub fn new(aps: &AtomProperties, ndx_rec: &[usize], ndx_lig: &[usize], by_atom: bool) -> PairIndex {
+        let unique_res = |ndx: &[usize]| {
+            let mut res: Vec<usize> = ndx.iter().map(|&i| aps.atom_props[i].resid).collect();
+            res.sort();
+            res.dedup();
+            res
+        };
+        let rows = unique_res(ndx_rec);
+        let cols = match by_atom {
+            true => {
+                let mut cols = ndx_lig.to_vec();
+                cols.sort();
+                cols
+            },
+            false => unique_res(ndx_lig)
+        };
+        let mut ids = vec![0; aps.atom_props.len()];
+        for &i in ndx_rec {
+            ids[i] = rows.binary_search(&aps.atom_props[i].resid).unwrap();
+        }
         for &j in ndx_lig {
-            if ndx_lig[0] == ndx_rec[0] && j <= i {
-                continue;
-            }
-            let qj = aps.atom_props[j].charge;
-            let cj = aps.atom_props[j].type_id;
-            let xj = coord[[j, 0]];
-            let yj = coord[[j, 1]];
-            let zj = coord[[j, 2]];
-            let r = ((xi - xj).powi(2) + (yi - yj).powi(2) + (zi - zj).powi(2)).sqrt();
-            if r <= settings.r_cutoff {
-                let r = r / 10.0;   // The fucking unit system
-                let e_elec = qi * qj / r * coefficients::screening_method(r, coeff, settings.elec_screen);
-                let e_vdw = (aps.c12[[ci, cj]] / r.powi(6) - aps.c6[[ci, cj]]) / r.powi(6);
-                de_elec[i] += e_elec;
-                de_elec[j] += e_elec;
-                de_vdw[i] += e_vdw;
-                de_vdw[j] += e_vdw;
-            }
+            ids[j] = match by_atom {
+                true => cols.binary_search(&j).unwrap(),
+                false => cols.binary_search(&aps.atom_props[j].resid).unwrap()
+            };
         }
+        PairIndex { by_atom, rows, cols, ids }
     }
+}
+
+pub fn calc_mm(ndx_rec: &[usize], ndx_lig: &[usize], aps: &AtomProperties, coord: &ArrayView2<f64>, 
+            coeff: &Coefficients, settings: &Settings) -> (Array1<f64>, Array1<f64>) {
+    let mut de_elec: Array1<f64> = Array1::zeros(aps.atom_props.len());
+    let mut de_vdw: Array1<f64> = Array1::zeros(aps.atom_props.len());
+
+    for_each_pair_mm(ndx_rec, ndx_lig, aps, coord, coeff, settings, |i, j, e_elec, e_vdw| {
+        de_elec[i] += e_elec;
+        de_elec[j] += e_elec;
+        de_vdw[i] += e_vdw;
+        de_vdw[j] += e_vdw;
+    });
 
     de_elec = de_elec * coeff.f / coeff.pdie / 2.0;
     de_vdw = de_vdw / 2.0;
@@ -276,150 +357,100 @@ fn calc_mm(ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, aps: &AtomProperties, coo
     return (de_elec, de_vdw)
 }
 
-fn calc_pbsa(coord: &ArrayView2<f64>, time_list: &Vec<f64>, 
-            ndx_rec_norm: &Vec<usize>, ndx_lig_norm: &Vec<usize>, cur_frm: usize, sys_name: &String, temp_dir: &PathBuf, 
-            aps: &AtomProperties, pbe_set: &PBESet, pba_set: &PBASet, settings: &Settings) -> (Array1<f64>, Array1<f64>) {
-    prepare_pqr(cur_frm, &time_list, &temp_dir, sys_name, coord, &ndx_rec_norm, ndx_lig_norm, aps);
-
-    // From AMBER-PB4, the surface extension constant γ=0.0072 kcal/(mol·Å2)=0.030125 kJ/(mol·Å^2)
-    // but the default gamma parameter for apbs calculation is set to 1, in order to directly obtain the surface area
-    // then the SA energy term is calculated by s_mmpbsa
-    let gamma = 0.030125;
-    let bias = 0.0;
-    let f_name = format!("{}_{}ns", sys_name, time_list[cur_frm]);
-    if let Some(pbsa_kernel) = &settings.pbsa_kernel {
-        if pbsa_kernel.eq("apbs") {
-            if settings.apbs_path.as_ref().is_none() {
-                return (Array1::zeros(aps.atom_props.len()), Array1::zeros(aps.atom_props.len()))
-            }
-            let apbs = settings.apbs_path.as_ref().unwrap();
-            write_apbs_input(ndx_rec_norm, ndx_lig_norm, coord, &Array1::from_iter(aps.atom_props.iter().map(|a| a.radius)),
-                    pbe_set, pba_set, temp_dir, &f_name, settings);
-            // invoke apbs program to do apbs calculations
-            let apbs_result = Command::new(apbs).arg(format!("{}.apbs", f_name)).current_dir(temp_dir).output().expect("running apbs failed.");
-            let apbs_err = String::from_utf8(apbs_result.stderr).expect("Failed to parse apbs output.");
-            let apbs_result = String::from_utf8(apbs_result.stdout).expect("Failed to parse apbs output.");
-            if settings.debug_mode {
-                let mut outfile = File::create(temp_dir.join(format!("{}.out", f_name))).expect("Failed to create output file.");
-                outfile.write_all(apbs_result.as_bytes()).expect("Failed to write apbs output.");
-                let mut errfile = File::create(temp_dir.join(format!("{}.err", f_name))).expect("Failed to create err file.");
-                errfile.write_all(apbs_err.as_bytes()).expect("Failed to write apbs output.");
-            }
-            // let apbs_result = fs::read_to_string(temp_dir.join(format!("{}.out", f_name))).expect("Failed to parse apbs output.");
-    
-            // preserve CALCULATION, Atom and SASA lines
-            let apbs_result: Vec<&str> = apbs_result.split("\n").filter_map(|p|
-                if p.trim().starts_with("CALCULATION") || p.trim().starts_with("Atom") || p.trim().starts_with("SASA") {
-                    Some(p.trim())
-                } else {
-                    None
-                }
-            ).collect();
-    
-            // extract apbs results
-            let indexes: Vec<usize> = apbs_result.iter().enumerate().filter_map(|(i, &p)| match p.starts_with("CAL") {
-                true => Some(i),
-                false => None
-            }).collect();
-    
-            let mut com_pb_sol: Vec<f64> = vec![];
-            let mut com_pb_vac: Vec<f64> = vec![];
-            let mut rec_pb_sol: Vec<f64> = vec![];
-            let mut rec_pb_vac: Vec<f64> = vec![];
-            let mut lig_pb_sol: Vec<f64> = vec![];
-            let mut lig_pb_vac: Vec<f64> = vec![];
-            let mut com_sa: Vec<f64> = vec![];
-            let mut rec_sa: Vec<f64> = vec![];
-            let mut lig_sa: Vec<f64> = vec![];
-    
-            let mut skip_pb = true;     // the first time PB calculation should be wasted
-            for (i, &idx) in indexes.iter().enumerate() {
-                let st = idx + 1;
-                let ed = match indexes.get(i + 1) {
-                    Some(&idx) => idx,
-                    None => apbs_result.len()
-                };
-                if apbs_result[idx].contains(&"_com_SOL") {
-                    if !skip_pb {
-                        apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut com_pb_sol);
-                    }
-                    skip_pb = !skip_pb;
-                } else if apbs_result[idx].contains(&"_com_VAC") {
-                    if !skip_pb {
-                        apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut com_pb_vac);
-                    }
-                    skip_pb = !skip_pb;
-                } else if apbs_result[idx].contains(&"_rec_SOL") {
-                    if !skip_pb {
-                        apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut rec_pb_sol);
-                    }
-                    skip_pb = !skip_pb;
-                } else if apbs_result[idx].contains(&"_rec_VAC") {
-                    if !skip_pb {
-                        apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut rec_pb_vac);
-                    }
-                    skip_pb = !skip_pb;
-                } else if apbs_result[idx].contains(&"_lig_SOL") {
-                    if !skip_pb {
-                        apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut lig_pb_sol);
-                    }
-                    skip_pb = !skip_pb;
-                } else if apbs_result[idx].contains(&"_lig_VAC") {
-                    if !skip_pb {
-                        apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut lig_pb_vac);
-                    }
-                    skip_pb = !skip_pb;
-                } else if apbs_result[idx].contains(&"_com_SAS") {
-                    apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut com_sa);
-                } else if apbs_result[idx].contains(&"_rec_SAS") {
-                    apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut rec_sa);
-                } else if apbs_result[idx].contains(&"_lig_SAS") {
-                    apbs_result[st..ed].par_iter().map(|&p| parse_apbs_line(p)).collect_into_vec(&mut lig_sa);
-                }
-            }
-    
-            let com_pb: Array1<f64> = Array1::from_vec(com_pb_sol) - Array1::from_vec(com_pb_vac);
-            let com_sa: Array1<f64> = Array1::from_vec(com_sa.par_iter().map(|i| gamma * *i + bias / com_sa.len() as f64).collect());
-            let mut rec_pb: Array1<f64> = Array1::from_vec(rec_pb_sol) - Array1::from_vec(rec_pb_vac);
-            let mut rec_sa: Array1<f64> = Array1::from_vec(rec_sa.par_iter().map(|i| gamma * *i + bias / rec_sa.len() as f64).collect());
-            let mut lig_pb: Array1<f64> = Array1::from_vec(lig_pb_sol) - Array1::from_vec(lig_pb_vac);
-            let mut lig_sa: Array1<f64> = Array1::from_vec(lig_sa.par_iter().map(|i| gamma * *i + bias / lig_sa.len() as f64).collect());
-    
-            if ndx_rec_norm[0] < ndx_lig_norm[0] {
-                rec_pb.append(Axis(0), lig_pb.view()).unwrap();
-                rec_sa.append(Axis(0), lig_sa.view()).unwrap();
-                return (com_pb - rec_pb, com_sa - rec_sa)
-            } else if ndx_rec_norm[0] > ndx_lig_norm[0] {
-                lig_pb.append(Axis(0), rec_pb.view()).unwrap();
-                lig_sa.append(Axis(0), rec_sa.view()).unwrap();
-                return (com_pb - lig_pb, com_sa - lig_sa)
-            } else {
-                return (rec_pb, rec_sa)
-            }
+// calc_mm with the receptor-ligand pair energies also summed into the matrices of pair_index
+pub fn calc_mm_pairwise(ndx_rec: &[usize], ndx_lig: &[usize], aps: &AtomProperties, coord: &ArrayView2<f64>,
+                        coeff: &Coefficients, settings: &Settings, pair_index: &PairIndex)
+                        -> (Array1<f64>, Array1<f64>, Array2<f64>, Array2<f64>) {
+    let mut de_elec: Array1<f64> = Array1::zeros(aps.atom_props.len());
+    let mut de_vdw: Array1<f64> = Array1::zeros(aps.atom_props.len());
+    let mut pair_elec: Array2<f64> = Array2::zeros((pair_index.rows.len(), pair_index.cols.len()));
+    let mut pair_vdw: Array2<f64> = Array2::zeros((pair_index.rows.len(), pair_index.cols.len()));
+
+    for_each_pair_mm(ndx_rec, ndx_lig, aps, coord, coeff, settings, |i, j, e_elec, e_vdw| {
+        de_elec[i] += e_elec;
+        de_elec[j] += e_elec;
+        de_vdw[i] += e_vdw;
+        de_vdw[j] += e_vdw;
+        pair_elec[[pair_index.ids[i], pair_index.ids[j]]] += e_elec;
+        pair_vdw[[pair_index.ids[i], pair_index.ids[j]]] += e_vdw;
+    });
+
+    de_elec = de_elec * coeff.f / coeff.pdie / 2.0;
+    de_vdw /= 2.0;
+    pair_elec *= coeff.f / coeff.pdie;
+
+    (de_elec, de_vdw, pair_elec, pair_vdw)
+}
+
+// f(i, j, elec, vdw) for each receptor-ligand atom pair, elec without the f / pdie factor
+fn for_each_pair_mm<F: FnMut(usize, usize, f64, f64)>(ndx_rec: &[usize], ndx_lig: &[usize], aps: &AtomProperties,
+                                                     coord: &ArrayView2<f64>, coeff: &Coefficients, settings: &Settings, mut f: F) {
+    for_each_pair(coord, ndx_rec, ndx_lig, settings.r_cutoff, |i, j, r| {
+        let qi = aps.atom_props[i].charge;
+        let ci = aps.atom_props[i].type_id;
+        let qj = aps.atom_props[j].charge;
+        let cj = aps.atom_props[j].type_id;
+        let r = r / 10.0;   // The fucking unit system
+        let e_elec = qi * qj / r * coefficients::screening_method(r, coeff, settings.elec_screen);
+        let e_vdw = (aps.c12[[ci, cj]] / r.powi(6) - aps.c6[[ci, cj]]) / r.powi(6);
+        f(i, j, e_elec, e_vdw);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use ndarray::Array1;
+    use super::*;
+    use crate::atom_property::AtomProperty;
+
+    // PB of each atom is its x coordinate and SA is 0.5 kJ/mol, so that the terms of each frame are known
+    struct MockKernel;
+
+    impl SolvationKernel for MockKernel {
+        fn prepare(&self, _frame: &Frame) -> Result<(), SmError> {
+            Ok(())
         }
-        else {
-            println!("Currently Delphi kernel not available.");
-            return (Array1::zeros(aps.atom_props.len()), Array1::zeros(aps.atom_props.len()))
+
+        fn run(&self, frame: &Frame) -> Result<(Array1<f64>, Array1<f64>), SmError> {
+            Ok((frame.coord.column(0).to_owned(), Array1::from_elem(frame.aps.atom_props.len(), 0.5)))
         }
-    } else {
-        return (Array1::zeros(aps.atom_props.len()), Array1::zeros(aps.atom_props.len()))
     }
-}
 
-fn parse_apbs_line(line: &str) -> f64 {
-    line.split(":")
-        .skip(1)
-        .next().expect("Cannot get information from apbs")
-        .trim_start()
-        .split(" ")
-        .next().expect("Cannot get information from apbs")
-        .parse().expect("Cannot parse value from apbs")
-}
+    #[test]
+    fn sums_kernel_terms() {
+        // uncharged atoms without LJ, so that MM is zero
+        let aps = AtomProperties {
+            c6: Array2::zeros((1, 1)),
+            c12: Array2::zeros((1, 1)),
+            at_map: HashMap::new(),
+            radius_type: "test".to_string(),
+            atom_props: (0..4).map(|id| AtomProperty {
+                charge: 0.0, radius: 1.5, type_id: 0, id, name: "C".to_string(), resname: "MOL".to_string(), resid: id / 2, mass: 12.0
+            }).collect(),
+        };
+        let residues = vec![
+            Residue { id: 0, name: "REC".to_string(), nr: 1 },
+            Residue { id: 1, name: "LIG".to_string(), nr: 2 },
+        ];
+        let coordinates = Array3::from_shape_fn((3, 4, 3), |(t, i, d)| (t * 10 + i) as f64 + d as f64 * 0.1);
+        let time_list = vec![0.0, 1000.0, 2000.0];
+        let mut settings = Settings::new();
+        settings.pbsa_kernel = Some("mock".to_string());
+        let pbe_set = PBESet::new(298.15);
+        let pba_set = PBASet::new(298.15);
+        let temp_dir = std::env::temp_dir().join(format!("s_mmpbsa_mock_kernel_{}", std::process::id()));
 
-fn transform_coordinate(base: &Array1<f64>, origin: &Array1<f64>, target_length: f64) -> Array1<f64> {
-    let v_ch: Array1<f64> = origin - base;
-    let cur_len = v_ch.iter().map(|d| d.powi(2)).sum::<f64>().sqrt();
-    let lambda = target_length / cur_len;
-    let new_v_ch: Array1<f64> = Array1::from_iter(v_ch.iter().map(|r| r * lambda));
-    return base + new_v_ch
-}
\ No newline at end of file
+        let result = calculate_mmpbsa(&time_list, &coordinates, &aps, &temp_dir, &vec![0, 1], &vec![2, 3], &residues,
+                                      &"mock".to_string(), "WT", &pbe_set, &pba_set, &MockKernel, &settings).unwrap();
+        fs::remove_dir_all(&temp_dir).ok();
+        for t in 0..3 {
+            let pb: f64 = (0..4).map(|i| (t * 10 + i) as f64).sum();
+            assert!((result.pb[t] - pb).abs() < 1e-9, "frame {}: ΔPB {} instead of {}", t, result.pb[t], pb);
+            assert!((result.sa[t] - 2.0).abs() < 1e-9, "frame {}: ΔSA {} instead of 2", t, result.sa[t]);
+            assert!(result.mm[t].abs() < 1e-9, "frame {}: ΔMM {} instead of 0", t, result.mm[t]);
+            assert!((result.dh[t] - pb - 2.0).abs() < 1e-9, "frame {}: ΔH {} instead of {}", t, result.dh[t], pb + 2.0);
+        }
+        assert_eq!(result.pb_atom.row(1).to_vec(), vec![10.0, 11.0, 12.0, 13.0]);
+    }
+}
@@ -0,0 +1,449 @@
+// Residue mutations by truncating side chains to the residue templates of the bundled force field
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Formatter};
+use std::fs;
+use ndarray::{s, Array1, Array2, Array3, Axis};
+use crate::atom_property::AtomProperties;
+use crate::error::SmError;
+use crate::system::normalize_index;
+use crate::parse_tpr::Residue;
+use crate::utils;
+
+const FORCE_FIELD: &str = "amber14sb";
+// distance (A) within which heavy atoms, or a hydrogen and its heavy atom, are taken as bonded
+const BOND_MAX: f64 = 2.0;
+const BOND_H_MAX: f64 = 1.3;
+
+// residue nr mutated to target residue, a group of ligand atoms deleted, or a combination of them
+#[derive(Clone)]
+pub enum Mutation {
+    Residue { nr: i32, original: Option<String>, target: String, ligand: bool },      // original residue name if given
+    Group(Vec<usize>),      // atom ids from 1, as in the output pdb files
+    Multiple(Vec<Mutation>),
+}
+
+// system with the mutation applied
+pub struct Mutant {
+    pub atom_map: Vec<usize>,       // original atom id of each atom
+    pub aps: AtomProperties,
+    pub coordinates: Array3<f64>,
+    pub ndx_rec: Vec<usize>,
+    pub ndx_lig: Vec<usize>,
+    pub residues: Vec<Residue>,
+}
+
+impl Mutation {
+    pub fn new(nr: i32, target: &str, ligand: bool) -> Option<Mutation> {
+        target_name(target).map(|target| Mutation::Residue { nr, original: None, target, ligand })
+    }
+
+    // e.g. Y42G, 42G or 42GLY, with L: before for ligand residues, or D25A+I50A for multiple mutations
+    pub fn from(mutation: &str) -> Option<Mutation> {
+        if mutation.contains('+') {
+            let ms: Option<Vec<Mutation>> = mutation.split('+').map(Mutation::from).collect();
+            return ms.map(Mutation::Multiple);
+        }
+        let mutation = mutation.trim();
+        let (mutation, ligand) = match mutation.to_uppercase().starts_with("L:") {
+            true => (&mutation[2..], true),
+            false => (mutation, false)
+        };
+        let st = mutation.find(|c: char| c.is_ascii_digit())?;
+        let ed = mutation.rfind(|c: char| c.is_ascii_digit())? + 1;
+        let original = match st {
+            0 => None,
+            _ => Some(target_name(&mutation[..st])?)
+        };
+        let target = target_name(&mutation[ed..])?;
+        Some(Mutation::Residue { nr: mutation[st..ed].parse().ok()?, original, target, ligand })
+    }
+
+    // e.g. 1-5,8
+    pub fn group(atoms: &str) -> Option<Mutation> {
+        let mut atoms: Vec<usize> = utils::range2list(atoms).iter().filter_map(|&i| usize::try_from(i).ok()).collect();
+        atoms.sort();
+        atoms.dedup();
+        match atoms.is_empty() || atoms[0] == 0 {
+            true => None,
+            false => Some(Mutation::Group(atoms))
+        }
+    }
+
+    // e.g. Y42G, lig-Y5A, del_1-5_8 or D25A+I50A, None if mutated to itself,
+    // error if the residue is not found in the system or is not the original one given
+    pub fn label(&self, aps: &AtomProperties, ndx_lig: &[usize], residues: &[Residue]) -> Result<Option<String>, SmError> {
+        match self {
+            Mutation::Residue { nr, target, ligand, .. } => {
+                let res = self.residue(aps, ndx_lig, residues)?;
+                if one_letter(&res.name) == one_letter(target) {
+                    return Ok(None);
+                }
+                let side = match ligand {
+                    true => "lig-",
+                    false => ""
+                };
+                Ok(Some(format!("{}{}{}{}", side, one_letter(&res.name), nr, one_letter(target))))
+            },
+            Mutation::Group(atoms) => Ok(Some(group_label(atoms))),
+            Mutation::Multiple(ms) => {
+                let labels: Option<Vec<String>> = ms.iter().map(|m| m.label(aps, ndx_lig, residues))
+                    .collect::<Result<_, _>>()?;
+                Ok(labels.map(|labels| labels.join("+")))
+            }
+        }
+    }
+
+    // residue of the receptor or ligand side with the nr, which should be the original residue if given
+    fn residue<'a>(&self, aps: &AtomProperties, ndx_lig: &[usize], residues: &'a [Residue]) -> Result<&'a Residue, SmError> {
+        let Mutation::Residue { nr, original, ligand, .. } = self else {
+            return Err(SmError::Input("Not a residue mutation".to_string()));
+        };
+        let side = match ligand {
+            true => "ligand",
+            false => "receptor"
+        };
+        let res = residues.iter().find(|r| r.nr == *nr && ndx_lig.iter().any(|&i| aps.atom_props[i].resid == r.id) == *ligand)
+            .ok_or_else(|| SmError::Input(format!("Residue {} not found in {}", nr, side)))?;
+        match original {
+            Some(original) if one_letter(original) != one_letter(&res.name) =>
+                Err(SmError::Input(format!("Residue {} of {} is {}, not {}", nr, side, res.name, original))),
+            _ => Ok(res)
+        }
+    }
+
+    pub fn apply(&self, aps: &AtomProperties, coordinates: &Array3<f64>, ndx_rec: &[usize], ndx_lig: &[usize],
+                 residues: &[Residue], ff: Option<&ForceField>) -> Result<Mutant, SmError> {
+        match self {
+            Mutation::Residue { target, .. } => {
+                let res = self.residue(aps, ndx_lig, residues)?;
+                let ff = ff.ok_or_else(|| SmError::Input(format!("{} force field not loaded", FORCE_FIELD)))?;
+                let (aps, coordinates, ndx_rec, ndx_lig, atom_map) = mutate(aps, coordinates, ndx_rec, ndx_lig, res, target, ff)?;
+                let mut residues = residues.to_vec();
+                residues[res.id].name = target.to_string();
+                Ok(Mutant { atom_map, aps, coordinates, ndx_rec, ndx_lig, residues })
+            },
+            Mutation::Group(atoms) => {
+                if let Some(a) = atoms.iter().find(|&&a| !ndx_lig.contains(&(a - 1))) {
+                    return Err(SmError::Input(format!("Atom {} is not in ligand", a)));
+                }
+                let mut aps = aps.clone();
+                let dummy = dummy_type_id(&mut aps);
+                for &a in atoms {
+                    aps.atom_props[a - 1].charge = 0.0;
+                    aps.atom_props[a - 1].type_id = dummy;
+                }
+                Ok(Mutant { atom_map: (0..aps.atom_props.len()).collect(), aps, coordinates: coordinates.clone(),
+                    ndx_rec: ndx_rec.to_vec(), ndx_lig: ndx_lig.to_vec(), residues: residues.to_vec() })
+            },
+            Mutation::Multiple(ms) => {
+                // atom deletions first, as their atom ids change after residue mutations
+                let mut ms: Vec<&Mutation> = ms.iter().collect();
+                ms.sort_by_key(|m| !matches!(m, Mutation::Group(_)));
+                let mut mutant = Mutant { atom_map: (0..aps.atom_props.len()).collect(), aps: aps.clone(), coordinates: coordinates.clone(),
+                    ndx_rec: ndx_rec.to_vec(), ndx_lig: ndx_lig.to_vec(), residues: residues.to_vec() };
+                for m in ms {
+                    let atom_map = mutant.atom_map;
+                    mutant = m.apply(&mutant.aps, &mutant.coordinates, &mutant.ndx_rec, &mutant.ndx_lig, &mutant.residues, ff)?;
+                    mutant.atom_map = mutant.atom_map.iter().map(|&i| atom_map[i]).collect();
+                }
+                Ok(mutant)
+            }
+        }
+    }
+}
+
+// as given by the user, e.g. Y42G, L:5A, del_1-5_8 or D25A+I50A
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Mutation::Residue { nr, original, target, ligand } => {
+                let side = match ligand {
+                    true => "L:",
+                    false => ""
+                };
+                write!(f, "{}{}{}{}", side, original.as_deref().map(one_letter).unwrap_or_default(), nr, one_letter(target))
+            },
+            Mutation::Group(atoms) => write!(f, "{}", group_label(atoms)),
+            Mutation::Multiple(ms) => {
+                let ms: Vec<String> = ms.iter().map(|m| m.to_string()).collect();
+                write!(f, "{}", ms.join("+"))
+            }
+        }
+    }
+}
+
+// consecutive atoms as ranges, e.g. del_1-5_8
+fn group_label(atoms: &[usize]) -> String {
+    let mut ranges: Vec<String> = vec![];
+    let mut st = 0;
+    for i in 1..=atoms.len() {
+        if i == atoms.len() || atoms[i] != atoms[i - 1] + 1 {
+            ranges.push(match i - 1 > st {
+                true => format!("{}-{}", atoms[st], atoms[i - 1]),
+                false => atoms[st].to_string()
+            });
+            st = i;
+        }
+    }
+    format!("del_{}", ranges.join("_"))
+}
+
+// one letter name of amino acids, others unchanged
+fn one_letter(name: &str) -> String {
+    utils::resname_3to1(name).unwrap_or(name.to_string())
+}
+
+// 1 or 3 letters to the residue name of force field
+fn target_name(name: &str) -> Option<String> {
+    let name = name.trim().to_uppercase();
+    match name.len() {
+        1 => utils::resname_1to3(&name),
+        3 => Some(name),
+        _ => None
+    }
+}
+
+struct Template {
+    atoms: Vec<(String, String, f64)>,      // name, type, charge
+    bonds: Vec<[String; 2]>,
+}
+
+// residue templates and LJ parameters of the bundled force field
+pub struct ForceField {
+    templates: HashMap<String, Template>,
+    lj: HashMap<String, (f64, f64)>,        // sigma (nm) and epsilon (kJ/mol) of atom types
+}
+
+impl ForceField {
+    pub fn load() -> Result<ForceField, SmError> {
+        let ff_dir = env::current_exe().unwrap().parent().unwrap().join("include").join(format!("{}.ff", FORCE_FIELD));
+        let read = |name: &str| -> Result<(String, String), SmError> {
+            let path = ff_dir.join(name).display().to_string();
+            let content = fs::read_to_string(&path).map_err(|e| SmError::io(&path, e))?;
+            Ok((path, content))
+        };
+
+        let (rtp_path, rtp) = read("aminoacids.rtp")?;
+        let mut templates: HashMap<String, Template> = HashMap::new();
+        let mut residue = String::new();
+        let mut section = String::new();
+        for (l, line) in rtp.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                let name = line.trim_matches(|c| c == '[' || c == ']').trim();
+                match name {
+                    "atoms" | "bonds" | "impropers" | "dihedrals" | "cmap" => section = name.to_string(),
+                    "bondedtypes" => section = String::new(),
+                    _ => {
+                        residue = name.to_string();
+                        section = String::new();
+                        templates.insert(residue.to_string(), Template { atoms: vec![], bonds: vec![] });
+                    }
+                }
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match section.as_str() {
+                "atoms" if fields.len() >= 3 => {
+                    let charge = fields[2].parse().map_err(|_| SmError::parse(&rtp_path, l + 1, &format!("invalid charge {}", fields[2])))?;
+                    templates.get_mut(&residue).unwrap().atoms.push((fields[0].to_string(), fields[1].to_string(), charge));
+                },
+                "bonds" if fields.len() >= 2 => {
+                    templates.get_mut(&residue).unwrap().bonds.push([fields[0].to_string(), fields[1].to_string()]);
+                },
+                _ => {}
+            }
+        }
+
+        let (itp_path, itp) = read("ffnonbonded.itp")?;
+        let mut lj: HashMap<String, (f64, f64)> = HashMap::new();
+        let mut in_atomtypes = false;
+        for (l, line) in itp.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.starts_with('[') {
+                in_atomtypes = line.contains("atomtypes");
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if in_atomtypes && fields.len() >= 7 {
+                let parse = |s: &str| s.parse::<f64>().map_err(|_| SmError::parse(&itp_path, l + 1, &format!("invalid value {}", s)));
+                lj.insert(fields[0].to_string(), (parse(fields[5])?, parse(fields[6])?));
+            }
+        }
+        Ok(ForceField { templates, lj })
+    }
+}
+
+// atoms, coordinates, receptor/ligand indices and original atom ids of the mutated system
+type MutatedSystem = (AtomProperties, Array3<f64>, Vec<usize>, Vec<usize>, Vec<usize>);
+
+// the residue mutated to target, only if the target side chain is a truncation of the original one,
+// whose removed heavy atoms become hydrogens
+fn mutate(aps: &AtomProperties, coordinates: &Array3<f64>, ndx_rec: &[usize], ndx_lig: &[usize],
+              residue: &Residue, target: &str, ff: &ForceField) -> Result<MutatedSystem, SmError> {
+    let res_atoms: Vec<usize> = aps.atom_props.iter().filter(|a| a.resid == residue.id).map(|a| a.id).collect();
+    let name = |i: usize| aps.atom_props[i].name.as_str();
+    let is_h = |name: &str| name.starts_with('H');
+    let dist = |i: usize, j: usize| (0..3).map(|d| (coordinates[[0, i, d]] - coordinates[[0, j, d]]).powi(2)).sum::<f64>().sqrt();
+    let heavy: Vec<usize> = res_atoms.iter().cloned().filter(|&i| !is_h(name(i))).collect();
+    // hydrogens bonded to the heavy atom, by distance in the first frame
+    let h_of = |x: usize| -> Vec<usize> {
+        res_atoms.iter().cloned().filter(|&h| is_h(name(h)) && heavy.iter()
+            .min_by(|&&a, &&b| dist(h, a).partial_cmp(&dist(h, b)).unwrap())
+            .is_some_and(|&p| p == x && dist(h, x) < BOND_H_MAX)).collect()
+    };
+    let find = |n: &str| heavy.iter().cloned().find(|&x| name(x) == n);
+
+    // terminal residues have their own templates
+    let n_term = find("N").is_some_and(|n| h_of(n).len() >= 2);
+    let c_term = heavy.iter().any(|&x| ["OC1", "OC2", "OXT"].contains(&name(x)));
+    let key = match (n_term, c_term) {
+        (true, _) => format!("N{}", target),
+        (_, true) => format!("C{}", target),
+        _ => target.to_string()
+    };
+    let tpl = ff.templates.get(&key)
+        .ok_or_else(|| SmError::Input(format!("Residue {} not found in {} force field", key, FORCE_FIELD)))?;
+    let tpl_heavy: Vec<&(String, String, f64)> = tpl.atoms.iter().filter(|a| !is_h(&a.0)).collect();
+    if let Some(a) = tpl_heavy.iter().find(|a| find(&a.0).is_none()) {
+        return Err(SmError::Input(format!("{} is not a truncation of {}, atom {} not found", target, residue.name, a.0)));
+    }
+    // bonds of the template between heavy atoms must be in the original, e.g. the CD-N ring closure of PRO
+    if let Some([a, b]) = tpl.bonds.iter().find(|[a, b]| !is_h(a) && !is_h(b)
+        && find(a).zip(find(b)).is_some_and(|(x, y)| dist(x, y) >= BOND_MAX)) {
+        return Err(SmError::Input(format!("{} is not a truncation of {}, bond {}-{} not found", target, residue.name, a, b)));
+    }
+    // atom types of the template would be mixed into a system of another force field
+    if !tpl.atoms.iter().any(|a| aps.at_map.contains_key(&a.1)) {
+        return Err(SmError::Input(format!("No atom types of {} in {} found in the system, which should be of {} force field",
+                                          key, FORCE_FIELD, FORCE_FIELD)));
+    }
+    let removed: Vec<usize> = heavy.iter().cloned().filter(|&x| !tpl_heavy.iter().any(|a| a.0 == name(x))).collect();
+
+    let mut new_aps = aps.clone();
+    let mut new_coordinates = coordinates.clone();
+    let mut keep: Vec<usize> = vec![];
+    let mut used: Vec<usize> = vec![];
+    for (tpl_name, tpl_type, tpl_charge) in tpl_heavy {
+        let x = find(tpl_name).unwrap();
+        new_aps.atom_props[x].type_id = type_id(&mut new_aps, ff, tpl_type)?;
+        new_aps.atom_props[x].charge = *tpl_charge;
+        keep.push(x);
+        // hydrogens of template kept from the original ones first, then converted from removed heavy atoms
+        let tpl_h: Vec<&(String, String, f64)> = tpl.bonds.iter().filter_map(|[a, b]| match (a == tpl_name, b == tpl_name) {
+            (true, _) => Some(b),
+            (_, true) => Some(a),
+            _ => None
+        }).filter(|n| is_h(n)).filter_map(|n| tpl.atoms.iter().find(|a| &a.0 == n)).collect();
+        let have = h_of(x);
+        let spare: Vec<usize> = removed.iter().cloned().filter(|&y| !used.contains(&y) && dist(x, y) < BOND_MAX).collect();
+        if tpl_h.len() > have.len() + spare.len() {
+            return Err(SmError::Input(format!("No atoms to place hydrogens of {} in {}", tpl_name, target)));
+        }
+        // named as in the template, so that kept and converted hydrogens are not named the same
+        for (k, (h_name, h_type, h_charge)) in tpl_h.iter().enumerate() {
+            let h_type = type_id(&mut new_aps, ff, h_type)?;
+            let h = match have.get(k) {
+                Some(&h) => {
+                    new_aps.atom_props[h].type_id = h_type;
+                    new_aps.atom_props[h].name = h_name.to_string();
+                    h
+                },
+                None => {
+                    let y = spare[k - have.len()];
+                    used.push(y);
+                    new_aps.atom_props[y].change_atom(Some(&h_type), h_name, &aps.radius_type);
+                    let bond = match tpl_name.chars().next() {
+                        Some('N') => 1.01,
+                        Some('O') => 0.96,
+                        Some('S') => 1.34,
+                        _ => 1.09
+                    };
+                    for layer in 0..new_coordinates.shape()[0] {
+                        let base: Array1<f64> = new_coordinates.slice(s![layer, x, ..]).to_owned();
+                        let origin: Array1<f64> = new_coordinates.slice(s![layer, y, ..]).to_owned();
+                        new_coordinates.slice_mut(s![layer, y, ..]).assign(&transform_coordinate(&base, &origin, bond));
+                    }
+                    y
+                }
+            };
+            new_aps.atom_props[h].charge = *h_charge;
+            keep.push(h);
+        }
+    }
+
+    // delete other atoms in the mutated residue
+    let del_list: Vec<usize> = res_atoms.iter().cloned().filter(|i| !keep.contains(i)).collect();
+    new_aps.atom_props.retain(|a| !del_list.contains(&a.id));
+    let retain_id: Vec<usize> = new_aps.atom_props.iter().map(|a| a.id).collect();
+    // 每次删除原子后重新排序剩余原子id
+    for (i, ap) in new_aps.atom_props.iter_mut().enumerate() {
+        ap.id = i;
+    };
+    let new_coordinates: Array3<f64> = new_coordinates.select(Axis(1), &retain_id);
+    let mut new_ndx_rec = ndx_rec.to_vec();
+    new_ndx_rec.retain(|x| !del_list.contains(x));
+    let mut new_ndx_lig = ndx_lig.to_vec();
+    new_ndx_lig.retain(|x| !del_list.contains(x));
+    let (new_ndx_rec, new_ndx_lig) = normalize_index(&new_ndx_rec, Some(&new_ndx_lig));
+    Ok((new_aps, new_coordinates, new_ndx_rec, new_ndx_lig, retain_id))
+}
+
+// LJ type id without LJ interactions, for the deleted atoms
+fn dummy_type_id(aps: &mut AtomProperties) -> usize {
+    if let Some(&id) = aps.at_map.get("DUM") {
+        return id;
+    }
+    let n = aps.c6.nrows();
+    let mut c6: Array2<f64> = Array2::zeros((n + 1, n + 1));
+    let mut c12: Array2<f64> = Array2::zeros((n + 1, n + 1));
+    c6.slice_mut(s![..n, ..n]).assign(&aps.c6);
+    c12.slice_mut(s![..n, ..n]).assign(&aps.c12);
+    aps.c6 = c6;
+    aps.c12 = c12;
+    aps.at_map.insert("DUM".to_string(), n);
+    n
+}
+
+// LJ type id of the force field atom type, added to the system by Lorentz-Berthelot rules if not present
+fn type_id(aps: &mut AtomProperties, ff: &ForceField, at_type: &str) -> Result<usize, SmError> {
+    if let Some(&id) = aps.at_map.get(at_type) {
+        return Ok(id);
+    }
+    let &(sigma, eps) = ff.lj.get(at_type)
+        .ok_or_else(|| SmError::Input(format!("Atom type {} not found in {} force field", at_type, FORCE_FIELD)))?;
+    let n = aps.c6.nrows();
+    let mut c6: Array2<f64> = Array2::zeros((n + 1, n + 1));
+    let mut c12: Array2<f64> = Array2::zeros((n + 1, n + 1));
+    c6.slice_mut(s![..n, ..n]).assign(&aps.c6);
+    c12.slice_mut(s![..n, ..n]).assign(&aps.c12);
+    for i in 0..=n {
+        let (sigma_i, eps_i) = match i < n && aps.c6[[i, i]] > 0.0 && aps.c12[[i, i]] > 0.0 {
+            true => ((aps.c12[[i, i]] / aps.c6[[i, i]]).powf(1.0 / 6.0), aps.c6[[i, i]].powi(2) / 4.0 / aps.c12[[i, i]]),
+            false if i == n => (sigma, eps),
+            false => (0.0, 0.0)
+        };
+        let sigma_ij = (sigma_i + sigma) / 2.0;
+        let eps_ij = (eps_i * eps).sqrt();
+        c6[[i, n]] = 4.0 * eps_ij * sigma_ij.powi(6);
+        c12[[i, n]] = 4.0 * eps_ij * sigma_ij.powi(12);
+        c6[[n, i]] = c6[[i, n]];
+        c12[[n, i]] = c12[[i, n]];
+    }
+    aps.c6 = c6;
+    aps.c12 = c12;
+    aps.at_map.insert(at_type.to_string(), n);
+    Ok(n)
+}
+
+fn transform_coordinate(base: &Array1<f64>, origin: &Array1<f64>, target_length: f64) -> Array1<f64> {
+    let v_ch: Array1<f64> = origin - base;
+    let cur_len = v_ch.iter().map(|d| d.powi(2)).sum::<f64>().sqrt();
+    let lambda = target_length / cur_len;
+    let new_v_ch: Array1<f64> = Array1::from_iter(v_ch.iter().map(|r| r * lambda));
+    base + new_v_ch
+}
@@ -0,0 +1,80 @@
+use ndarray::ArrayView2;
+
+// call f(i, j, r) for each receptor-ligand atom pair within r_cutoff (A)
+// if receptor is ligand (ndx_lig[0] == ndx_rec[0]), each pair is visited once with i < j
+// finite cutoff uses cell list, otherwise all pairs are looped
+pub fn for_each_pair<F: FnMut(usize, usize, f64)>(coord: &ArrayView2<f64>, ndx_rec: &[usize], ndx_lig: &[usize],
+                                                  r_cutoff: f64, f: F) {
+    if r_cutoff.is_finite() && r_cutoff > 0.0 {
+        cell_list_pairs(coord, ndx_rec, ndx_lig, r_cutoff, f);
+    } else {
+        all_pairs(coord, ndx_rec, ndx_lig, r_cutoff, f);
+    }
+}
+
+pub fn all_pairs<F: FnMut(usize, usize, f64)>(coord: &ArrayView2<f64>, ndx_rec: &[usize], ndx_lig: &[usize],
+                                              r_cutoff: f64, mut f: F) {
+    let same = ndx_lig[0] == ndx_rec[0];
+    for &i in ndx_rec {
+        for &j in ndx_lig {
+            if same && j <= i {
+                continue;
+            }
+            let r = distance(coord, i, j);
+            if r <= r_cutoff {
+                f(i, j, r);
+            }
+        }
+    }
+}
+
+// ligand atoms put into cubic cells with edge of r_cutoff within its bounding box,
+// only 27 cells around each receptor atom searched and receptor atoms far from the box skipped
+pub fn cell_list_pairs<F: FnMut(usize, usize, f64)>(coord: &ArrayView2<f64>, ndx_rec: &[usize], ndx_lig: &[usize],
+                                                    r_cutoff: f64, mut f: F) {
+    let same = ndx_lig[0] == ndx_rec[0];
+    let mut lower = [f64::INFINITY; 3];
+    let mut upper = [f64::NEG_INFINITY; 3];
+    for &j in ndx_lig {
+        for d in 0..3 {
+            lower[d] = lower[d].min(coord[[j, d]]);
+            upper[d] = upper[d].max(coord[[j, d]]);
+        }
+    }
+    let n_cells: Vec<i64> = (0..3).map(|d| ((upper[d] - lower[d]) / r_cutoff) as i64 + 1).collect();
+    let cell_of = |i: usize| -> [i64; 3] {
+        [0, 1, 2].map(|d| ((coord[[i, d]] - lower[d]) / r_cutoff).floor() as i64)
+    };
+    let mut cells: Vec<Vec<usize>> = vec![vec![]; (n_cells[0] * n_cells[1] * n_cells[2]) as usize];
+    for &j in ndx_lig {
+        let [cx, cy, cz] = cell_of(j);
+        cells[((cx * n_cells[1] + cy) * n_cells[2] + cz) as usize].push(j);
+    }
+    for &i in ndx_rec {
+        let c = cell_of(i);
+        if (0..3).any(|d| c[d] < -1 || c[d] > n_cells[d]) {
+            continue;
+        }
+        let range = |d: usize| (c[d] - 1).max(0)..=(c[d] + 1).min(n_cells[d] - 1);
+        for cx in range(0) {
+            for cy in range(1) {
+                for cz in range(2) {
+                    for &j in &cells[((cx * n_cells[1] + cy) * n_cells[2] + cz) as usize] {
+                        if same && j <= i {
+                            continue;
+                        }
+                        let r = distance(coord, i, j);
+                        if r <= r_cutoff {
+                            f(i, j, r);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn distance(coord: &ArrayView2<f64>, i: usize, j: usize) -> f64 {
+    ((coord[[i, 0]] - coord[[j, 0]]).powi(2) + (coord[[i, 1]] - coord[[j, 1]]).powi(2)
+        + (coord[[i, 2]] - coord[[j, 2]]).powi(2)).sqrt()
+}
@@ -1,14 +1,18 @@
 use std::fmt::Formatter;
 use std::path::Path;
-use std::{fmt, fs};
+use std::fmt;
 use std::io::Write;
 use std::io::BufReader;
 use ndarray::Array2;
-use regex::Regex;
+use std::str::FromStr;
+use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::BufRead;
 
+use crate::error::SmError;
+use crate::get_dump;
+use crate::parse_tpx::{read_tpx, TPXData};
 use crate::settings::Settings;
 
 pub struct TPR {
@@ -37,25 +41,129 @@ impl fmt::Display for TPR {
 }
 
 impl TPR {
-    pub fn from(mdp: &str, settings: &Settings) -> TPR {
+    // read tpr natively, or the output of gmx dump if tpr version not supported
+    pub fn from(tpr_path: &str, settings: &Settings) -> Result<TPR, SmError> {
+        if !tpr_path.ends_with(".tpr") {
+            return TPR::from_dump(tpr_path, settings);
+        }
+        match read_tpx(tpr_path) {
+            Ok(tpx) => TPR::from_tpx(tpx, tpr_path, settings),
+            Err(e) => {
+                println!("Note: {}, will use gmx dump instead.", e);
+                let dump_path = get_dump(&tpr_path.to_string(), settings)?;
+                TPR::from_dump(&dump_path, settings)
+            }
+        }
+    }
+
+    // chemical bonds within atoms of ndx_com, indexed by the order of sorted ndx_com
+    pub fn get_bonds(&self, ndx_com: &[usize]) -> Vec<[usize; 2]> {
+        let mut local_ids: Vec<Option<usize>> = vec![None; self.n_atoms];
+        let mut ndx_com = ndx_com.to_vec();
+        ndx_com.sort_unstable();
+        for (i, &a) in ndx_com.iter().enumerate() {
+            local_ids[a] = Some(i);
+        }
+        let mut bonds: Vec<[usize; 2]> = vec![];
+        let mut offset = 0;
+        for mol in &self.molecules {
+            for _ in 0..self.molecule_types[mol.molecule_type_id].molecules_num {
+                for &[i, j] in &mol.bonds {
+                    if let (Some(i), Some(j)) = (local_ids[offset + i], local_ids[offset + j]) {
+                        bonds.push([i, j]);
+                    }
+                }
+                offset += mol.atoms_num;
+            }
+        }
+        bonds
+    }
+
+    fn from_tpx(tpx: TPXData, tpr_path: &str, settings: &Settings) -> Result<TPR, SmError> {
+        println!("Loading tpr file: {}\n", tpr_path);
+        let name = tpx.name.trim().replace(" ", "_");
+        println!("System name: {}", name);
+        println!("Total atoms number: {}", tpx.natoms);
+
+        println!("System molecular types:");
+        let mut molecule_types: Vec<MolType> = vec![];
+        for (mt_id, &(mol_type, molecules_num)) in tpx.molblocks.iter().enumerate() {
+            let moltype = MolType::new(mt_id, tpx.moltypes[mol_type].name.to_string(), molecules_num);
+            println!("{}", moltype);
+            molecule_types.push(moltype);
+        }
+
+        println!("Total atom types: {}.", tpx.atnr);
+        let fun_type: Vec<LJType> = tpx.lj.iter().map(|&(c6, c12)| LJType::new(c6, c12)).collect();
+        let radius: Vec<f64> = (0..tpx.atnr).map(|i| {
+            let (c6, c12) = tpx.lj[i * tpx.atnr + i];
+            ff_radius(c6, c12, settings)
+        }).collect();
+        println!("Total LJ function types: {}", fun_type.len());
+
+        let mut molecules: Vec<Molecule> = vec![];
+        let mut atom_radii: Vec<f64> = vec![];
+        for (molecule_type_id, mt) in tpx.moltypes.iter().enumerate() {
+            println!("Reading molecule {} information...", molecule_type_id);
+            let offset: usize = molecules.iter().map(|p| p.atoms_num).sum();
+            let mut atom_names = mt.atom_names.to_vec();
+            // assign H types by connection atoms from angle information
+            for [i, j, k] in mt.angles() {
+                rename_h(&mut atom_names, i, j, k);
+            }
+            let atoms: Vec<Atom> = mt.atoms.iter().enumerate().map(|(i, a)| {
+                atom_radii.push(radius[a.type_id]);
+                Atom::new(offset + i, &mt.type_names[i], a.type_id, (a.mass, a.charge), a.resind,
+                          atom_names[i].to_string(), radius[a.type_id])
+            }).collect();
+            let residues: Vec<Residue> = mt.residues.iter().enumerate()
+                .map(|(id, (name, nr))| Residue::new(id, name.to_string(), *nr)).collect();
+            molecules.push(Molecule::new(molecule_type_id, mt.name.to_string(), &atoms, &residues, &mt.bonds()));
+        }
+
+        println!("Reading coordinate information...");
+        let coordinates: Vec<f64> = tpx.x.iter().map(|x| x * 10.0).collect();
+
+        write_ff_radius(tpr_path, &atom_radii)?;
+
+        println!("System molecular composition:");
+        for mol in &molecules {
+            println!("Molecule {}: {}", mol.molecule_type_id, mol);
+        }
+
+        Ok(TPR {
+            name,
+            n_atoms: tpx.natoms,
+            molecule_types_num: molecule_types.len(),
+            molecule_types,
+            atom_types_num: tpx.atnr,
+            lj_sr_params: fun_type,
+            molecules,
+            dt: tpx.dt,
+            nsteps: tpx.nsteps,
+            nstxout: tpx.nstxout,
+            temp: tpx.ref_t,
+            coordinates: Array2::from_shape_vec((tpx.natoms, 3), coordinates).unwrap()
+        })
+    }
+
+    fn from_dump(mdp: &str, settings: &Settings) -> Result<TPR, SmError> {
         let mut name = String::new();
         let mut atoms_num = 0;
         let mut molecule_types_num = 0;
         let mut atom_types_num = 0;
         let mut molecule_types: Vec<MolType> = vec![];
 
-        let file = File::open(mdp).unwrap();
-        let mut reader = BufReader::new(file);
+        let mut reader = DumpReader::open(mdp)?;
         let mut buf = String::from("");
 
         let mut fun_type: Vec<LJType> = vec![];
-        let mut sigma: Vec<f64> = vec![];
-        let mut epsilon: Vec<f64> = vec![];
         let mut radius: Vec<f64> = vec![];
 
         let mut atom_resids: Vec<usize> = vec![];   // residue ids of each atom
         let mut atom_types: Vec<usize> = vec![];    // atom type
         let mut atom_radii: Vec<f64> = vec![];      // atom radius
+        let mut atom_masses: Vec<f64> = vec![];     // atom mass
         let mut atom_charges: Vec<f64> = vec![];    // atom charge
         let mut atom_names: Vec<String> = vec![];   // atom name
         let mut type_names: Vec<String> = vec![];   // atom type name
@@ -70,9 +178,17 @@ impl TPR {
         let mut nstxout = 0;
         let mut temp = 0.0;
 
+        // chemical bonds, constraints and settles, for making molecules whole
+        let bond_re = Regex::new(r"\((BONDS|G96BONDS|MORSE|CUBICBONDS|CONNBONDS|HARMONIC|FENEBONDS|TABBONDS|CONSTR|SETTLE)\)\s+(\d+)\s*(\d*)\s*(\d*)").unwrap();
+
+        // atom parameters, e.g.
+        // atom[     0]={type=  0, typeB=  0, ptype=    Atom, m= 1.60000e+01,
+        // q=-4.91104e-01, mB= 1.60000e+01, qB=-4.91104e-01, resind=    0, atomnumber= -1}
+        let atom_re = Regex::new(r".*type=\s*(\d+).*\bm=\s*([^,]+),\s*q=\s*([^,]+),.*resind=\s*(\d+).*").unwrap();
+
         println!("Loading dump file: {}\n", mdp);
         loop {
-            let bytes = read_line(&mut reader, &mut buf);
+            let bytes = reader.read_line(&mut buf)?;
             if bytes == 0 {
                 break;
             }
@@ -80,16 +196,16 @@ impl TPR {
             // MD steps
             if buf.starts_with("inputrec:") {
                 loop {
-                    read_line(&mut reader, &mut buf);
+                    reader.next_line(&mut buf)?;
                     if buf.trim().starts_with("dt") {
                         let re = Regex::new(r"dt\s+=\s*(.*)").unwrap();
-                        dt = re.captures(&buf).unwrap().get(1).unwrap().as_str().trim().parse().unwrap();
-                        read_line(&mut reader, &mut buf);
+                        dt = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
+                        reader.next_line(&mut buf)?;
                         let re = Regex::new(r"nsteps\s+=\s*(.*)").unwrap();
-                        nsteps = re.captures(&buf).unwrap().get(1).unwrap().as_str().trim().parse().unwrap();
+                        nsteps = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                     } else if buf.trim().starts_with("nstxout-compressed") {
                         let re = Regex::new(r"nstxout-compressed\s+=\s*(.*)").unwrap();
-                        nstxout = re.captures(&buf).unwrap().get(1).unwrap().as_str().trim().parse().unwrap();
+                        nstxout = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                         break
                     }
                 }
@@ -100,40 +216,40 @@ impl TPR {
                     0 => None,
                     _ => Some(p)
                 }).collect();
-                temp = ref_t[1].trim().parse().expect("Failed to get temperature.");
+                temp = reader.parse(ref_t.get(1).unwrap_or(&"").trim())?;
             }
 
             // molecules define
             if buf.starts_with("topology:") {
                 // name
-                read_line(&mut reader, &mut buf);       // name="Protein in water"
+                reader.next_line(&mut buf)?;       // name="Protein in water"
                 let re = Regex::new("name\\s*=\\s*\"(.*)\"").unwrap();
-                name = re.captures(&buf).unwrap().get(1).unwrap().as_str().trim().to_string();
+                name = reader.get(&re, &buf, 1)?.trim().to_string();
                 name = name.replace(" ", "_");
                 println!("System name: {}", name);
 
                 // atom num
-                read_line(&mut reader, &mut buf);       // #atoms = 3218
+                reader.next_line(&mut buf)?;       // #atoms = 3218
                 let re = Regex::new(r"#atoms\s*=\s*(\d+)").unwrap();
-                atoms_num = re.captures(&buf).unwrap().get(1).unwrap().as_str().trim().parse().unwrap();
+                atoms_num = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                 println!("Total atoms number: {}", atoms_num);
 
                 // molecule types num
-                read_line(&mut reader, &mut buf);
+                reader.next_line(&mut buf)?;
                 let re = Regex::new(r"#molblock\s*=\s*(\d+)").unwrap();
-                molecule_types_num = re.captures(&buf).unwrap().get(1).unwrap().as_str().trim().parse().unwrap();
+                molecule_types_num = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
 
                 println!("System molecular types:");
                 for mt_id in 0..molecule_types_num {
                     loop {
-                        read_line(&mut reader, &mut buf);
+                        reader.next_line(&mut buf)?;
                         if buf.trim().starts_with("molblock (") {
-                            read_line(&mut reader, &mut buf);
+                            reader.next_line(&mut buf)?;
                             let re = Regex::new("moltype\\s*=\\s*\\d+\\s*\"(.*)\"").unwrap();
-                            let name = re.captures(&buf).unwrap().get(1).unwrap().as_str().to_string();
-                            read_line(&mut reader, &mut buf);
+                            let name = reader.get(&re, &buf, 1)?.to_string();
+                            reader.next_line(&mut buf)?;
                             let re = Regex::new(r"#molecules\s*=\s*(\d+)").unwrap();
-                            let molecules_num: i64 = re.captures(&buf).unwrap().get(1).unwrap().as_str().parse().unwrap();
+                            let molecules_num: i64 = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                             let moltype = MolType::new(mt_id, name, molecules_num);
                             println!("{}", moltype);
                             molecule_types.push(moltype);
@@ -145,32 +261,23 @@ impl TPR {
 
             // force field parameters (atom radius here)
             if buf.trim().starts_with("ffparams:") {
-                read_line(&mut reader, &mut buf);
+                reader.next_line(&mut buf)?;
                 let re = Regex::new(r"atnr\s*=\s*(\d+)").unwrap();
-                atom_types_num = re.captures(&buf).unwrap().get(1).unwrap().as_str().parse().unwrap();
+                atom_types_num = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                 println!("Total atom types: {}.", atom_types_num);
 
-                read_line(&mut reader, &mut buf);
+                reader.next_line(&mut buf)?;
                 // functype[0]=LJ_SR, c6= 2.07413384e-03, c12= 1.51207360e-06
                 let re = Regex::new(r"functype\[(\d*)]=LJ_SR,\s*c6\s*=\s*(\S*),\s*c12\s*=\s*(\S*)").unwrap();
                 for i in 0..atom_types_num {
                     for j in 0..atom_types_num {
-                        read_line(&mut reader, &mut buf);
-                        let m = re.captures(&buf).unwrap();
-                        let c6: f64 = m.get(2).unwrap().as_str().parse().unwrap();
-                        let c12: f64 = m.get(3).unwrap().as_str().parse().unwrap();
+                        reader.next_line(&mut buf)?;
+                        let m = reader.captures(&re, &buf)?;
+                        let c6: f64 = reader.parse(&m[2])?;
+                        let c12: f64 = reader.parse(&m[3])?;
                         fun_type.push(LJType::new(c6, c12));
-                        // calculate σ, ε, radius for each atom
                         if j == i {
-                            if c6 != 0.0 && c12 != 0.0 {
-                                sigma.push(10.0 * (c12 / c6).powf(1.0 / 6.0)); // nm to A
-                                epsilon.push(c6.powi(2) / (4.0 * c12));
-                                radius.push(sigma[i] / 2.0); // sigma is diameter
-                            } else {
-                                sigma.push(0.0);
-                                epsilon.push(0.0);
-                                radius.push(settings.radius_ff_default);
-                            }
+                            radius.push(ff_radius(c6, c12, settings));
                         }
                     }
                 }
@@ -184,90 +291,86 @@ impl TPR {
                 let offset: usize = molecules.iter().map(|p| p.atoms_num).sum();
 
                 let re = Regex::new(r"moltype \((\d+)\)").unwrap();
-                let molecule_type_id: usize = re.captures(&buf).unwrap().get(1).unwrap().as_str().parse().unwrap();
+                let molecule_type_id: usize = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                 println!("Reading molecule {} information...", molecule_type_id);
-                read_line(&mut reader, &mut buf);
+                reader.next_line(&mut buf)?;
                 let re = Regex::new("name\\s*=\\s*\"(.*)\"").unwrap();
-                let molecule_name = re.captures(&buf).unwrap().get(1).unwrap().as_str().to_string();
-                read_line(&mut reader, &mut buf);
-                read_line(&mut reader, &mut buf);
+                let molecule_name = reader.get(&re, &buf, 1)?.to_string();
+                reader.next_line(&mut buf)?;
+                reader.next_line(&mut buf)?;
                 let re = Regex::new(r"atom \((\d+)\):").unwrap();
-                let atoms_num: usize = re.captures(&buf).unwrap().get(1).unwrap().as_str().parse().unwrap();
+                let atoms_num: usize = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
 
                 // atom parameters
-                // atom[     0]={type=  0, typeB=  0, ptype=    Atom, m= 1.60000e+01,
-                // q=-4.91104e-01, mB= 1.60000e+01, qB=-4.91104e-01, resind=    0, atomnumber= -1}
-                let re = Regex::new(r".*type=\s*(\d+).*q=\s*([^,]+),.*resind=\s*(\d+).*").unwrap();
                 for _ in 0..atoms_num {
-                    read_line(&mut reader, &mut buf);
-                    let c = re.captures(&buf).unwrap();
-                    let atom_type_id: usize = c.get(1).unwrap().as_str().parse().unwrap();
-                    let atom_charge: f64 = c.get(2).unwrap().as_str().parse().unwrap();
-                    let residue_index: usize = c.get(3).unwrap().as_str().parse().unwrap();
+                    reader.next_line(&mut buf)?;
+                    let c = reader.captures(&atom_re, &buf)?;
+                    let atom_type_id: usize = reader.parse(&c[1])?;
+                    let atom_mass: f64 = reader.parse(&c[2])?;
+                    let atom_charge: f64 = reader.parse(&c[3])?;
+                    let residue_index: usize = reader.parse(&c[4])?;
                     atom_resids.push(residue_index);
                     atom_types.push(atom_type_id);
                     atom_radii.push(radius[atom_type_id]);
+                    atom_masses.push(atom_mass);
                     atom_charges.push(atom_charge);
                 }
 
                 // atom names
-                read_line(&mut reader, &mut buf);
+                reader.next_line(&mut buf)?;
                 // atom[0]={name="O1"}
                 let re = Regex::new("name=\"(.*)\"").unwrap();
                 for _ in 0..atoms_num {
-                    read_line(&mut reader, &mut buf);
-                    let name = re.captures(&buf).unwrap().get(1).unwrap().as_str();
+                    reader.next_line(&mut buf)?;
+                    let name = reader.get(&re, &buf, 1)?;
                     atom_names.push(name.to_string());
                 }
 
                 // atom types
-                read_line(&mut reader, &mut buf);
+                reader.next_line(&mut buf)?;
                 // type[0]={name="N3",nameB="N3"}
                 let re = Regex::new("name=\"(.*)\",").unwrap();
                 for _ in 0..atoms_num {
-                    read_line(&mut reader, &mut buf);
-                    let name = re.captures(&buf).unwrap().get(1).unwrap().as_str();
+                    reader.next_line(&mut buf)?;
+                    let name = reader.get(&re, &buf, 1)?;
                     type_names.push(name.to_string());
                 }
 
                 // residues
-                read_line(&mut reader, &mut buf);
+                reader.next_line(&mut buf)?;
                 let re = Regex::new(r"\s*residue \((\d+)\)").unwrap();
-                let res_num: i32 = re.captures(&buf).unwrap().get(1).unwrap().as_str().parse().unwrap();
+                let res_num: i32 = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                 let re = Regex::new("residue\\[(\\d+)]=\\{name=\"(.+)\",.*nr=([\\d\\-]+).*").unwrap();
                 for _ in 0..res_num {
-                    read_line(&mut reader, &mut buf);
-                    let m = re.captures(&buf).unwrap();
-                    let id: usize = m.get(1).unwrap().as_str().parse().unwrap();
-                    let name = m.get(2).unwrap().as_str().to_string();
-                    let nr: i32 = m.get(3).unwrap().as_str().parse().unwrap();
+                    reader.next_line(&mut buf)?;
+                    let m = reader.captures(&re, &buf)?;
+                    let id: usize = reader.parse(&m[1])?;
+                    let name = m[2].to_string();
+                    let nr: i32 = reader.parse(&m[3])?;
                     residues.push(Residue::new(id, name, nr));
                 }
 
-                // angles
+                // bonds and angles
+                let mut bonds: Vec<[usize; 2]> = vec![];
                 loop {
-                    read_line(&mut reader, &mut buf);
+                    reader.next_line(&mut buf)?;
+                    read_bonds(&buf, &bond_re, &mut bonds);
                     if buf.trim().starts_with("Angle:") {
-                        read_line(&mut reader, &mut buf);
+                        reader.next_line(&mut buf)?;
                         let re = Regex::new(r"nr\s*:\s*(\d+)").unwrap();
-                        let angles: i32 = re.captures(&buf).unwrap().get(1).unwrap().as_str().parse().unwrap();
+                        let angles: i32 = reader.parse(reader.get(&re, &buf, 1)?.trim())?;
                         if angles > 0 {
-                            read_line(&mut reader, &mut buf);
+                            reader.next_line(&mut buf)?;
                             let re = Regex::new(r"\(ANGLES\)\s+(\d+)\s+(\d+)\s+(\d+)").unwrap();
                             // assign H types by connection atoms from angle information
                             for _ in 0..angles / 4 {
-                                read_line(&mut reader, &mut buf);
+                                reader.next_line(&mut buf)?;
                                 if re.is_match(&buf) {
-                                    let c = re.captures(&buf).unwrap();
-                                    let i: usize = c.get(1).unwrap().as_str().parse().unwrap();
-                                    let j: usize = c.get(2).unwrap().as_str().parse().unwrap();
-                                    let k: usize = c.get(3).unwrap().as_str().trim().parse().unwrap();
-                                    if atom_names[offset + i].starts_with(['H', 'h']) {
-                                        atom_names[offset + i] = format!("H{}", atom_names[offset + j]);
-                                    }
-                                    if atom_names[offset + k].starts_with(['H', 'h']) {
-                                        atom_names[offset + k] = format!("H{}", atom_names[offset + j]);
-                                    }
+                                    let c = reader.captures(&re, &buf)?;
+                                    let i: usize = reader.parse(&c[1])?;
+                                    let j: usize = reader.parse(&c[2])?;
+                                    let k: usize = reader.parse(&c[3])?;
+                                    rename_h(&mut atom_names[offset..], i, j, k);
                                 } else {
                                     break;
                                 }
@@ -282,14 +385,18 @@ impl TPR {
                     atoms.push(Atom::new(id,
                                         &type_names[id],
                                         atom_types[id],
-                                        atom_charges[id],
+                                        (atom_masses[id], atom_charges[id]),
                                         atom_resids[id],
                                         atom_names[id].to_string(),
                                         atom_radii[id]));
                 }
 
-                molecules.push(Molecule::new(molecule_type_id, molecule_name, atoms_num,
-                                             &atoms, &residues));
+                molecules.push(Molecule::new(molecule_type_id, molecule_name, &atoms, &residues, &bonds));
+            }
+
+            // constraints and settles after angles
+            if let Some(mol) = molecules.last_mut() {
+                read_bonds(&buf, &bond_re, &mut mol.bonds);
             }
 
             // coordinates
@@ -298,12 +405,12 @@ impl TPR {
                 println!("Reading coordinate information...");
                 let re = Regex::new(r"\{\s*(.*),\s*(.*),\s*(.*)\}").unwrap();
                 for _ in 0..atoms_num {
-                    read_line(&mut reader, &mut buf);
+                    reader.next_line(&mut buf)?;
                     // x[    0]={ 1.41430e+00,  1.38595e+00,  2.14591e-01}
-                    let caps = re.captures(&buf).unwrap();
-                    let x: f64 = caps.get(1).unwrap().as_str().parse().unwrap();
-                    let y: f64 = caps.get(2).unwrap().as_str().parse().unwrap();
-                    let z: f64 = caps.get(3).unwrap().as_str().parse().unwrap();
+                    let caps = reader.captures(&re, &buf)?;
+                    let x: f64 = reader.parse(&caps[1])?;
+                    let y: f64 = reader.parse(&caps[2])?;
+                    let z: f64 = reader.parse(&caps[3])?;
                     coordinates.push(x * 10.0);
                     coordinates.push(y * 10.0);
                     coordinates.push(z * 10.0);
@@ -311,22 +418,17 @@ impl TPR {
             }
         }
 
-        println!("Backup force field radius...");
-        let ff_dat = Path::new(mdp).parent().unwrap().join("ff_radius.dat");
-        if ff_dat.is_file() {
-            fs::remove_file(&ff_dat).unwrap();
-        }
-        let mut ff_dat = File::create(ff_dat).unwrap();
-        for r in atom_radii {
-            writeln!(ff_dat, "{:.2}", r).unwrap();
-        }
+        write_ff_radius(mdp, &atom_radii)?;
 
         println!("System molecular composition:");
         for mol in &molecules {
             println!("Molecule {}: {}", mol.molecule_type_id, mol);
         }
 
-        TPR {
+        if coordinates.len() != atoms_num * 3 {
+            return Err(SmError::parse(mdp, 0, &format!("coordinates of {} atoms not found", atoms_num)));
+        }
+        Ok(TPR {
             name,
             n_atoms: atoms_num,
             molecule_types_num,
@@ -339,7 +441,7 @@ impl TPR {
             nstxout,
             temp,
             coordinates: Array2::from_shape_vec((atoms_num, 3), coordinates).unwrap()
-        }
+        })
     }
 }
 
@@ -385,17 +487,19 @@ pub struct Molecule {
     pub atoms_num: usize,
     pub atoms: Vec<Atom>,
     pub residues: Vec<Residue>,
+    pub bonds: Vec<[usize; 2]>,
 }
 
 impl Molecule {
-    fn new(molecule_type_id: usize, molecule_name: String, atoms_num: usize,
-           atoms: &Vec<Atom>, residues: &Vec<Residue>) -> Molecule {
+    fn new(molecule_type_id: usize, molecule_name: String,
+           atoms: &[Atom], residues: &[Residue], bonds: &[[usize; 2]]) -> Molecule {
         Molecule {
             molecule_type_id,
             molecule_name,
-            atoms_num,
+            atoms_num: atoms.len(),
             atoms: atoms.to_vec(),
             residues: residues.to_vec(),
+            bonds: bonds.to_vec(),
         }
     }
 }
@@ -412,6 +516,7 @@ pub struct Atom {
     pub id: usize,
     pub at_type: String,
     pub type_id: usize,
+    pub mass: f64,
     pub charge: f64,
     pub resind: usize,
     pub name: String,
@@ -426,12 +531,13 @@ impl fmt::Display for Atom {
 }
 
 impl Atom {
-    fn new(id: usize, at_type: &str, type_id: usize, charge: f64, residue_index: usize, name: String,
+    fn new(id: usize, at_type: &str, type_id: usize, (mass, charge): (f64, f64), residue_index: usize, name: String,
            radius: f64) -> Atom {
         Atom {
             id,
             at_type: at_type.to_string(),
             type_id,
+            mass,
             charge,
             resind: residue_index,
             name,
@@ -463,7 +569,91 @@ impl Residue {
     }
 }
 
-fn read_line(reader: &mut BufReader<File>, buf: &mut String) -> usize {
-    buf.clear();
-    reader.read_line(buf).unwrap()
+// calculate radius from LJ parameters of each atom type
+fn ff_radius(c6: f64, c12: f64, settings: &Settings) -> f64 {
+    if c6 != 0.0 && c12 != 0.0 {
+        let sigma = 10.0 * (c12 / c6).powf(1.0 / 6.0); // nm to A
+        sigma / 2.0 // sigma is diameter
+    } else {
+        settings.radius_ff_default
+    }
+}
+
+// rename H atoms of angle i-j-k by the connected heavy atom
+fn rename_h(atom_names: &mut [String], i: usize, j: usize, k: usize) {
+    if atom_names[i].starts_with(['H', 'h']) {
+        atom_names[i] = format!("H{}", atom_names[j]);
+    }
+    if atom_names[k].starts_with(['H', 'h']) {
+        atom_names[k] = format!("H{}", atom_names[j]);
+    }
+}
+
+// e.g. 0 type=121 (BONDS)   0  18, settle of old versions only with O
+fn read_bonds(buf: &str, re: &Regex, bonds: &mut Vec<[usize; 2]>) {
+    if let Some(c) = re.captures(buf) {
+        let i: usize = c.get(2).unwrap().as_str().parse().unwrap();
+        let j: usize = c.get(3).unwrap().as_str().parse().unwrap_or(i + 1);
+        bonds.push([i, j]);
+        if c.get(1).unwrap().as_str().eq("SETTLE") {
+            let k: usize = c.get(4).unwrap().as_str().parse().unwrap_or(i + 2);
+            bonds.push([i, k]);
+        }
+    }
+}
+
+fn write_ff_radius(tpr_path: &str, atom_radii: &[f64]) -> Result<(), SmError> {
+    println!("Backup force field radius...");
+    let ff_dat = Path::new(tpr_path).parent().unwrap_or(Path::new(".")).join("ff_radius.dat");
+    let ff_name = ff_dat.display().to_string();
+    let mut ff_dat = File::create(ff_dat).map_err(|e| SmError::io(&ff_name, e))?;
+    for r in atom_radii {
+        writeln!(ff_dat, "{:.2}", r).map_err(|e| SmError::io(&ff_name, e))?;
+    }
+    Ok(())
+}
+
+// gmx dump output read by lines, so that errors could be located
+struct DumpReader {
+    reader: BufReader<File>,
+    file: String,
+    line: usize,
+}
+
+impl DumpReader {
+    fn open(file: &str) -> Result<DumpReader, SmError> {
+        let f = File::open(file).map_err(|e| SmError::io(file, e))?;
+        Ok(DumpReader { reader: BufReader::new(f), file: file.to_string(), line: 0 })
+    }
+
+    // 0 at the end of file
+    fn read_line(&mut self, buf: &mut String) -> Result<usize, SmError> {
+        buf.clear();
+        self.line += 1;
+        self.reader.read_line(buf).map_err(|e| SmError::io(&self.file, e))
+    }
+
+    // the dump file should not end here
+    fn next_line(&mut self, buf: &mut String) -> Result<(), SmError> {
+        match self.read_line(buf)? {
+            0 => Err(self.error("unexpected end of file")),
+            _ => Ok(())
+        }
+    }
+
+    fn captures<'b>(&self, re: &Regex, buf: &'b str) -> Result<Captures<'b>, SmError> {
+        re.captures(buf).ok_or_else(|| self.error(&format!("expected {}, found {}", re, buf.trim())))
+    }
+
+    fn get<'b>(&self, re: &Regex, buf: &'b str, i: usize) -> Result<&'b str, SmError> {
+        Ok(self.captures(re, buf)?.get(i).map_or("", |m| m.as_str()))
+    }
+
+    fn parse<T: FromStr>(&self, s: &str) -> Result<T, SmError> {
+        s.parse().map_err(|_| self.error(&format!("invalid value {}", s)))
+    }
+
+    fn error(&self, msg: &str) -> SmError {
+        SmError::parse(&self.file, self.line, msg)
+    }
 }
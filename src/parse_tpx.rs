@@ -0,0 +1,533 @@
+use std::fs;
+
+// binary tpr (tpx) reader, layout follows tpxio.cpp of GROMACS
+// only tpx version 119 (GROMACS 2020) to 133 (GROMACS 2024) supported, whose body is big-endian in-memory serialized
+const TPX_MIN_VERSION: i32 = 119;
+const TPX_MAX_VERSION: i32 = 133;
+const TPX_GENERATION: i32 = 28;
+
+// tpx versions changing the layout
+const TPXV_STORE_NONBONDED_INTERACTION_EXCLUSION_GROUP: i32 = 120;
+const TPXV_VSITE1: i32 = 121;
+const TPXV_MTS: i32 = 122;
+const TPXV_REMOVED_CONSTANT_ACCELERATION: i32 = 123;
+const TPXV_SOFTCORE_GAPSYS: i32 = 125;
+const TPXV_READDED_CONSTANT_ACCELERATION: i32 = 126;
+const TPXV_REMOVE_THOLE_RFAC: i32 = 127;
+const TPXV_REMOVE_ATOMTYPES: i32 = 128;
+const TPXV_ENSEMBLE_TEMPERATURE: i32 = 129;
+const TPXV_MASS_REPARTITIONING: i32 = 131;
+const TPXV_VERLET_BUFFER_PRESSURE_TOL: i32 = 133;
+
+// interaction function types, the same order as ifunc.h
+const F_BONDS: usize = 0;
+const F_TABBONDS: usize = 7;
+const F_ANGLES: usize = 10;
+const F_CONSTR: usize = 62;
+const F_SETTLE: usize = 64;
+const F_LJ: i32 = 37;
+const F_VSITE1: i32 = 65;
+const F_NRE: usize = 94;
+const NR_GROUP_TYPES: usize = 10;       // SimulationAtomGroupType
+const NR_FEP_TYPES: usize = 7;          // FreeEnergyPerturbationCouplingType
+
+pub struct TPXAtom {
+    pub type_id: usize,
+    pub mass: f64,
+    pub charge: f64,
+    pub resind: usize,
+}
+
+pub struct TPXMolType {
+    pub name: String,
+    pub atoms: Vec<TPXAtom>,
+    pub atom_names: Vec<String>,
+    pub type_names: Vec<String>,
+    pub residues: Vec<(String, i32)>,
+    pub ilists: Vec<Vec<i32>>,          // iatoms of each interaction function type
+}
+
+impl TPXMolType {
+    // atom pairs of chemical bonds, constraints and settles, local index in molecule
+    pub fn bonds(&self) -> Vec<[usize; 2]> {
+        let mut bonds: Vec<[usize; 2]> = vec![];
+        for ilist in self.ilists[F_BONDS..=F_TABBONDS].iter().chain([&self.ilists[F_CONSTR]]) {
+            bonds.extend(ilist.chunks(3).map(|b| [b[1] as usize, b[2] as usize]));
+        }
+        for s in self.ilists[F_SETTLE].chunks(4) {
+            bonds.push([s[1] as usize, s[2] as usize]);
+            bonds.push([s[1] as usize, s[3] as usize]);
+        }
+        bonds
+    }
+
+    // atoms of each angle, local index in molecule
+    pub fn angles(&self) -> Vec<[usize; 3]> {
+        self.ilists[F_ANGLES].chunks(4).map(|a| [a[1] as usize, a[2] as usize, a[3] as usize]).collect()
+    }
+}
+
+pub struct TPXData {
+    pub name: String,
+    pub natoms: usize,
+    pub atnr: usize,
+    pub lj: Vec<(f64, f64)>,            // c6 and c12 of each atom type pair
+    pub moltypes: Vec<TPXMolType>,
+    pub molblocks: Vec<(usize, i64)>,   // molecule type and number of molecules
+    pub x: Vec<f64>,                    // nm
+    pub dt: f64,
+    pub nsteps: u64,
+    pub nstxout: u32,
+    pub ref_t: f64,
+}
+
+struct TPXReader {
+    buf: Vec<u8>,
+    pos: usize,
+    double: bool,
+}
+
+impl TPXReader {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        if self.pos + n > self.buf.len() {
+            return Err("unexpected end of tpr file".to_string());
+        }
+        self.pos += n;
+        Ok(&self.buf[self.pos - n..self.pos])
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), String> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // float or double according to tpr precision, float converted by its shortest decimal as gmx dump prints
+    fn real(&mut self) -> Result<f64, String> {
+        match self.double {
+            true => self.f64(),
+            false => Ok(f32::from_be_bytes(self.take(4)?.try_into().unwrap()).to_string().parse().unwrap())
+        }
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        let n = self.i32()?;
+        if n < 0 {
+            return Err(format!("negative count {} in tpr file", n));
+        }
+        Ok(n as usize)
+    }
+
+    fn skip_ints(&mut self, n: usize) -> Result<(), String> {
+        self.skip(4 * n)
+    }
+
+    fn skip_reals(&mut self, n: usize) -> Result<(), String> {
+        self.skip(n * if self.double { 8 } else { 4 })
+    }
+
+    // in-memory serialized string: u64 length and chars
+    fn string(&mut self) -> Result<String, String> {
+        let n = self.i64()? as usize;
+        Ok(String::from_utf8_lossy(self.take(n)?).to_string())
+    }
+
+    // xdr string of header: length with null, length and padded chars
+    fn xdr_string(&mut self) -> Result<String, String> {
+        self.i32()?;
+        let n = self.count()?;
+        let s = String::from_utf8_lossy(self.take(n)?).to_string();
+        self.skip((4 - n % 4) % 4)?;
+        Ok(s)
+    }
+
+    fn symstr(&mut self, symtab: &[String]) -> Result<String, String> {
+        let i = self.count()?;
+        symtab.get(i).cloned().ok_or(format!("symbol {} out of range", i))
+    }
+}
+
+pub fn read_tpx(fname: &str) -> Result<TPXData, String> {
+    let buf = fs::read(fname).map_err(|e| format!("cannot read {}: {}", fname, e))?;
+    let mut r = TPXReader { buf, pos: 0, double: false };
+
+    // header, xdr
+    let version = r.xdr_string()?;
+    if !version.starts_with("VERSION") {
+        return Err(format!("{} is not a tpr file", fname));
+    }
+    let precision = r.i32()?;
+    r.double = match precision {
+        4 => false,
+        8 => true,
+        _ => return Err(format!("unknown precision {} of tpr file", precision))
+    };
+    let fver = r.i32()?;
+    if !(TPX_MIN_VERSION..=TPX_MAX_VERSION).contains(&fver) {
+        return Err(format!("tpx version {} ({}) not supported by the native tpr reader", fver, version.trim_start_matches("VERSION ")));
+    }
+    let fgen = r.i32()?;
+    if fgen > TPX_GENERATION {
+        return Err(format!("tpx generation {} not supported by the native tpr reader", fgen));
+    }
+    r.xdr_string()?;                    // file tag
+    let natoms = r.count()?;
+    let ngtc = r.count()?;
+    r.i32()?;                           // fep state
+    r.real()?;                          // lambda
+    let mut present = [false; 6];       // ir, topology, x, v, f, box
+    for p in present.iter_mut() {
+        *p = r.i32()? != 0;
+    }
+    let [has_ir, has_top, has_x, has_v, has_f, has_box] = present;
+    if !has_ir || !has_top || !has_x {
+        return Err("tpr file without inputrec, topology or coordinates".to_string());
+    }
+    r.i64()?;                           // body size
+
+    // state
+    if has_box {
+        r.skip_reals(27)?;              // box, box_rel, boxv
+    }
+    r.skip_reals(ngtc)?;                // obsolete tcoupl lambda
+
+    // topology
+    let n_sym = r.count()?;
+    let mut symtab: Vec<String> = Vec::with_capacity(n_sym);
+    for _ in 0..n_sym {
+        symtab.push(r.string()?);
+    }
+    let name = r.symstr(&symtab)?;
+    let (atnr, lj) = read_ffparams(&mut r, fver)?;
+    let n_moltypes = r.count()?;
+    let mut moltypes: Vec<TPXMolType> = Vec::with_capacity(n_moltypes);
+    for _ in 0..n_moltypes {
+        moltypes.push(read_moltype(&mut r, &symtab, fver)?);
+    }
+    let n_molblocks = r.count()?;
+    let mut molblocks: Vec<(usize, i64)> = Vec::with_capacity(n_molblocks);
+    for _ in 0..n_molblocks {
+        let mol_type = r.count()?;
+        let n_mols = r.count()? as i64;
+        r.i32()?;                       // atoms per molecule
+        let n_posres_a = r.count()?;
+        r.skip_reals(3 * n_posres_a)?;
+        let n_posres_b = r.count()?;
+        r.skip_reals(3 * n_posres_b)?;
+        molblocks.push((mol_type, n_mols));
+    }
+    if r.count()? != natoms {
+        return Err("inconsistent atom number in tpr file".to_string());
+    }
+    if r.bool()? {
+        // intermolecular interactions
+        read_ilists(&mut r, fver)?;
+    }
+    if fver < TPXV_REMOVE_ATOMTYPES {
+        let n = r.count()?;
+        r.skip_ints(n)?;                // atomic numbers
+    }
+    let n_cmap = r.count()?;
+    let grid_spacing = r.count()?;
+    r.skip_reals(n_cmap * grid_spacing * grid_spacing * 4)?;
+    for _ in 0..NR_GROUP_TYPES {
+        let n = r.count()?;
+        r.skip_ints(n)?;
+    }
+    let n_group_names = r.count()?;
+    r.skip_ints(n_group_names)?;
+    for _ in 0..NR_GROUP_TYPES {
+        let n = r.count()?;
+        r.skip(n)?;
+    }
+    if fver >= TPXV_STORE_NONBONDED_INTERACTION_EXCLUSION_GROUP {
+        let n = r.i64()? as usize;
+        r.skip_ints(n)?;
+    }
+
+    // coordinates
+    let mut x: Vec<f64> = Vec::with_capacity(3 * natoms);
+    for _ in 0..3 * natoms {
+        x.push(r.real()?);
+    }
+    if has_v {
+        r.skip_reals(3 * natoms)?;
+    }
+    if has_f {
+        r.skip_reals(3 * natoms)?;
+    }
+
+    // pbc type and periodic molecules
+    r.i32()?;
+    r.bool()?;
+
+    let (dt, nsteps, nstxout, ref_t) = read_inputrec(&mut r, fver)?;
+
+    Ok(TPXData { name, natoms, atnr, lj, moltypes, molblocks, x, dt, nsteps, nstxout, ref_t })
+}
+
+fn read_ffparams(r: &mut TPXReader, fver: i32) -> Result<(usize, Vec<(f64, f64)>), String> {
+    let atnr = r.count()?;
+    let n_types = r.count()?;
+    let mut functypes: Vec<i32> = Vec::with_capacity(n_types);
+    for _ in 0..n_types {
+        let mut ft = r.i32()?;
+        // function types added later than the file
+        if fver < TPXV_VSITE1 && ft >= F_VSITE1 {
+            ft += 1;
+        }
+        functypes.push(ft);
+    }
+    r.f64()?;                           // reppow
+    r.real()?;                          // fudgeQQ
+    let mut lj: Vec<(f64, f64)> = Vec::with_capacity(atnr * atnr);
+    for (i, &ft) in functypes.iter().enumerate() {
+        if i < atnr * atnr {
+            if ft != F_LJ {
+                return Err("only LJ non-bonded interactions supported by the native tpr reader".to_string());
+            }
+            lj.push((r.real()?, r.real()?));
+        } else {
+            let (n_ints, n_reals) = iparams_size(ft, fver)?;
+            r.skip_ints(n_ints)?;
+            r.skip_reals(n_reals)?;
+        }
+    }
+    Ok((atnr, lj))
+}
+
+// numbers of ints and reals of interaction parameters
+fn iparams_size(ft: i32, fver: i32) -> Result<(usize, usize), String> {
+    Ok(match ft {
+        0 | 1 | 5 | 10 | 11 | 24 => (0, 4),            // harmonic
+        2 => (0, 6),                                    // morse
+        3 | 14 | 38 | 51 => (0, 3),
+        4 | 65 => (0, 0),
+        6 | 12 | 21 | 62 | 63 | 64 => (0, 2),
+        7 | 8 | 18 | 26 => (1, 2),                      // tabulated
+        9 | 16 => (0, 8),
+        13 | 15 | 33 | 36 => (0, 4),
+        17 | 22 | 49 | 60 => (0, 6),
+        19 | 25 | 58 | 59 => (1, 4),                    // periodic
+        20 | 23 | 52 => (0, 12),
+        27 => (2, 0),                                   // cmap
+        35 => (0, 5),
+        37 => (0, 2),
+        48 | 66 | 67 => (0, 1),
+        50 => (0, if fver < TPXV_REMOVE_THOLE_RFAC { 4 } else { 3 }),
+        53 => (1, 5),                                   // flat-bottomed posres
+        54 => (2, 4),                                   // disres
+        56 => (3, 3),                                   // orires
+        68..=70 => (0, 2),
+        71..=73 => (0, 3),
+        74 => (1, 1),
+        _ => return Err(format!("interaction function type {} not supported by the native tpr reader", ft))
+    })
+}
+
+fn read_ilists(r: &mut TPXReader, fver: i32) -> Result<Vec<Vec<i32>>, String> {
+    let mut ilists: Vec<Vec<i32>> = vec![vec![]; F_NRE];
+    for (ft, ilist) in ilists.iter_mut().enumerate() {
+        if fver < TPXV_VSITE1 && ft as i32 == F_VSITE1 {
+            continue;
+        }
+        let n = r.count()?;
+        for _ in 0..n {
+            ilist.push(r.i32()?);
+        }
+    }
+    Ok(ilists)
+}
+
+fn read_moltype(r: &mut TPXReader, symtab: &[String], fver: i32) -> Result<TPXMolType, String> {
+    let name = r.symstr(symtab)?;
+    let n_atoms = r.count()?;
+    let n_res = r.count()?;
+    let mut atoms: Vec<TPXAtom> = Vec::with_capacity(n_atoms);
+    for _ in 0..n_atoms {
+        let mass = r.real()?;
+        let charge = r.real()?;
+        r.real()?;                      // mB
+        r.real()?;                      // qB
+        let type_id = r.u16()? as usize;
+        r.u16()?;                       // typeB
+        r.i32()?;                       // ptype
+        let resind = r.count()?;
+        r.i32()?;                       // atomic number
+        atoms.push(TPXAtom { type_id, mass, charge, resind });
+    }
+    let mut atom_names: Vec<String> = Vec::with_capacity(n_atoms);
+    for _ in 0..n_atoms {
+        atom_names.push(r.symstr(symtab)?);
+    }
+    let mut type_names: Vec<String> = Vec::with_capacity(n_atoms);
+    for _ in 0..n_atoms {
+        type_names.push(r.symstr(symtab)?);
+    }
+    for _ in 0..n_atoms {
+        r.symstr(symtab)?;              // typeB names
+    }
+    let mut residues: Vec<(String, i32)> = Vec::with_capacity(n_res);
+    for _ in 0..n_res {
+        let res_name = r.symstr(symtab)?;
+        let nr = r.i32()?;
+        r.u8()?;                        // insertion code
+        residues.push((res_name, nr));
+    }
+    let ilists = read_ilists(r, fver)?;
+    // obsolete charge groups
+    let n_cgs = r.count()?;
+    r.skip_ints(n_cgs + 1)?;
+    // exclusions
+    let n_lists = r.count()?;
+    let n_elements = r.count()?;
+    r.skip_ints(n_lists + 1 + n_elements)?;
+    Ok(TPXMolType { name, atoms, atom_names, type_names, residues, ilists })
+}
+
+// only dt, nsteps, nstxout-compressed and the first ref-t are needed
+fn read_inputrec(r: &mut TPXReader, fver: i32) -> Result<(f64, u64, u32, f64), String> {
+    r.i32()?;                           // integrator
+    let nsteps = r.i64()?;
+    r.i64()?;                           // init-step
+    r.i32()?;                           // simulation-part
+    if fver >= TPXV_MTS && r.bool()? {
+        let n_levels = r.count()?;
+        r.skip_ints(2 * n_levels)?;
+    }
+    if fver >= TPXV_MASS_REPARTITIONING {
+        r.real()?;
+    }
+    if fver >= TPXV_ENSEMBLE_TEMPERATURE {
+        r.i32()?;
+        r.real()?;
+    }
+    // nstcalcenergy, cutoff-scheme, (ns-type), nstlist, (ndelta)
+    r.skip_ints(5)?;
+    r.real()?;                          // rtpi
+    // nstcomm, comm-mode, nstcgsteep, nbfgscorr, nstlog, nstxout, nstvout, nstfout, nstenergy
+    r.skip_ints(9)?;
+    let nstxout = r.i32()?;
+    r.f64()?;                           // tinit
+    let dt = r.f64()?;
+    r.real()?;                          // compressed-x-precision
+    r.real()?;                          // verlet-buffer-tolerance
+    if fver >= TPXV_VERLET_BUFFER_PRESSURE_TOL {
+        r.real()?;
+    }
+    r.real()?;                          // rlist
+    r.i32()?;                           // (nstcalclr)
+    // coulombtype, coulomb-modifier, rcoulomb-switch, rcoulomb, vdwtype, vdw-modifier, rvdw-switch, rvdw
+    r.skip_ints(2)?;
+    r.skip_reals(2)?;
+    r.skip_ints(2)?;
+    r.skip_reals(2)?;
+    r.i32()?;                           // DispCorr
+    r.skip_reals(4)?;                   // epsilon-r, epsilon-rf, table-extension, fourierspacing
+    r.skip_ints(4)?;                    // fourier-nx, ny, nz, pme-order
+    r.skip_reals(2)?;                   // ewald-rtol, ewald-rtol-lj
+    r.i32()?;                           // ewald-geometry
+    r.real()?;                          // epsilon-surface
+    r.i32()?;                           // lj-pme-comb-rule
+    r.bool()?;                          // continuation
+    r.i32()?;                           // tcoupl
+    r.bool()?;                          // print-nose-hoover-chain-variables
+    r.skip_ints(3)?;                    // nsttcouple, pcoupl, pcoupltype
+    r.i32()?;                           // nstpcouple
+    r.skip_reals(1 + 9 + 9)?;           // tau-p, ref-p, compressibility
+    r.i32()?;                           // refcoord-scaling
+    r.skip_reals(6)?;                   // posres-com, posres-comB
+    r.real()?;                          // shake-tol
+    r.i32()?;                           // free-energy
+
+    // free energy
+    r.i32()?;                           // init-lambda-state
+    r.f64()?;                           // init-lambda
+    r.f64()?;                           // delta-lambda
+    let n_lambda = r.count()?;
+    if n_lambda > 0 {
+        for _ in 0..NR_FEP_TYPES {
+            r.skip(8 * n_lambda)?;
+            r.skip(NR_FEP_TYPES)?;
+        }
+    }
+    r.real()?;                          // sc-alpha
+    r.i32()?;                           // sc-power
+    r.skip_reals(2)?;                   // sc-r-power, sc-sigma
+    r.bool()?;                          // sc-coul
+    r.skip_ints(4)?;                    // nstdhdl, separate-dhdl-file, dhdl-derivatives, dh_hist_size
+    r.f64()?;                           // dh_hist_spacing
+    r.i32()?;                           // dhdl-print-energy
+    if fver >= TPXV_SOFTCORE_GAPSYS {
+        r.i32()?;
+        r.skip_reals(3)?;
+    }
+    r.i32()?;                           // calc-lambda-neighbors
+    if r.bool()? || r.bool()? {
+        return Err("simulated tempering or expanded ensemble not supported by the native tpr reader".to_string());
+    }
+
+    r.skip_ints(2)?;                    // disre, disre-weighting
+    r.bool()?;                          // disre-mixed
+    r.skip_reals(2)?;                   // dr-fc, dr-tau
+    r.i32()?;                           // nstdisreout
+    r.skip_reals(2)?;                   // orire-fc, orire-tau
+    r.i32()?;                           // nstorireout
+    r.skip_reals(2)?;                   // emstep, emtol
+    r.bool()?;                          // Shake-SOR
+    r.i32()?;                           // niter
+    r.real()?;                          // fcstep
+    r.skip_ints(2)?;                    // constraint-algorithm, lincs-order
+    r.real()?;                          // lincs-warnangle
+    r.i32()?;                           // lincs-iter
+    r.real()?;                          // bd-fric
+    r.i64()?;                           // ld-seed
+    r.skip_reals(9 + 1)?;               // deform, cos-acceleration
+    r.skip_ints(4)?;                    // userint
+    r.skip_reals(4)?;                   // userreal
+    for opt in ["pull", "awh", "rotation", "interactiveMD"] {
+        if r.bool()? {
+            return Err(format!("{} not supported by the native tpr reader", opt));
+        }
+    }
+
+    // grpopts
+    let ngtc = r.count()?;
+    r.i32()?;                           // nh-chain-length
+    if !(TPXV_REMOVED_CONSTANT_ACCELERATION..TPXV_READDED_CONSTANT_ACCELERATION).contains(&fver) {
+        r.i32()?;                       // ngacc
+    }
+    r.skip_ints(2)?;                    // ngfrz, ngener
+    r.skip_reals(ngtc)?;                // nrdf
+    let mut ref_t = 0.0;
+    for i in 0..ngtc {
+        let t = r.real()?;
+        if i == 0 {
+            ref_t = t;
+        }
+    }
+
+    Ok((dt, nsteps as u64, nstxout as u32, ref_t))
+}
@@ -0,0 +1,398 @@
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek};
+use ndarray::Array3;
+use crate::error::SmError;
+
+// xtc compressed coordinates, see xdrfile.c of GROMACS
+const MAGIC: i32 = 1995;
+const MAGIC_LARGE: i32 = 2023;      // since GROMACS 2023, for > 298261617 atoms
+const FIRST_IDX: usize = 9;
+const MAGIC_INTS: [u32; 73] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 10, 12, 16, 20, 25, 32, 40, 50, 64,
+    80, 101, 128, 161, 203, 256, 322, 406, 512, 645, 812, 1024, 1290,
+    1625, 2048, 2580, 3250, 4096, 5060, 6501, 8192, 10321, 13003,
+    16384, 20642, 26007, 32768, 41285, 52015, 65536, 82570, 104031,
+    131072, 165140, 208063, 262144, 330280, 416127, 524287, 660561,
+    832255, 1048576, 1321122, 1664510, 2097152, 2642245, 3329021,
+    4194304, 5284491, 6658042, 8388607, 10568983, 13316085, 16777216
+];
+
+pub struct XTCReader {
+    reader: BufReader<File>,
+    pub natoms: usize,
+    large: bool,
+    fname: String,
+    error: Option<SmError>,     // reading stops at broken frames, with the reason kept here
+}
+
+impl XTCReader {
+    pub fn open(fname: &str) -> Result<XTCReader, SmError> {
+        let file = File::open(fname).map_err(|e| SmError::io(fname, e))?;
+        let mut reader = XTCReader { reader: BufReader::new(file), natoms: 0, large: false,
+                                     fname: fname.to_string(), error: None };
+        // peek the first header for atom number
+        if let Some((natoms, _, _, _)) = reader.read_header() {
+            reader.natoms = natoms;
+        }
+        reader.check()?;
+        reader.reader.rewind().map_err(|e| SmError::io(fname, e))?;
+        Ok(reader)
+    }
+
+    // the error of the last broken frame, if any
+    pub fn check(&mut self) -> Result<(), SmError> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(())
+        }
+    }
+
+    fn fail<T>(&mut self, msg: &str) -> Option<T> {
+        self.error = Some(SmError::parse(&self.fname, 0, msg));
+        None
+    }
+
+    fn read_header(&mut self) -> Option<(usize, i32, f32, [[f32; 3]; 3])> {
+        let magic = self.read_i32()?;
+        if magic != MAGIC && magic != MAGIC_LARGE {
+            return self.fail(&format!("not a valid xtc file, magic number {} found", magic));
+        }
+        self.large = magic == MAGIC_LARGE;
+        let natoms = self.read_i32()? as usize;
+        let step = self.read_i32()?;
+        let time = self.read_f32()?;
+        let mut box_vec = [[0.0; 3]; 3];
+        for v in box_vec.iter_mut() {
+            for x in v.iter_mut() {
+                *x = self.read_f32()?;
+            }
+        }
+        Some((natoms, step, time, box_vec))
+    }
+
+    fn read_coords(&mut self, natoms: usize) -> Option<Vec<[f32; 3]>> {
+        let lsize = self.read_i32()? as usize;
+        if lsize != natoms {
+            return self.fail(&format!("broken frame with {} atoms in header but {} in coordinates", natoms, lsize));
+        }
+        let mut coords: Vec<[f32; 3]> = Vec::with_capacity(natoms);
+        // few atoms are not compressed
+        if natoms <= 9 {
+            for _ in 0..natoms {
+                coords.push([self.read_f32()?, self.read_f32()?, self.read_f32()?]);
+            }
+            return Some(coords);
+        }
+        let precision = self.read_f32()?;
+        let mut min_int = [0i32; 3];
+        let mut max_int = [0i32; 3];
+        for x in min_int.iter_mut() {
+            *x = self.read_i32()?;
+        }
+        for x in max_int.iter_mut() {
+            *x = self.read_i32()?;
+        }
+        let mut size_int = [0u32; 3];
+        for i in 0..3 {
+            size_int[i] = (max_int[i] as i64 - min_int[i] as i64 + 1) as u32;
+        }
+        let mut bit_size_int = [0u32; 3];
+        let bit_size = if size_int.iter().any(|&s| s > 0xffffff) {
+            for i in 0..3 {
+                bit_size_int[i] = size_of_int(size_int[i]);
+            }
+            0
+        } else {
+            size_of_ints(&size_int)
+        };
+        let mut small_idx = self.read_i32()? as usize;
+        let mut smaller = (MAGIC_INTS[FIRST_IDX.max(small_idx.saturating_sub(1))] / 2) as i32;
+        let mut small_num = (MAGIC_INTS[small_idx] / 2) as i32;
+        let mut size_small = [MAGIC_INTS[small_idx]; 3];
+        let byte_cnt = self.read_byte_cnt()?;
+        let mut buf = vec![0u8; byte_cnt];
+        self.reader.read_exact(&mut buf).ok()?;
+        self.skip_padding(byte_cnt)?;
+
+        let inv_precision = 1.0 / precision;
+        let to_nm = |c: &[i32; 3]| [c[0] as f32 * inv_precision, c[1] as f32 * inv_precision, c[2] as f32 * inv_precision];
+        let mut bits = BitReader::new(&buf);
+        let mut run = 0u32;
+        let mut i = 0;
+        while i < natoms {
+            let mut this_coord = [0i32; 3];
+            if bit_size == 0 {
+                for k in 0..3 {
+                    this_coord[k] = bits.receive_bits(bit_size_int[k]) as i32;
+                }
+            } else {
+                let nums = bits.receive_ints(bit_size, &size_int);
+                for k in 0..3 {
+                    this_coord[k] = nums[k] as i32;
+                }
+            }
+            i += 1;
+            for k in 0..3 {
+                this_coord[k] += min_int[k];
+            }
+            let mut prev_coord = this_coord;
+            let flag = bits.receive_bits(1);
+            let mut is_smaller = 0i32;
+            if flag == 1 {
+                run = bits.receive_bits(5);
+                is_smaller = (run % 3) as i32;
+                run -= is_smaller as u32;
+                is_smaller -= 1;
+            }
+            if run > 0 {
+                for k in (0..run).step_by(3) {
+                    let nums = bits.receive_ints(small_idx as u32, &size_small);
+                    i += 1;
+                    for d in 0..3 {
+                        this_coord[d] = nums[d] as i32 + prev_coord[d] - small_num;
+                    }
+                    if k == 0 {
+                        // first two atoms are interchanged for better compression of water
+                        std::mem::swap(&mut this_coord, &mut prev_coord);
+                        coords.push(to_nm(&prev_coord));
+                    } else {
+                        prev_coord = this_coord;
+                    }
+                    coords.push(to_nm(&this_coord));
+                }
+            } else {
+                coords.push(to_nm(&this_coord));
+            }
+            small_idx = (small_idx as i32 + is_smaller) as usize;
+            if is_smaller < 0 {
+                small_num = smaller;
+                smaller = match small_idx > FIRST_IDX {
+                    true => (MAGIC_INTS[small_idx - 1] / 2) as i32,
+                    false => 0
+                };
+            } else if is_smaller > 0 {
+                smaller = small_num;
+                small_num = (MAGIC_INTS[small_idx] / 2) as i32;
+            }
+            size_small = [MAGIC_INTS[small_idx]; 3];
+        }
+        Some(coords)
+    }
+
+    fn skip_coords(&mut self, natoms: usize) -> Option<()> {
+        self.read_i32()?;
+        let n_bytes = match natoms <= 9 {
+            true => natoms * 12,
+            false => {
+                // precision, min_int, max_int and small_idx
+                self.reader.seek_relative(4 * 8).ok()?;
+                let byte_cnt = self.read_byte_cnt()?;
+                byte_cnt + (4 - byte_cnt % 4) % 4
+            }
+        };
+        self.reader.seek_relative(n_bytes as i64).ok()
+    }
+
+    fn read_byte_cnt(&mut self) -> Option<usize> {
+        match self.large {
+            true => {
+                let mut b = [0u8; 8];
+                self.read_bytes(&mut b)?;
+                Some(u64::from_be_bytes(b) as usize)
+            }
+            false => Some(self.read_i32()? as usize)
+        }
+    }
+
+    fn skip_padding(&mut self, byte_cnt: usize) -> Option<()> {
+        let pad = (4 - byte_cnt % 4) % 4;
+        self.reader.seek_relative(pad as i64).ok()
+    }
+
+    fn read_bytes(&mut self, b: &mut [u8]) -> Option<()> {
+        match self.reader.read_exact(b) {
+            Ok(_) => Some(()),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => {
+                self.error = Some(SmError::io(&self.fname, e));
+                None
+            }
+        }
+    }
+
+    fn read_i32(&mut self) -> Option<i32> {
+        let mut b = [0u8; 4];
+        self.read_bytes(&mut b)?;
+        Some(i32::from_be_bytes(b))
+    }
+
+    fn read_f32(&mut self) -> Option<f32> {
+        let mut b = [0u8; 4];
+        self.read_bytes(&mut b)?;
+        Some(f32::from_be_bytes(b))
+    }
+}
+
+struct BitReader<'a> {
+    buf: &'a [u8],
+    cnt: usize,
+    last_bits: u32,
+    last_byte: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(buf: &'a [u8]) -> BitReader<'a> {
+        BitReader { buf, cnt: 0, last_bits: 0, last_byte: 0 }
+    }
+
+    fn next_byte(&mut self) -> u32 {
+        // truncated buffer reads as zero, the same as xdrfile
+        let b = self.buf.get(self.cnt).cloned().unwrap_or(0) as u32;
+        self.cnt += 1;
+        b
+    }
+
+    fn receive_bits(&mut self, mut n_bits: u32) -> u32 {
+        let mask = match n_bits >= 32 {
+            true => u32::MAX,
+            false => (1u32 << n_bits) - 1
+        };
+        let mut num = 0u32;
+        while n_bits >= 8 {
+            self.last_byte = (self.last_byte << 8) | self.next_byte();
+            num |= (self.last_byte >> self.last_bits) << (n_bits - 8);
+            n_bits -= 8;
+        }
+        if n_bits > 0 {
+            if self.last_bits < n_bits {
+                self.last_bits += 8;
+                self.last_byte = (self.last_byte << 8) | self.next_byte();
+            }
+            self.last_bits -= n_bits;
+            num |= (self.last_byte >> self.last_bits) & ((1u32 << n_bits) - 1);
+        }
+        num & mask
+    }
+
+    // decode 3 ints packed in n_bits with the given sizes
+    fn receive_ints(&mut self, mut n_bits: u32, sizes: &[u32; 3]) -> [u32; 3] {
+        let mut bytes = [0u32; 32];
+        let mut n_bytes = 0;
+        while n_bits > 8 {
+            bytes[n_bytes] = self.receive_bits(8);
+            n_bytes += 1;
+            n_bits -= 8;
+        }
+        if n_bits > 0 {
+            bytes[n_bytes] = self.receive_bits(n_bits);
+            n_bytes += 1;
+        }
+        let mut nums = [0u32; 3];
+        for i in (1..3).rev() {
+            let mut num = 0u64;
+            for j in (0..n_bytes).rev() {
+                num = (num << 8) | bytes[j] as u64;
+                let p = num / sizes[i] as u64;
+                bytes[j] = p as u32;
+                num -= p * sizes[i] as u64;
+            }
+            nums[i] = num as u32;
+        }
+        nums[0] = bytes[0] | (bytes[1] << 8) | (bytes[2] << 16) | (bytes[3] << 24);
+        nums
+    }
+}
+
+fn size_of_int(size: u32) -> u32 {
+    let mut num = 1u64;
+    let mut n_bits = 0;
+    while size as u64 >= num && n_bits < 32 {
+        n_bits += 1;
+        num <<= 1;
+    }
+    n_bits
+}
+
+fn size_of_ints(sizes: &[u32; 3]) -> u32 {
+    let mut bytes = [0u32; 32];
+    bytes[0] = 1;
+    let mut n_bytes = 1;
+    for &size in sizes {
+        let mut tmp = 0u32;
+        for b in bytes.iter_mut().take(n_bytes) {
+            tmp += *b * size;
+            *b = tmp & 0xff;
+            tmp >>= 8;
+        }
+        while tmp != 0 {
+            bytes[n_bytes] = tmp & 0xff;
+            n_bytes += 1;
+            tmp >>= 8;
+        }
+    }
+    let mut num = 1;
+    let mut n_bits = 0;
+    n_bytes -= 1;
+    while bytes[n_bytes] >= num {
+        n_bits += 1;
+        num *= 2;
+    }
+    n_bits + n_bytes as u32 * 8
+}
+
+// times (ps), coordinates and box vectors of frames
+pub type XtcFrames = (Vec<f64>, Array3<f64>, Array3<f64>);
+
+// read frames within bt-et (ps) every dt (ps, 0 means every frame), only atoms in `atoms` (all if None) kept
+// coordinates and box vectors are in A as read_coord_xvg
+pub fn read_xtc(fname: &str, atoms: Option<&[usize]>, bt: f64, et: f64, dt: f64) -> Result<XtcFrames, SmError> {
+    let mut xtc = XTCReader::open(fname)?;
+    if let Some(atoms) = atoms {
+        if let Some(&max_id) = atoms.iter().max() {
+            if max_id >= xtc.natoms {
+                return Err(SmError::Input(format!("Atom {} selected but only {} atoms in {}", max_id + 1, xtc.natoms, fname)));
+            }
+        }
+    }
+    let mut time_list: Vec<f64> = vec![];
+    let mut coordinates: Vec<f64> = vec![];
+    let mut boxes: Vec<f64> = vec![];
+    let mut t_ref: Option<f64> = None;
+    while let Some(header) = xtc.read_header() {
+        let t = header.2 as f64;
+        let tol = 1e-4 * t.abs().max(1.0);
+        if t > et + tol {
+            break;
+        }
+        let mut keep = t >= bt - tol && t <= et + tol;
+        if keep && dt > 0.0 {
+            // the same as trjconv -dt, counted from the first frame in time range
+            let t0 = *t_ref.get_or_insert(t);
+            keep = (t - t0 - ((t - t0) / dt).round() * dt).abs() <= tol;
+        }
+        if !keep {
+            match xtc.skip_coords(header.0) {
+                Some(_) => continue,
+                None => break
+            }
+        }
+        let coords = match xtc.read_coords(header.0) {
+            Some(c) => c,
+            None => break       // truncated last frame
+        };
+        match atoms {
+            Some(atoms) => atoms.iter().for_each(|&i| coordinates.extend(coords[i].iter().map(|&x| x as f64 * 10.0))),
+            None => coords.iter().for_each(|c| coordinates.extend(c.iter().map(|&x| x as f64 * 10.0)))
+        }
+        header.3.iter().for_each(|v| boxes.extend(v.iter().map(|&x| x as f64 * 10.0)));
+        time_list.push(t);
+    }
+    xtc.check()?;
+    if time_list.is_empty() {
+        return Err(SmError::Input(format!("No frames found in {} between {} and {} ps", fname, bt, et)));
+    }
+    let n_frames = time_list.len();
+    let n_atoms = coordinates.len() / n_frames / 3;
+    Ok((time_list, Array3::from_shape_vec((n_frames, n_atoms, 3), coordinates).unwrap(),
+        Array3::from_shape_vec((n_frames, 3, 3), boxes).unwrap()))
+}
@@ -1,23 +1,36 @@
 use std::fs;
 use ndarray::Array3;
+use crate::error::SmError;
 
-pub fn read_coord_xvg(coord_fname: &str) -> (Vec<f64>, Array3<f64>) {
-    let xvg = fs::read_to_string(coord_fname).unwrap();
-    let xvg: Vec<&str> = xvg.split("\n").filter_map(|s| 
-        if !s.is_empty() && !s.trim().starts_with("@") && !s.trim().starts_with("#") { 
-            Some(s)
+pub fn read_coord_xvg(coord_fname: &str) -> Result<(Vec<f64>, Array3<f64>), SmError> {
+    let xvg = fs::read_to_string(coord_fname).map_err(|e| SmError::io(coord_fname, e))?;
+    let xvg: Vec<(usize, &str)> = xvg.split("\n").enumerate().filter_map(|(l, s)|
+        if !s.trim().is_empty() && !s.trim().starts_with("@") && !s.trim().starts_with("#") {
+            Some((l + 1, s))
         } else {
             None
         }
     ).collect();
+    if xvg.is_empty() {
+        return Err(SmError::parse(coord_fname, 0, "no coordinates found"));
+    }
     let mut time_list = vec![];
     let mut coordinates = vec![];
-    for coord in &xvg {
+    let mut n_values = 0;
+    for &(l, coord) in &xvg {
         let mut coord_ts: Vec<f64> = coord.split_whitespace()
-            .filter_map(|s| s.parse().ok())
-            .collect();
+            .map(|s| s.parse().map_err(|_| SmError::parse(coord_fname, l, &format!("invalid value {}", s))))
+            .collect::<Result<_, _>>()?;
+        // time and xyz of each atom, the same length in all frames
+        if n_values == 0 {
+            n_values = coord_ts.len();
+        }
+        if coord_ts.len() != n_values || n_values % 3 != 1 {
+            return Err(SmError::parse(coord_fname, l, &format!("{} values found, expected time and xyz of {} atoms",
+                                                                coord_ts.len(), n_values / 3)));
+        }
         time_list.push(coord_ts.remove(0));
         coordinates.append(&mut coord_ts);
     }
-    (time_list, Array3::from_shape_vec((xvg.len(), coordinates.len() / xvg.len() / 3, 3), coordinates).unwrap() * 10.0)
-}
\ No newline at end of file
+    Ok((time_list, Array3::from_shape_vec((xvg.len(), n_values / 3, 3), coordinates).unwrap() * 10.0))
+}
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use ndarray::{Array3, ArrayView2, ArrayViewMut2, Axis};
+
+// remove PBC of complex in each frame: make molecules whole by bonds, put fragments (chains, ligand)
+// together by minimum image and center the complex in box, works for triclinic box
+// coordinates and boxes in A, ndx_lig are ligand atom indexes in coordinates
+pub fn fix_pbc(coordinates: &mut Array3<f64>, boxes: &Array3<f64>, bonds: &[[usize; 2]], ndx_lig: &[usize]) {
+    let n_atoms = coordinates.shape()[1];
+    let mut neighbors: Vec<Vec<usize>> = vec![vec![]; n_atoms];
+    for &[i, j] in bonds {
+        neighbors[i].push(j);
+        neighbors[j].push(i);
+    }
+    let mut is_lig = vec![false; n_atoms];
+    ndx_lig.iter().for_each(|&i| is_lig[i] = true);
+
+    // fragments as (atom, parent atom) in bond graph traversal order, receptor first then bigger first
+    let mut fragments: Vec<Vec<(usize, usize)>> = vec![];
+    let mut visited = vec![false; n_atoms];
+    for root in 0..n_atoms {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        let mut fragment: Vec<(usize, usize)> = vec![(root, root)];
+        let mut queue: VecDeque<usize> = VecDeque::from([root]);
+        while let Some(i) = queue.pop_front() {
+            for &j in &neighbors[i] {
+                if !visited[j] {
+                    visited[j] = true;
+                    fragment.push((j, i));
+                    queue.push_back(j);
+                }
+            }
+        }
+        fragments.push(fragment);
+    }
+    fragments.sort_by_key(|f| (is_lig[f[0].0], usize::MAX - f.len()));
+
+    for (mut frame, bx) in coordinates.axis_iter_mut(Axis(0)).zip(boxes.axis_iter(Axis(0))) {
+        let mut placed: Vec<usize> = vec![];
+        for fragment in &fragments {
+            // make whole
+            for &(i, p) in &fragment[1..] {
+                let x = add(get(&frame, p), min_image(sub(get(&frame, i), get(&frame, p)), &bx));
+                set(&mut frame, i, x);
+            }
+            // put fragment center to the nearest image of placed atoms
+            let center = fragment.iter().fold([0.0; 3], |c, &(i, _)| add(c, get(&frame, i)))
+                .map(|c| c / fragment.len() as f64);
+            let nearest = placed.iter().map(|&i| {
+                let d = min_image(sub(center, get(&frame, i)), &bx);
+                (d.iter().map(|x| x * x).sum::<f64>(), add(get(&frame, i), d))
+            }).min_by(|a, b| a.0.total_cmp(&b.0));
+            if let Some((_, image)) = nearest {
+                let shift = sub(image, center);
+                fragment.iter().for_each(|&(i, _)| translate(&mut frame, i, shift));
+            }
+            placed.extend(fragment.iter().map(|&(i, _)| i));
+        }
+        // center complex in box
+        let center = (0..n_atoms).fold([0.0; 3], |c, i| add(c, get(&frame, i))).map(|c| c / n_atoms as f64);
+        let box_center = (0..3).fold([0.0; 3], |c, i| add(c, [bx[[i, 0]], bx[[i, 1]], bx[[i, 2]]])).map(|c| c / 2.0);
+        let shift = sub(box_center, center);
+        (0..n_atoms).for_each(|i| translate(&mut frame, i, shift));
+    }
+}
+
+// box vectors in rows, lower triangular as gromacs
+fn min_image(mut d: [f64; 3], bx: &ArrayView2<f64>) -> [f64; 3] {
+    for i in (0..3).rev() {
+        if bx[[i, i]] > 0.0 {
+            let n = (d[i] / bx[[i, i]]).round();
+            for (k, x) in d.iter_mut().enumerate() {
+                *x -= n * bx[[i, k]];
+            }
+        }
+    }
+    d
+}
+
+fn get(frame: &ArrayViewMut2<f64>, i: usize) -> [f64; 3] {
+    [frame[[i, 0]], frame[[i, 1]], frame[[i, 2]]]
+}
+
+fn set(frame: &mut ArrayViewMut2<f64>, i: usize, x: [f64; 3]) {
+    for (k, x) in x.iter().enumerate() {
+        frame[[i, k]] = *x;
+    }
+}
+
+fn translate(frame: &mut ArrayViewMut2<f64>, i: usize, d: [f64; 3]) {
+    for (k, d) in d.iter().enumerate() {
+        frame[[i, k]] += d;
+    }
+}
+
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
@@ -1,25 +1,29 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use ndarray::{Array1, ArrayView2};
 use crate::apbs_param::*;
 use crate::atom_property::AtomProperties;
 use crate::settings::Settings;
+use crate::error::SmError;
 
-pub fn prepare_pqr(cur_frm: usize, time_list: &Vec<f64>,
-                   temp_dir: &Path, sys_name: &String, coord: &ArrayView2<f64>,
+pub fn prepare_pqr(temp_dir: &Path, f_name: &str, coord: &ArrayView2<f64>,
                    ndx_rec_norm: &Vec<usize>, ndx_lig_norm: &Vec<usize>,
-                   aps: &AtomProperties) {
-    let f_name = format!("{}_{}ns", sys_name, time_list[cur_frm]);
+                   aps: &AtomProperties) -> Result<(), SmError> {
+    let create = |mol: &str| -> Result<(String, File), SmError> {
+        let path = temp_dir.join(format!("{}_{}.pqr", f_name, mol)).display().to_string();
+        let f = File::create(&path).map_err(|e| SmError::io(&path, e))?;
+        Ok((path, f))
+    };
     let mut pqr_com = match ndx_lig_norm[0] != ndx_rec_norm[0] {
-        true => Some(File::create(&temp_dir.join(format!("{}_com.pqr", f_name))).unwrap()),
+        true => Some(create("com")?),
         false => None
     };
     let mut pqr_lig = match ndx_lig_norm[0] != ndx_rec_norm[0] {
-        true => Some(File::create(&temp_dir.join(format!("{}_lig.pqr", f_name))).unwrap()),
+        true => Some(create("lig")?),
         false => None
     };
-    let mut pqr_rec = File::create(&temp_dir.join(format!("{}_rec.pqr", f_name))).unwrap();
+    let (rec_path, mut pqr_rec) = create("rec")?;
     
     // loop atoms and write pqr information (from pqr)
     for at_id in 0..aps.atom_props.len() {
@@ -37,33 +41,33 @@ pub fn prepare_pqr(cur_frm: usize, time_list: &Vec<f64>,
 
         // write qrv files
         // if has ligand
-        if let Some(pqr_com) = &mut pqr_com {
-            pqr_com.write_all(atom_line.as_bytes()).unwrap();
+        if let Some((path, pqr_com)) = &mut pqr_com {
+            pqr_com.write_all(atom_line.as_bytes()).map_err(|e| SmError::io(path, e))?;
         }
-        if let Some(pqr_lig) = &mut pqr_lig {
+        if let Some((path, pqr_lig)) = &mut pqr_lig {
             if ndx_lig_norm.contains(&at_id) {
-                pqr_lig.write_all(atom_line.as_bytes()).unwrap();
+                pqr_lig.write_all(atom_line.as_bytes()).map_err(|e| SmError::io(path, e))?;
             }
         }
         if ndx_rec_norm.contains(&at_id) {
-            pqr_rec.write_all(atom_line.as_bytes()).unwrap();
+            pqr_rec.write_all(atom_line.as_bytes()).map_err(|e| SmError::io(&rec_path, e))?;
         }
     }
+    Ok(())
 }
 
 pub fn write_apbs_input(ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, coord: &ArrayView2<f64>,
                   atm_radius: &Array1<f64>, pbe_set: &PBESet, pba_set: &PBASet,
-                  temp_dir: &PathBuf, f_name: &String, settings: &Settings) {
-    let mut input_apbs = File::create(temp_dir.join(format!("{}.apbs", f_name))).unwrap();
-    writeln!(input_apbs, "read").expect("Failed writing apbs file.");
+                  temp_dir: &PathBuf, f_name: &String, settings: &Settings) -> Result<(), SmError> {
+    let mut input_apbs = String::from("read\n");
     if ndx_lig[0] != ndx_rec[0] {
-        writeln!(input_apbs, "  mol pqr {}_com.pqr", f_name).expect("Failed writing apbs file.");
+        input_apbs.push_str(&format!("  mol pqr {}_com.pqr\n", f_name));
     }
-    writeln!(input_apbs, "  mol pqr {}_rec.pqr", f_name).expect("Failed writing apbs file.");
+    input_apbs.push_str(&format!("  mol pqr {}_rec.pqr\n", f_name));
     if ndx_lig[0] != ndx_rec[0] {
-        writeln!(input_apbs, "  mol pqr {}_lig.pqr", f_name).expect("Failed writing apbs file.");
+        input_apbs.push_str(&format!("  mol pqr {}_lig.pqr\n", f_name));
     }
-    writeln!(input_apbs, "end\n").expect("Failed writing apbs file.");
+    input_apbs.push_str("end\n\n");
     
     let (rec_box, lig_box, com_box) =
         gen_mesh_params(ndx_rec, ndx_lig, coord, atm_radius);
@@ -72,40 +76,38 @@ pub fn write_apbs_input(ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, coord: &Arra
     pbe_set0.sdie = 1.0;
 
     if ndx_lig[0] != ndx_rec[0] {
-        input_apbs.write_all(dim_apbs(format!("{}_com", f_name).as_str(), 1,
+        input_apbs.push_str(&dim_apbs(format!("{}_com", f_name).as_str(), 1,
                                     com_box[0], com_box[3], 
                                     com_box[1], com_box[4], 
                                     com_box[2], com_box[5],
                                     settings,
-                                    pbe_set, &pbe_set0, pba_set).as_bytes()).
-            expect("Failed writing apbs file.");
+                                    pbe_set, &pbe_set0, pba_set));
     }
     if ndx_lig[0] != ndx_rec[0] {
-        input_apbs.write_all(dim_apbs(format!("{}_rec", f_name).as_str(), 2,
+        input_apbs.push_str(&dim_apbs(format!("{}_rec", f_name).as_str(), 2,
                                     rec_box[0], rec_box[3], 
                                     rec_box[1], rec_box[4], 
                                     rec_box[2], rec_box[5],
                                     settings,
-                                    pbe_set, &pbe_set0, pba_set).as_bytes()).
-            expect("Failed writing apbs file.");
+                                    pbe_set, &pbe_set0, pba_set));
     } else {
-        input_apbs.write_all(dim_apbs(format!("{}_rec", f_name).as_str(), 1,
+        input_apbs.push_str(&dim_apbs(format!("{}_rec", f_name).as_str(), 1,
                                     rec_box[0], rec_box[3], 
                                     rec_box[1], rec_box[4], 
                                     rec_box[2], rec_box[5],
                                     settings,
-                                    pbe_set, &pbe_set0, pba_set).as_bytes()).
-            expect("Failed writing apbs file.");
+                                    pbe_set, &pbe_set0, pba_set));
     }
     if ndx_lig[0] != ndx_rec[0] {
-        input_apbs.write_all(dim_apbs(format!("{}_lig", f_name).as_str(), 3,
+        input_apbs.push_str(&dim_apbs(format!("{}_lig", f_name).as_str(), 3,
                                     lig_box[0], lig_box[3], 
                                     lig_box[1], lig_box[4], 
                                     lig_box[2], lig_box[5],
                                     settings,
-                                    pbe_set, &pbe_set0, pba_set).as_bytes()).
-            expect("Failed writing apbs file.");
+                                    pbe_set, &pbe_set0, pba_set));
     }
+    let path = temp_dir.join(format!("{}.apbs", f_name));
+    fs::write(&path, input_apbs).map_err(|e| SmError::io(&path.display().to_string(), e))
 }
 
 fn get_lb(ndx: &Vec<usize>, axis: usize, coord: &ArrayView2<f64>, atm_radius: &Array1<f64>) -> f64 {
@@ -150,8 +152,9 @@ pub fn gen_mesh_params(ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, coord: &Array
     return (rec_box, lig_box, com_box);
 }
 
-pub fn dim_apbs(file: &str, mol_index: i32, min_x: f64, max_x: f64, min_y: f64, max_y: f64, min_z: f64, max_z: f64,
-                settings: &Settings, pbe_set: &PBESet, pbe_set0: &PBESet, pba_set: &PBASet) -> String {
+// grid points, coarse and fine grid lengths and center of a box, shared by apbs and built-in kernel
+pub fn mesh_params(min_x: f64, max_x: f64, min_y: f64, max_y: f64, min_z: f64, max_z: f64,
+                   settings: &Settings) -> ([i32; 3], [f64; 3], [f64; 3], [f64; 3]) {
     let cfac = settings.cfac;
     let fadd = settings.fadd;
     let df = settings.df;
@@ -176,6 +179,14 @@ pub fn dim_apbs(file: &str, mol_index: i32, min_x: f64, max_x: f64, min_y: f64,
     let n_y = ((f_y / df / t).round() * t) as i32 + 1;
     let n_z = ((f_z / df / t).round() * t) as i32 + 1;
 
+    ([n_x, n_y, n_z], [c_x, c_y, c_z], [f_x, f_y, f_z], [x_center, y_center, z_center])
+}
+
+pub fn dim_apbs(file: &str, mol_index: i32, min_x: f64, max_x: f64, min_y: f64, max_y: f64, min_z: f64, max_z: f64,
+                settings: &Settings, pbe_set: &PBESet, pbe_set0: &PBESet, pba_set: &PBASet) -> String {
+    let ([n_x, n_y, n_z], [c_x, c_y, c_z], [f_x, f_y, f_z], [x_center, y_center, z_center]) =
+        mesh_params(min_x, max_x, min_y, max_y, min_z, max_z, settings);
+
     let mg_set = "mg-auto";
 
     let xyz_set = format!("  {mg_set}\n  mol    {mol_index:7}\
@@ -185,7 +196,7 @@ pub fn dim_apbs(file: &str, mol_index: i32, min_x: f64, max_x: f64, min_y: f64,
         \n  fgcent {x_center:7.3}  {y_center:7.3}  {z_center:7.3}\
         \n  cgcent {x_center:7.3}  {y_center:7.3}  {z_center:7.3}\n");
 
-    return format!("\nELEC name {}_SOL\n\
+    format!("\nELEC name {}_SOL\n\
     {}\n\
     {}\n\
     end\n\n\
@@ -198,6 +209,6 @@ pub fn dim_apbs(file: &str, mol_index: i32, min_x: f64, max_x: f64, min_y: f64,
     end\n\n\
     print elecEnergy {}_SOL - {}_VAC end\n\
     print apolEnergy {}_SAS end\n\n", file, xyz_set, pbe_set.to_string(), file,
-                   xyz_set, pbe_set0.to_string(), file, mol_index,
-                   pba_set.to_string(), file, file, file);
+            xyz_set, pbe_set0.to_string(), file, mol_index,
+            pba_set.to_string(), file, file, file)
 }
\ No newline at end of file
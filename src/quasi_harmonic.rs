@@ -0,0 +1,233 @@
+// Quasi-harmonic configurational entropy from the mass-weighted covariance of fitted coordinates
+use ndarray::{s, Array2, Array3};
+
+const KB: f64 = 1.380649e-23;               // J/K
+const HBAR: f64 = 1.054571817e-34;          // J·s
+const R: f64 = 8.314462618;                 // J/mol/K
+const AMU_A2: f64 = 1.66053906660e-47;      // amu·Å² to kg·m²
+const FIT_ITERS: usize = 3;
+
+// S (J/mol/K) of the atoms over the frames (Andricioaei & Karplus), overall translation and rotation removed by fitting
+pub fn qh_entropy(coord: &Array3<f64>, ts_ids: &[usize], atoms: &[usize], masses: &[f64], temperature: f64) -> f64 {
+    let w: Vec<f64> = atoms.iter().map(|&a| masses[a]).collect();
+    let mut frames: Vec<Array2<f64>> = ts_ids.iter().map(|&t| {
+        let mut x: Array2<f64> = Array2::zeros((atoms.len(), 3));
+        for (i, &a) in atoms.iter().enumerate() {
+            x.row_mut(i).assign(&coord.slice(s![t, a, ..]));
+        }
+        centered(x, &w)
+    }).collect();
+
+    // fit to the average structure, refined from the first frame
+    let mut avg = frames[0].to_owned();
+    for _ in 0..FIT_ITERS {
+        for x in frames.iter_mut() {
+            *x = fit(x, &avg, &w);
+        }
+        avg = frames.iter().fold(Array2::zeros((atoms.len(), 3)), |acc, x| acc + x) / frames.len() as f64;
+    }
+
+    // eigenvalues of the covariance are those of the frames x frames Gram matrix, cheaper for large systems
+    let m = frames.len();
+    let mut y: Array2<f64> = Array2::zeros((m, atoms.len() * 3));
+    for (x, mut r) in frames.iter().zip(y.rows_mut()) {
+        let d = x - &avg;
+        for (i, a) in d.rows().into_iter().enumerate() {
+            for k in 0..3 {
+                r[i * 3 + k] = a[k] * w[i].sqrt();
+            }
+        }
+    }
+    let gram: Array2<f64> = y.dot(&y.t()) / m as f64;
+    let eigval = symmetric_eigenvalues(gram);
+    let max_val = eigval.iter().cloned().fold(0.0, f64::max);
+
+    // quantum harmonic oscillators with ω = sqrt(kT/λ)
+    let kt = KB * temperature;
+    eigval.iter().filter(|&&l| l > max_val * 1e-10).map(|&l| {
+        let a = HBAR / (kt * l * AMU_A2).sqrt();
+        a / a.exp_m1() - (-(-a).exp_m1()).ln()
+    }).sum::<f64>() * R
+}
+
+fn centered(x: Array2<f64>, w: &[f64]) -> Array2<f64> {
+    let total: f64 = w.iter().sum();
+    let mut com = [0.0; 3];
+    for (i, r) in x.rows().into_iter().enumerate() {
+        for d in 0..3 {
+            com[d] += r[d] * w[i] / total;
+        }
+    }
+    let mut x = x;
+    for mut r in x.rows_mut() {
+        for d in 0..3 {
+            r[d] -= com[d];
+        }
+    }
+    x
+}
+
+// rotate centered x onto centered reference by the quaternion method (Horn)
+fn fit(x: &Array2<f64>, reference: &Array2<f64>, w: &[f64]) -> Array2<f64> {
+    let mut c = [[0.0; 3]; 3];
+    for (i, (a, b)) in x.rows().into_iter().zip(reference.rows()).enumerate() {
+        for p in 0..3 {
+            for q in 0..3 {
+                c[p][q] += w[i] * a[p] * b[q];
+            }
+        }
+    }
+    let [[sxx, sxy, sxz], [syx, syy, syz], [szx, szy, szz]] = c;
+    let n = Array2::from_shape_vec((4, 4), vec![
+        sxx + syy + szz, syz - szy, szx - sxz, sxy - syx,
+        syz - szy, sxx - syy - szz, sxy + syx, szx + sxz,
+        szx - sxz, sxy + syx, -sxx + syy - szz, syz + szy,
+        sxy - syx, szx + sxz, syz + szy, -sxx - syy + szz,
+    ]).unwrap();
+    let (eigval, eigvec) = jacobi_eigen(n);
+    let k = (0..4).fold(0, |k, i| if eigval[i] > eigval[k] { i } else { k });
+    let (q0, q1, q2, q3) = (eigvec[[0, k]], eigvec[[1, k]], eigvec[[2, k]], eigvec[[3, k]]);
+    let rot = Array2::from_shape_vec((3, 3), vec![
+        q0 * q0 + q1 * q1 - q2 * q2 - q3 * q3, 2.0 * (q1 * q2 - q0 * q3), 2.0 * (q1 * q3 + q0 * q2),
+        2.0 * (q1 * q2 + q0 * q3), q0 * q0 - q1 * q1 + q2 * q2 - q3 * q3, 2.0 * (q2 * q3 - q0 * q1),
+        2.0 * (q1 * q3 - q0 * q2), 2.0 * (q2 * q3 + q0 * q1), q0 * q0 - q1 * q1 - q2 * q2 + q3 * q3,
+    ]).unwrap();
+    x.dot(&rot.t())
+}
+
+// eigenvalues of a symmetric matrix by Householder reduction to tridiagonal form and implicit QL (tred2 and tqli),
+// O(n^3) in total instead of per sweep of Jacobi rotations
+fn symmetric_eigenvalues(a: Array2<f64>) -> Vec<f64> {
+    let n = a.nrows();
+    let mut a: Vec<Vec<f64>> = a.rows().into_iter().map(|r| r.to_vec()).collect();
+    let mut d = vec![0.0; n];
+    let mut e = vec![0.0; n];
+    for i in (1..n).rev() {
+        let l = i - 1;
+        let scale: f64 = a[i][..=l].iter().map(|x| x.abs()).sum();
+        if l == 0 || scale == 0.0 {
+            e[i] = a[i][l];
+            continue;
+        }
+        a[i][..=l].iter_mut().for_each(|x| *x /= scale);
+        let mut h: f64 = a[i][..=l].iter().map(|x| x * x).sum();
+        let f = a[i][l];
+        let g = match f >= 0.0 {
+            true => -h.sqrt(),
+            false => h.sqrt()
+        };
+        e[i] = scale * g;
+        h -= f * g;
+        a[i][l] = f - g;
+        let mut f = 0.0;
+        for j in 0..=l {
+            let g = a[j][..=j].iter().zip(&a[i][..=j]).map(|(x, y)| x * y).sum::<f64>()
+                + (j + 1..=l).map(|k| a[k][j] * a[i][k]).sum::<f64>();
+            e[j] = g / h;
+            f += e[j] * a[i][j];
+        }
+        let hh = f / (h + h);
+        let u = a[i][..=l].to_vec();
+        for j in 0..=l {
+            let f = u[j];
+            e[j] -= hh * f;
+            let g = e[j];
+            for k in 0..=j {
+                a[j][k] -= f * e[k] + g * u[k];
+            }
+        }
+    }
+    for i in 0..n {
+        d[i] = a[i][i];
+    }
+
+    // QL with implicit shifts on the diagonal d and subdiagonal e
+    if n > 0 {
+        e.rotate_left(1);
+        e[n - 1] = 0.0;
+    }
+    for l in 0..n {
+        for _ in 0..30 {
+            let m = (l..n - 1).find(|&m| e[m].abs() <= f64::EPSILON * (d[m].abs() + d[m + 1].abs())).unwrap_or(n - 1);
+            if m == l {
+                break;
+            }
+            let mut g = (d[l + 1] - d[l]) / (2.0 * e[l]);
+            let mut r = g.hypot(1.0);
+            g = d[m] - d[l] + e[l] / (g + r.copysign(g));
+            let (mut s, mut c, mut p) = (1.0, 1.0, 0.0);
+            let mut underflow = false;
+            for i in (l..m).rev() {
+                let f = s * e[i];
+                let b = c * e[i];
+                r = f.hypot(g);
+                e[i + 1] = r;
+                if r == 0.0 {
+                    d[i + 1] -= p;
+                    e[m] = 0.0;
+                    underflow = true;
+                    break;
+                }
+                s = f / r;
+                c = g / r;
+                g = d[i + 1] - p;
+                r = (d[i] - g) * s + 2.0 * c * b;
+                p = s * r;
+                d[i + 1] = g + p;
+                g = c * r - b;
+            }
+            if underflow {
+                continue;
+            }
+            d[l] -= p;
+            e[l] = g;
+            e[m] = 0.0;
+        }
+    }
+    d
+}
+
+// eigenvalues and eigenvectors (columns) of a symmetric matrix by cyclic Jacobi rotations
+fn jacobi_eigen(mut a: Array2<f64>) -> (Vec<f64>, Array2<f64>) {
+    let n = a.nrows();
+    let mut v: Array2<f64> = Array2::eye(n);
+    let scale: f64 = a.iter().map(|x| x * x).sum();
+    for _ in 0..100 {
+        let mut off = 0.0;
+        for p in 0..n {
+            for q in p + 1..n {
+                off += a[[p, q]] * a[[p, q]];
+            }
+        }
+        if off <= scale * 1e-24 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[[p, q]] == 0.0 {
+                    continue;
+                }
+                let theta = (a[[q, q]] - a[[p, p]]) / (2.0 * a[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[[k, p]], a[[k, q]]);
+                    a[[k, p]] = c * akp - s * akq;
+                    a[[k, q]] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * apk - s * aqk;
+                    a[[q, k]] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[[k, p]], v[[k, q]]);
+                    v[[k, p]] = c * vkp - s * vkq;
+                    v[[k, q]] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[[i, i]]).collect(), v)
+}
@@ -0,0 +1,175 @@
+// Results of a system in one versioned file: a YAML header followed by the gzip compressed WT and mutant results,
+// with coordinates kept once for WT and only the changed atoms of mutants
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use ndarray::{s, Array3, Axis};
+use serde::{Deserialize, Serialize};
+use crate::analyzation::SMResult;
+use crate::apbs_param::{PBASet, PBESet};
+use crate::atom_radius::RADIUS_TYPES;
+use crate::error::SmError;
+use crate::settings::Settings;
+
+const MAGIC: &[u8; 8] = b"SMMPBSA\n";
+// 0: pickle of a single SMResult per file, written by earlier versions; 1: this container
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub format_version: u32,
+    pub program_version: String,
+    pub parameters: BTreeMap<String, String>,
+    pub input_hashes: BTreeMap<String, String>,
+}
+
+impl Header {
+    // hashes of trajectory and topology, as given by checkpoint::system_hashes
+    pub fn new(pbe_set: &PBESet, pba_set: &PBASet, settings: &Settings, hashes: (u64, u64)) -> Header {
+        let parameters = BTreeMap::from([
+            ("radius_type".to_string(), RADIUS_TYPES[settings.radius_type].to_string()),
+            ("r_cutoff".to_string(), settings.r_cutoff.to_string()),
+            ("screen_method".to_string(), settings.elec_screen.to_string()),
+            ("pbsa_kernel".to_string(), settings.pbsa_kernel.clone().unwrap_or_default()),
+            ("cfac".to_string(), settings.cfac.to_string()),
+            ("fadd".to_string(), settings.fadd.to_string()),
+            ("df".to_string(), settings.df.to_string()),
+            ("pb_settings".to_string(), pbe_set.to_string()),
+            ("sa_settings".to_string(), format!("{}\nsurften {}\nsurfoff {}", pba_set, pba_set.surften, pba_set.surfoff)),
+        ]);
+        let input_hashes = BTreeMap::from([
+            ("trajectory".to_string(), format!("{:016x}", hashes.0)),
+            ("topology".to_string(), format!("{:016x}", hashes.1)),
+        ]);
+        Header { format_version: FORMAT_VERSION, program_version: env!("CARGO_PKG_VERSION").to_string(), parameters, input_hashes }
+    }
+
+    // results of format 0 have no record of program, parameters or inputs
+    fn legacy() -> Header {
+        Header { format_version: 0, program_version: "unknown".to_string(), parameters: BTreeMap::new(), input_hashes: BTreeMap::new() }
+    }
+}
+
+// coordinates of a mutant are taken from WT by atom_map, except the moved atoms
+#[derive(Serialize, Deserialize)]
+struct StoredResult {
+    result: SMResult,
+    atom_map: Option<Vec<usize>>,       // None if coordinates kept in result
+    moved: Vec<usize>,
+    moved_coord: Array3<f64>,
+}
+
+// atom_maps gives the WT atom of each atom of the mutants, their coordinates are kept in full if not given
+pub fn save_results(target: &Path, header: &Header, result_wt: &SMResult, result_mutations: &[SMResult],
+                    atom_maps: &[Vec<usize>]) -> Result<(), SmError> {
+    println!("Saving results to {}", target.display());
+    let target_name = target.display().to_string();
+    let mut stored = vec![StoredResult { result: result_wt.clone(), atom_map: None, moved: vec![], moved_coord: Array3::zeros((0, 0, 0)) }];
+    for (k, result) in result_mutations.iter().enumerate() {
+        let mut result = result.clone();
+        match atom_maps.get(k) {
+            Some(atom_map) => {
+                let moved: Vec<usize> = atom_map.iter().enumerate()
+                    .filter(|&(j, &i)| result.coord.index_axis(Axis(1), j) != result_wt.coord.index_axis(Axis(1), i))
+                    .map(|(j, _)| j).collect();
+                let moved_coord = result.coord.select(Axis(1), &moved);
+                result.coord = Array3::zeros((0, 0, 0));
+                stored.push(StoredResult { result, atom_map: Some(atom_map.to_vec()), moved, moved_coord });
+            },
+            None => stored.push(StoredResult { result, atom_map: None, moved: vec![], moved_coord: Array3::zeros((0, 0, 0)) })
+        }
+    }
+
+    // written to a temporary file first, so that an interrupted saving never breaks the former results
+    let header = Header { format_version: FORMAT_VERSION, ..header.clone() };
+    let header = serde_yaml::to_string(&header).map_err(|e| SmError::Input(format!("Cannot save results to {}: {}", target_name, e)))?;
+    let tmp_file = target.with_extension("sm.tmp");
+    let tmp_name = tmp_file.display().to_string();
+    let mut f = BufWriter::new(File::create(&tmp_file).map_err(|e| SmError::io(&tmp_name, e))?);
+    f.write_all(MAGIC).map_err(|e| SmError::io(&tmp_name, e))?;
+    f.write_all(&(header.len() as u32).to_le_bytes()).map_err(|e| SmError::io(&tmp_name, e))?;
+    f.write_all(header.as_bytes()).map_err(|e| SmError::io(&tmp_name, e))?;
+    let mut gz = GzEncoder::new(f, Compression::default());
+    serde_pickle::to_writer(&mut gz, &stored, serde_pickle::SerOptions::new())
+        .map_err(|e| SmError::Input(format!("Cannot save results to {}: {}", target_name, e)))?;
+    gz.finish().and_then(|mut f| f.flush()).map_err(|e| SmError::io(&tmp_name, e))?;
+    fs::rename(&tmp_file, target).map_err(|e| SmError::io(&target_name, e))
+}
+
+// header and results, WT first, of a result file; a format 0 file gives its only result
+pub fn load_results(path: &str) -> Result<(Header, Vec<SMResult>), SmError> {
+    let mut f = BufReader::new(File::open(path).map_err(|e| SmError::io(path, e))?);
+    let mut magic = [0u8; 8];
+    if f.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Ok((Header::legacy(), vec![SMResult::from(path)?]));
+    }
+    let mut len = [0u8; 4];
+    f.read_exact(&mut len).map_err(|e| SmError::io(path, e))?;
+    let mut header = vec![0u8; u32::from_le_bytes(len) as usize];
+    f.read_exact(&mut header).map_err(|e| SmError::io(path, e))?;
+    let header: Header = serde_yaml::from_slice(&header).map_err(|e| SmError::parse(path, 0, &e.to_string()))?;
+
+    // migrate here when the stored layout of a new format version changes
+    let stored: Vec<StoredResult> = match header.format_version {
+        1 => serde_pickle::from_reader(GzDecoder::new(f), serde_pickle::DeOptions::new())
+            .map_err(|e| SmError::parse(path, 0, &e.to_string()))?,
+        v => return Err(SmError::Input(format!("{} is of result format {}, newer than {} supported by s_mmpbsa {}.",
+                                               path, v, FORMAT_VERSION, env!("CARGO_PKG_VERSION"))))
+    };
+    let wt_coord = match stored.first() {
+        Some(wt) => wt.result.coord.to_owned(),
+        None => return Err(SmError::parse(path, 0, "no results in file"))
+    };
+    let results = stored.into_iter().map(|st| {
+        let mut result = st.result;
+        if let Some(atom_map) = st.atom_map {
+            let mut coord = wt_coord.select(Axis(1), &atom_map);
+            for (k, &j) in st.moved.iter().enumerate() {
+                coord.slice_mut(s![.., j, ..]).assign(&st.moved_coord.slice(s![.., k, ..]));
+            }
+            result.coord = coord;
+        }
+        result
+    }).collect();
+    Ok((header, results))
+}
+
+// WT and mutant results of the system in wd, the format 0 files of WT and each mutant migrated to one file
+pub fn load_system(wd: &Path, sys_name: &str) -> Result<(SMResult, Vec<SMResult>), SmError> {
+    let sm_file = wd.join(format!("_MMPBSA_{}.sm", sys_name));
+    if sm_file.is_file() {
+        let (header, mut results) = load_results(&sm_file.display().to_string())?;
+        println!("Results written by s_mmpbsa {} in format {}.", header.program_version, header.format_version);
+        let result_wt = results.remove(0);
+        return Ok((result_wt, results));
+    }
+
+    // _MMPBSA_<system>_WT.sm and _MMPBSA_<system>-<mutation>_<mutation>.sm, not the files of another system
+    // named <system>-<suffix>, nor result files of the current format
+    let wt_name = format!("_MMPBSA_{}_WT.sm", sys_name);
+    let prefix = format!("_MMPBSA_{}-", sys_name);
+    // the mutation label itself may have "_", e.g. del_1-5_8, so split in the middle
+    let is_mutant = |n: &str| n.strip_prefix(&prefix).and_then(|n| n.strip_suffix(".sm")).map(|n| n.as_bytes())
+        .is_some_and(|n| n.len() % 2 == 1 && n[n.len() / 2] == b'_' && n[..n.len() / 2] == n[n.len() / 2 + 1..]);
+    let is_legacy = |f: &Path| {
+        let mut magic = [0u8; 8];
+        File::open(f).and_then(|mut f| f.read_exact(&mut magic)).is_err() || &magic != MAGIC
+    };
+    let mut files: Vec<PathBuf> = fs::read_dir(wd).map_err(|e| SmError::io(&wd.display().to_string(), e))?
+        .filter_map(|f| f.ok().map(|f| f.path()))
+        .filter(|f| f.file_name().and_then(|n| n.to_str()).is_some_and(|n| n == wt_name || is_mutant(n)) && is_legacy(f))
+        .collect();
+    files.sort();
+    let wt = files.iter().position(|f| f.ends_with(&wt_name))
+        .ok_or_else(|| SmError::Input(format!("The required _MMPBSA_{}.sm or {} file not found.", sys_name, wt_name)))?;
+    let wt_file = files.remove(wt);
+    let result_wt = SMResult::from(&wt_file.display().to_string())?;
+    let result_mutations: Vec<SMResult> = files.iter().map(|f| SMResult::from(&f.display().to_string())).collect::<Result<_, _>>()?;
+    save_results(&sm_file, &Header::legacy(), &result_wt, &result_mutations, &[])?;
+    println!("Migrated {} result files of format 0 to {}, the old files are kept.", files.len() + 1, sm_file.display());
+    Ok((result_wt, result_mutations))
+}
@@ -0,0 +1,88 @@
+use std::f64::consts::PI;
+use ndarray::{Array1, ArrayView2};
+use rayon::prelude::*;
+use crate::apbs_param::PBASet;
+use crate::atom_property::AtomProperties;
+use crate::neighbor::cell_list_pairs;
+
+// Per-atom SA energies (kJ/mol) of com - rec - lig, or of rec if no ligand, by Shrake-Rupley SASA
+// energy of each atom is surften * area + surfoff / atoms number, same as the APBS results
+pub fn calc_sa(ndx_rec: &Vec<usize>, ndx_lig: &Vec<usize>, coord: &ArrayView2<f64>, aps: &AtomProperties,
+               pba_set: &PBASet) -> Array1<f64> {
+    // solvent accessible radii
+    let radii: Vec<f64> = aps.atom_props.iter().map(|a| a.radius + pba_set.srad).collect();
+    let mut de_sa: Array1<f64> = Array1::zeros(aps.atom_props.len());
+    if ndx_lig[0] != ndx_rec[0] {
+        let ndx_com: Vec<usize> = (0..aps.atom_props.len()).collect();
+        for (i, e) in ndx_com.iter().zip(sa_energy(&ndx_com, coord, &radii, pba_set)) {
+            de_sa[*i] += e;
+        }
+        for ndx in [ndx_rec, ndx_lig] {
+            for (i, e) in ndx.iter().zip(sa_energy(ndx, coord, &radii, pba_set)) {
+                de_sa[*i] -= e;
+            }
+        }
+    } else {
+        for (i, e) in ndx_rec.iter().zip(sa_energy(ndx_rec, coord, &radii, pba_set)) {
+            de_sa[*i] += e;
+        }
+    }
+    de_sa
+}
+
+fn sa_energy(ndx: &[usize], coord: &ArrayView2<f64>, radii: &[f64], pba_set: &PBASet) -> Vec<f64> {
+    atom_areas(ndx, coord, radii, pba_set.sdens).iter()
+        .map(|a| pba_set.surften * a + pba_set.surfoff / ndx.len() as f64).collect()
+}
+
+// SASA (A^2) of each atom in ndx, by the fraction of sphere points not buried by neighbors
+// sdens points per A^2 on each sphere, as APBS
+fn atom_areas(ndx: &[usize], coord: &ArrayView2<f64>, radii: &[f64], sdens: f64) -> Vec<f64> {
+    let max_r = ndx.iter().map(|&i| radii[i]).fold(0.0, f64::max);
+    let mut neighbors: Vec<Vec<usize>> = vec![vec![]; coord.nrows()];
+    if max_r > 0.0 {
+        cell_list_pairs(coord, ndx, ndx, 2.0 * max_r, |i, j, r| {
+            if r < radii[i] + radii[j] {
+                neighbors[i].push(j);
+                neighbors[j].push(i);
+            }
+        });
+    }
+    ndx.par_iter().map(|&i| {
+        let r = radii[i];
+        let total = 4.0 * PI * r * r;
+        let n_points = (total * sdens).ceil().max(1.0) as usize;
+        let x = [coord[[i, 0]], coord[[i, 1]], coord[[i, 2]]];
+        // the last burying neighbor is usually also burying the next point
+        let mut last = 0;
+        let exposed = sphere_points(n_points).filter(|p| {
+            let s = [0, 1, 2].map(|d| x[d] + r * p[d]);
+            let buried = |j: usize| {
+                (s[0] - coord[[j, 0]]).powi(2) + (s[1] - coord[[j, 1]]).powi(2)
+                    + (s[2] - coord[[j, 2]]).powi(2) < radii[j] * radii[j]
+            };
+            if last < neighbors[i].len() && buried(neighbors[i][last]) {
+                return false;
+            }
+            match neighbors[i].iter().position(|&j| buried(j)) {
+                Some(k) => {
+                    last = k;
+                    false
+                }
+                None => true
+            }
+        }).count();
+        total * exposed as f64 / n_points as f64
+    }).collect()
+}
+
+// nearly uniform points on unit sphere by golden spiral
+fn sphere_points(n: usize) -> impl Iterator<Item = [f64; 3]> {
+    let golden_angle = PI * (3.0 - 5f64.sqrt());
+    (0..n).map(move |k| {
+        let z = 1.0 - (2.0 * k as f64 + 1.0) / n as f64;
+        let rho = (1.0 - z * z).sqrt();
+        let phi = golden_angle * k as f64;
+        [rho * phi.cos(), rho * phi.sin(), z]
+    })
+}
@@ -4,16 +4,22 @@ use std::str::FromStr;
 use regex::Regex;
 use toml::Value;
 
+#[derive(Clone)]
 pub struct Settings {
     pub radius_type: usize,
     pub radius_ff_default: f64,
     pub r_cutoff: f64,
     pub fix_pbc: bool,
     pub elec_screen: usize,
+    pub entropy_method: usize,
+    pub pairwise: usize,
+    pub hot_spot: f64,
+    pub warm_spot: f64,
     pub gmx_path: Option<String>,
     pub cfac: f64,
     pub fadd: f64,
     pub df: f64,
+    pub apbs_retries: usize,
     pub pbsa_kernel: Option<String>,
     pub apbs_path: Option<String>,
     pub delphi_path: Option<String>,
@@ -25,6 +31,9 @@ pub struct Settings {
     pub gaussian_exe: Option<String>,
     pub multiwfn_dir: Option<String>,
     pub nkernels: i32,
+    pub parallel_frames: usize,
+    pub resume: bool,
+    pub skip_failed: bool,
     pub debug_mode: bool,
     pub last_opened: String,
 }
@@ -33,6 +42,10 @@ impl Settings {
     pub fn new() -> Settings {
         Settings {
             elec_screen: 1,
+            entropy_method: 0,
+            pairwise: 0,
+            hot_spot: 8.368,
+            warm_spot: 4.184,
             radius_type: 3,
             radius_ff_default: 1.5,
             r_cutoff: 0.0,
@@ -41,6 +54,7 @@ impl Settings {
             cfac: 3.0,
             fadd: 10.0,
             df: 0.5,
+            apbs_retries: 1,
             pbsa_kernel: None,
             apbs_path: None,
             delphi_path: None,
@@ -52,6 +66,9 @@ impl Settings {
             sobtop_path: None,
             multiwfn_dir: None,
             nkernels: 1,
+            parallel_frames: 1,
+            resume: false,
+            skip_failed: false,
             debug_mode: false,
             last_opened: String::new(),
         }
@@ -66,6 +83,10 @@ impl Settings {
         // Read settings
         let elec_screen = parse_param(&setting_values, "screen_method", default_settings.elec_screen);
         let radius_type = parse_param(&setting_values, "radius_type", default_settings.radius_type);
+        let entropy_method = parse_param(&setting_values, "entropy_method", default_settings.entropy_method).min(1);
+        let pairwise = parse_param(&setting_values, "pairwise", default_settings.pairwise).min(2);
+        let hot_spot = parse_param(&setting_values, "hot_spot", default_settings.hot_spot);
+        let warm_spot = parse_param(&setting_values, "warm_spot", default_settings.warm_spot);
         let radius_ff_default = parse_param(&setting_values, "radius_default", default_settings.radius_ff_default);
         let r_cutoff = parse_param(&setting_values, "r_cutoff", default_settings.r_cutoff);
         let r_cutoff = if r_cutoff == 0.0 {
@@ -84,8 +105,9 @@ impl Settings {
         let cfac = parse_param(&setting_values, "cfac", default_settings.cfac);
         let fadd = parse_param(&setting_values, "fadd", default_settings.fadd);
         let df = parse_param(&setting_values, "df", default_settings.df);
+        let apbs_retries = parse_param(&setting_values, "apbs_retries", default_settings.apbs_retries);
         let pbsa_kernel = parse_param(&setting_values, "pbsa_kernel", "".to_string());
-        let pbsa_kernel = Some(pbsa_kernel.trim_start_matches('\"').trim_end_matches('\"').to_string());
+        let pbsa_kernel = Some(pbsa_kernel.trim_start_matches('\"').trim_end_matches('\"').to_string()).filter(|k| !k.is_empty());
         let apbs_path = parse_param(&setting_values, "apbs_path", "".to_string());
         let apbs_path = Some(apbs_path.trim_start_matches('\"').trim_end_matches('\"').to_string());
         let delphi_path = parse_param(&setting_values, "delphi_path", "".to_string());
@@ -104,6 +126,11 @@ impl Settings {
         let multiwfn_dir = parse_param(&setting_values, "multiwfn_dir", "".to_string());
         let multiwfn_dir = Some(multiwfn_dir.trim_start_matches('\"').trim_end_matches('\"').to_string());
         let nkernels = parse_param(&setting_values, "n_kernels", default_settings.nkernels);
+        let parallel_frames = parse_param(&setting_values, "parallel_frames", default_settings.parallel_frames);
+        let resume = parse_param(&setting_values, "resume", "\"n\"".to_string());
+        let resume = matches!(&resume[1..2], "y" | "Y");
+        let skip_failed = parse_param(&setting_values, "skip_failed", "\"n\"".to_string());
+        let skip_failed = matches!(&skip_failed[1..2], "y" | "Y");
         let debug_mode = parse_param(&setting_values, "debug_mode", "\"y\"".to_string());
         let debug_mode = match debug_mode[1..2].to_string().as_str() {
             "y" => true,
@@ -117,6 +144,10 @@ impl Settings {
 
         Settings {
             elec_screen,
+            entropy_method,
+            pairwise,
+            hot_spot,
+            warm_spot,
             radius_type,
             radius_ff_default,
             r_cutoff,
@@ -125,6 +156,7 @@ impl Settings {
             cfac,
             fadd,
             df,
+            apbs_retries,
             pbsa_kernel,
             apbs_path,
             delphi_path,
@@ -136,6 +168,9 @@ impl Settings {
             gaussian_exe,
             multiwfn_dir,
             nkernels,
+            parallel_frames,
+            resume,
+            skip_failed,
             debug_mode,
             last_opened,
         }
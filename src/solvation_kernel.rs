@@ -0,0 +1,296 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use ndarray::{Array1, ArrayView2, Axis};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use crate::apbs_param::{PBASet, PBESet};
+use crate::atom_property::AtomProperties;
+use crate::builtin_pb::calc_pb;
+use crate::delphi::Delphi;
+use crate::gb::{calc_gb, GBModel, GB_MODELS};
+use crate::prepare_apbs::{prepare_pqr, write_apbs_input};
+use crate::sasa::calc_sa;
+use crate::settings::Settings;
+use crate::error::SmError;
+
+// one frame of a system to be calculated by solvation kernels
+pub struct Frame<'a> {
+    pub coord: ArrayView2<'a, f64>,
+    pub ndx_rec: &'a Vec<usize>,
+    pub ndx_lig: &'a Vec<usize>,
+    pub aps: &'a AtomProperties,
+    pub temp_dir: &'a PathBuf,      // own directory of the frame
+    pub f_name: String,             // {sys_name}_{time}ns
+    pub warn: &'a (dyn Fn(&str) + Sync),    // warnings of the kernel, e.g. printed without breaking a progress bar
+}
+
+// Backend of solvation energies, gives per-atom polar (PB) and nonpolar (SA) energies (kJ/mol)
+// of com - rec - lig, or of rec if no ligand
+pub trait SolvationKernel: Sync {
+    // write input files of the frame to its directory, pqr files of com, rec and lig by default
+    fn prepare(&self, frame: &Frame) -> Result<(), SmError> {
+        prepare_pqr(frame.temp_dir, &frame.f_name, &frame.coord, frame.ndx_rec, frame.ndx_lig, frame.aps)
+    }
+
+    fn run(&self, frame: &Frame) -> Result<(Array1<f64>, Array1<f64>), SmError>;
+}
+
+// error if the program of the selected kernel is not available
+pub fn check_kernel(settings: &Settings) -> Result<(), SmError> {
+    match settings.pbsa_kernel.as_deref() {
+        Some("apbs") if settings.apbs_path.is_none() =>
+            Err(SmError::Input("PBSA kernel apbs selected but APBS not found, set apbs_path or choose another kernel".to_string())),
+        Some("delphi") if settings.delphi_path.is_none() =>
+            Err(SmError::Input("PBSA kernel delphi selected but DelPhi not found, set delphi_path or choose another kernel".to_string())),
+        _ => Ok(())
+    }
+}
+
+// kernel selected by settings.pbsa_kernel, without PBSA if no kernel selected
+pub fn get_kernel<'a>(pbe_set: &'a PBESet, pba_set: &'a PBASet, settings: &'a Settings) -> Result<Box<dyn SolvationKernel + 'a>, SmError> {
+    check_kernel(settings)?;
+    // kernels shared by concurrent frames
+    let n_threads = (settings.nkernels.max(1) as usize / settings.parallel_frames.max(1)).max(1);
+    Ok(match (settings.pbsa_kernel.as_deref(), &settings.apbs_path, &settings.delphi_path) {
+        (Some("apbs"), Some(program), _) => Box::new(Apbs { program, pbe_set, pba_set, n_threads, settings }),
+        (Some("delphi"), _, Some(program)) => Box::new(Delphi::new(program, pbe_set, pba_set, n_threads, settings)),
+        (Some("builtin"), _, _) => {
+            if pbe_set.pb_solver.ne("lpbe") {
+                println!("Note: built-in PB kernel solves lpbe instead of {}.", pbe_set.pb_solver);
+            }
+            Box::new(BuiltinPB { pbe_set, pba_set, n_threads, settings })
+        }
+        (Some(model), _, _) if GB_MODELS.contains(&model) => Box::new(GB { model: GBModel::new(model), pbe_set, pba_set, n_threads }),
+        _ => Box::new(NoKernel)
+    })
+}
+
+// zero solvation energies
+pub struct NoKernel;
+
+impl SolvationKernel for NoKernel {
+    fn prepare(&self, _frame: &Frame) -> Result<(), SmError> {
+        Ok(())
+    }
+
+    fn run(&self, frame: &Frame) -> Result<(Array1<f64>, Array1<f64>), SmError> {
+        Ok((Array1::zeros(frame.aps.atom_props.len()), Array1::zeros(frame.aps.atom_props.len())))
+    }
+}
+
+pub struct Apbs<'a> {
+    program: &'a str,
+    pbe_set: &'a PBESet,
+    pba_set: &'a PBASet,
+    n_threads: usize,
+    settings: &'a Settings,
+}
+
+impl SolvationKernel for Apbs<'_> {
+    // frames failed by apbs retried with coarser and larger fine grid, not those failed to write files
+    fn run(&self, frame: &Frame) -> Result<(Array1<f64>, Array1<f64>), SmError> {
+        let mut settings = self.settings.clone();
+        let mut retries = 0;
+        loop {
+            match self.run_apbs(frame, &settings) {
+                Err(e) if !matches!(e, SmError::Io { .. }) && retries < self.settings.apbs_retries => {
+                    retries += 1;
+                    settings.df *= 1.5;
+                    settings.fadd *= 2.0;
+                    (frame.warn)(&format!("Warning: {}\nRetrying {} with df = {} A, fadd = {} A ({}/{})...",
+                                          e, frame.f_name, settings.df, settings.fadd, retries, self.settings.apbs_retries));
+                }
+                result => return result
+            }
+        }
+    }
+}
+
+impl Apbs<'_> {
+    fn run_apbs(&self, frame: &Frame, settings: &Settings) -> Result<(Array1<f64>, Array1<f64>), SmError> {
+        let (coord, ndx_rec_norm, ndx_lig_norm, aps) = (&frame.coord, frame.ndx_rec, frame.ndx_lig, frame.aps);
+        let (temp_dir, f_name) = (frame.temp_dir, &frame.f_name);
+        let (apbs, pbe_set, pba_set, n_threads) = (self.program, self.pbe_set, self.pba_set, self.n_threads);
+        // the default gamma parameter for apbs calculation is set to 1, in order to directly obtain the surface area
+        // then the SA energy term is calculated by s_mmpbsa with surften and surfoff
+        let gamma = pba_set.surften;
+        let bias = pba_set.surfoff;
+        write_apbs_input(ndx_rec_norm, ndx_lig_norm, coord, &Array1::from_iter(aps.atom_props.iter().map(|a| a.radius)),
+                pbe_set, pba_set, temp_dir, &f_name, settings)?;
+        // invoke apbs program to do apbs calculations
+        let apbs_result = Command::new(apbs).arg(format!("{}.apbs", f_name)).current_dir(temp_dir)
+            .env("OMP_NUM_THREADS", n_threads.to_string()).output().map_err(|e| SmError::io(apbs, e))?;
+        let status = apbs_result.status;
+        let apbs_err = String::from_utf8_lossy(&apbs_result.stderr).to_string();
+        let apbs_result = String::from_utf8_lossy(&apbs_result.stdout).to_string();
+        if settings.debug_mode {
+            for (ext, content) in [("out", &apbs_result), ("err", &apbs_err)] {
+                let path = temp_dir.join(format!("{}.{}", f_name, ext));
+                fs::write(&path, content).map_err(|e| SmError::io(&path.display().to_string(), e))?;
+            }
+        }
+        if !status.success() {
+            return Err(SmError::program(&format!("apbs of {}", f_name), &apbs_err));
+        }
+        let out_name = format!("apbs output of {}", f_name);
+        let parse_block = |lines: &[&str]| -> Result<Vec<f64>, SmError> {
+            lines.par_iter().map(|&p| parse_apbs_line(p).ok_or_else(|| SmError::parse(&out_name, 0, &format!("invalid line {}", p))))
+                .collect()
+        };
+        // let apbs_result = fs::read_to_string(temp_dir.join(format!("{}.out", f_name))).expect("Failed to parse apbs output.");
+
+        // preserve CALCULATION, Atom and SASA lines
+        let apbs_result: Vec<&str> = apbs_result.split("\n").filter_map(|p|
+            if p.trim().starts_with("CALCULATION") || p.trim().starts_with("Atom") || p.trim().starts_with("SASA") {
+                Some(p.trim())
+            } else {
+                None
+            }
+        ).collect();
+
+        // extract apbs results
+        let indexes: Vec<usize> = apbs_result.iter().enumerate().filter_map(|(i, &p)| match p.starts_with("CAL") {
+            true => Some(i),
+            false => None
+        }).collect();
+
+        let mut com_pb_sol: Vec<f64> = vec![];
+        let mut com_pb_vac: Vec<f64> = vec![];
+        let mut rec_pb_sol: Vec<f64> = vec![];
+        let mut rec_pb_vac: Vec<f64> = vec![];
+        let mut lig_pb_sol: Vec<f64> = vec![];
+        let mut lig_pb_vac: Vec<f64> = vec![];
+        let mut com_sa: Vec<f64> = vec![];
+        let mut rec_sa: Vec<f64> = vec![];
+        let mut lig_sa: Vec<f64> = vec![];
+
+        let mut skip_pb = true;     // the first time PB calculation should be wasted
+        for (i, &idx) in indexes.iter().enumerate() {
+            let st = idx + 1;
+            let ed = match indexes.get(i + 1) {
+                Some(&idx) => idx,
+                None => apbs_result.len()
+            };
+            if apbs_result[idx].contains(&"_com_SOL") {
+                if !skip_pb {
+                    com_pb_sol = parse_block(&apbs_result[st..ed])?;
+                }
+                skip_pb = !skip_pb;
+            } else if apbs_result[idx].contains(&"_com_VAC") {
+                if !skip_pb {
+                    com_pb_vac = parse_block(&apbs_result[st..ed])?;
+                }
+                skip_pb = !skip_pb;
+            } else if apbs_result[idx].contains(&"_rec_SOL") {
+                if !skip_pb {
+                    rec_pb_sol = parse_block(&apbs_result[st..ed])?;
+                }
+                skip_pb = !skip_pb;
+            } else if apbs_result[idx].contains(&"_rec_VAC") {
+                if !skip_pb {
+                    rec_pb_vac = parse_block(&apbs_result[st..ed])?;
+                }
+                skip_pb = !skip_pb;
+            } else if apbs_result[idx].contains(&"_lig_SOL") {
+                if !skip_pb {
+                    lig_pb_sol = parse_block(&apbs_result[st..ed])?;
+                }
+                skip_pb = !skip_pb;
+            } else if apbs_result[idx].contains(&"_lig_VAC") {
+                if !skip_pb {
+                    lig_pb_vac = parse_block(&apbs_result[st..ed])?;
+                }
+                skip_pb = !skip_pb;
+            } else if apbs_result[idx].contains(&"_com_SAS") {
+                com_sa = parse_block(&apbs_result[st..ed])?;
+            } else if apbs_result[idx].contains(&"_rec_SAS") {
+                rec_sa = parse_block(&apbs_result[st..ed])?;
+            } else if apbs_result[idx].contains(&"_lig_SAS") {
+                lig_sa = parse_block(&apbs_result[st..ed])?;
+            }
+        }
+
+        // every calculation should give all atoms of the molecule, com and lig not calculated without ligand
+        let (n_rec, n_lig) = (ndx_rec_norm.len(), ndx_lig_norm.len());
+        let expected = match ndx_rec_norm[0] == ndx_lig_norm[0] {
+            true => vec![(&rec_pb_sol, n_rec), (&rec_pb_vac, n_rec), (&rec_sa, n_rec)],
+            false => vec![(&com_pb_sol, n_rec + n_lig), (&com_pb_vac, n_rec + n_lig), (&com_sa, n_rec + n_lig),
+                          (&rec_pb_sol, n_rec), (&rec_pb_vac, n_rec), (&rec_sa, n_rec),
+                          (&lig_pb_sol, n_lig), (&lig_pb_vac, n_lig), (&lig_sa, n_lig)]
+        };
+        for (values, n) in expected {
+            if values.len() != n {
+                return Err(SmError::parse(&out_name, 0, &format!("{} atoms found in results, expected {}", values.len(), n)));
+            }
+        }
+
+        let com_pb: Array1<f64> = Array1::from_vec(com_pb_sol) - Array1::from_vec(com_pb_vac);
+        let com_sa: Array1<f64> = Array1::from_vec(com_sa.par_iter().map(|i| gamma * *i + bias / com_sa.len() as f64).collect());
+        let mut rec_pb: Array1<f64> = Array1::from_vec(rec_pb_sol) - Array1::from_vec(rec_pb_vac);
+        let mut rec_sa: Array1<f64> = Array1::from_vec(rec_sa.par_iter().map(|i| gamma * *i + bias / rec_sa.len() as f64).collect());
+        let mut lig_pb: Array1<f64> = Array1::from_vec(lig_pb_sol) - Array1::from_vec(lig_pb_vac);
+        let mut lig_sa: Array1<f64> = Array1::from_vec(lig_sa.par_iter().map(|i| gamma * *i + bias / lig_sa.len() as f64).collect());
+
+        if ndx_rec_norm[0] < ndx_lig_norm[0] {
+            rec_pb.append(Axis(0), lig_pb.view()).unwrap();
+            rec_sa.append(Axis(0), lig_sa.view()).unwrap();
+            Ok((com_pb - rec_pb, com_sa - rec_sa))
+        } else if ndx_rec_norm[0] > ndx_lig_norm[0] {
+            lig_pb.append(Axis(0), rec_pb.view()).unwrap();
+            lig_sa.append(Axis(0), rec_sa.view()).unwrap();
+            Ok((com_pb - lig_pb, com_sa - lig_sa))
+        } else {
+            Ok((rec_pb, rec_sa))
+        }
+    }
+}
+
+fn parse_apbs_line(line: &str) -> Option<f64> {
+    line.split(":")
+        .skip(1)
+        .next()?
+        .trim_start()
+        .split(" ")
+        .next()?
+        .parse().ok()
+}
+
+// built-in linearized PB, with built-in SASA
+pub struct BuiltinPB<'a> {
+    pbe_set: &'a PBESet,
+    pba_set: &'a PBASet,
+    n_threads: usize,
+    settings: &'a Settings,
+}
+
+impl SolvationKernel for BuiltinPB<'_> {
+    fn run(&self, frame: &Frame) -> Result<(Array1<f64>, Array1<f64>), SmError> {
+        let pool = ThreadPoolBuilder::new().num_threads(self.n_threads).build().unwrap();
+        let de_pb = pool.install(|| calc_pb(frame.ndx_rec, frame.ndx_lig, &frame.coord, frame.aps, self.pbe_set, self.settings));
+        let de_sa = pool.install(|| calc_sa(frame.ndx_rec, frame.ndx_lig, &frame.coord, frame.aps, self.pba_set));
+        Ok((de_pb, de_sa))
+    }
+}
+
+// generalized Born, with built-in SASA
+pub struct GB<'a> {
+    model: GBModel<'a>,
+    pbe_set: &'a PBESet,
+    pba_set: &'a PBASet,
+    n_threads: usize,
+}
+
+impl SolvationKernel for GB<'_> {
+    // coordinates used directly
+    fn prepare(&self, _frame: &Frame) -> Result<(), SmError> {
+        Ok(())
+    }
+
+    fn run(&self, frame: &Frame) -> Result<(Array1<f64>, Array1<f64>), SmError> {
+        let pool = ThreadPoolBuilder::new().num_threads(self.n_threads).build().unwrap();
+        let de_gb = pool.install(|| calc_gb(frame.ndx_rec, frame.ndx_lig, &frame.coord, frame.aps, &self.model, self.pbe_set));
+        let de_sa = pool.install(|| calc_sa(frame.ndx_rec, frame.ndx_lig, &frame.coord, frame.aps, self.pba_set));
+        Ok((de_gb, de_sa))
+    }
+}
@@ -0,0 +1,110 @@
+// Error estimates of energy terms over trajectory frames
+
+const N_BOOT: usize = 1000;
+const SEED: u64 = 20221017;
+
+// mean (or other estimator) with standard deviation, standard error and 95% bootstrap confidence interval
+pub struct Stats {
+    pub value: f64,
+    pub sd: Option<f64>,        // only for per-frame terms
+    pub sem: f64,
+    pub ci: [f64; 2],
+}
+
+impl Stats {
+    // per-frame term: SEM by block averaging, CI of the mean by bootstrap
+    pub fn from_series(x: &[f64], samples: &[Vec<usize>]) -> Stats {
+        let boot: Vec<f64> = samples.iter().map(|ids| ids.iter().map(|&i| x[i]).sum::<f64>() / ids.len() as f64).collect();
+        Stats { value: mean(x), sd: Some(std_dev(x)), sem: block_sem(x), ci: percentile_ci(boot) }
+    }
+
+    // estimator over all frames, e.g. interaction entropy: SEM as the deviation of bootstrap estimates
+    pub fn from_estimator<F: Fn(&[usize]) -> f64>(n: usize, samples: &[Vec<usize>], f: F) -> Stats {
+        let boot: Vec<f64> = samples.iter().map(|ids| f(ids)).collect();
+        let all: Vec<usize> = (0..n).collect();
+        Stats { value: f(&all), sd: None, sem: std_dev(&boot), ci: percentile_ci(boot) }
+    }
+}
+
+pub fn mean(x: &[f64]) -> f64 {
+    x.iter().sum::<f64>() / x.len() as f64
+}
+
+pub fn std_dev(x: &[f64]) -> f64 {
+    if x.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(x);
+    (x.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (x.len() - 1) as f64).sqrt()
+}
+
+// standard error corrected for autocorrelation, the largest estimate of successive block halving (Flyvbjerg & Petersen)
+pub fn block_sem(x: &[f64]) -> f64 {
+    if x.len() < 2 {
+        return 0.0;
+    }
+    let mut blocks = x.to_vec();
+    let mut sem = std_dev(&blocks) / (blocks.len() as f64).sqrt();
+    // at least 4 blocks left
+    while blocks.len() >= 8 {
+        blocks = blocks.chunks_exact(2).map(|b| (b[0] + b[1]) / 2.0).collect();
+        sem = sem.max(std_dev(&blocks) / (blocks.len() as f64).sqrt());
+    }
+    sem
+}
+
+// frames needed for an independent sample, (block SEM / naive SEM)^2
+pub fn stat_ineff(x: &[f64]) -> f64 {
+    let naive = std_dev(x) / (x.len() as f64).sqrt();
+    match naive > 0.0 {
+        true => (block_sem(x) / naive).powi(2).max(1.0),
+        false => 1.0
+    }
+}
+
+// moving block bootstrap resamples of n frames, blocks of correlated frames kept together
+pub fn bootstrap_samples(n: usize, block_len: usize) -> Vec<Vec<usize>> {
+    let block_len = block_len.clamp(1, n.max(1));
+    let mut rng = SplitMix64(SEED);
+    (0..N_BOOT).map(|_| {
+        let mut ids: Vec<usize> = Vec::with_capacity(n + block_len);
+        while ids.len() < n {
+            let start = rng.below(n - block_len + 1);
+            ids.extend(start..start + block_len);
+        }
+        ids.truncate(n);
+        ids
+    }).collect()
+}
+
+fn percentile_ci(mut boot: Vec<f64>) -> [f64; 2] {
+    boot.retain(|v| v.is_finite());
+    if boot.is_empty() {
+        return [f64::NAN, f64::NAN];
+    }
+    boot.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    [percentile(&boot, 0.025), percentile(&boot, 0.975)]
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let pos = p * (sorted.len() - 1) as f64;
+    let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+}
+
+// reproducible resampling without extra dependencies
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
@@ -0,0 +1,205 @@
+// preparation of the system from tpr, index and trajectory files, for the interactive program and job files
+use std::fs;
+use std::path::Path;
+use ndarray::Array3;
+use crate::atom_property::AtomProperties;
+use crate::error::SmError;
+use crate::index_parser::{Index, IndexGroup};
+use crate::parse_tpr::{Residue, TPR};
+use crate::parse_xtc::read_xtc;
+use crate::pbc::fix_pbc;
+use crate::settings::Settings;
+use crate::utils::{append_new_name, make_ndx, trjconv};
+
+// convert rec and lig to begin at 0 and continous
+pub fn normalize_index(ndx_rec: &Vec<usize>, ndx_lig: Option<&Vec<usize>>) -> (Vec<usize>, Vec<usize>) {
+    if let Some(ndx_lig) = ndx_lig {
+        let mut ndx_rec_norm = ndx_rec.clone();
+        let mut ndx_lig_norm = ndx_lig.clone();
+        let last_atom = ndx_rec.len() + ndx_lig.len() - 1;
+        for cur_atom_id in 0..=last_atom {
+            if !ndx_lig_norm.contains(&cur_atom_id) && !ndx_rec_norm.contains(&cur_atom_id) {
+                let ndx_lig_norm2 = ndx_lig_norm.clone();
+                let ndx_rec_norm2 = ndx_rec_norm.clone();
+                let next_edge_lig = ndx_lig_norm2.iter().find(|&&i| i > cur_atom_id);
+                let next_edge_rec = ndx_rec_norm2.iter().find(|&&i| i > cur_atom_id);
+                let offset = if next_edge_lig.is_none() {
+                    if next_edge_rec.is_none() {
+                        0
+                    } else {
+                        next_edge_rec.unwrap() - cur_atom_id
+                    }
+                } else {
+                    if next_edge_rec.is_none() {
+                        next_edge_lig.unwrap() - cur_atom_id
+                    } else {
+                        next_edge_lig.unwrap().min(next_edge_rec.unwrap()) - cur_atom_id
+                    }
+                };
+                ndx_lig_norm.iter_mut().for_each(|i| if *i > cur_atom_id { *i -= offset } );
+                ndx_rec_norm.iter_mut().for_each(|i| if *i > cur_atom_id { *i -= offset } );
+            }
+        }
+        (ndx_rec_norm, ndx_lig_norm)
+    } else {
+        ((0..ndx_rec.len()).collect(), (0..ndx_rec.len()).collect())
+    }
+}
+
+pub fn get_residues_tpr(tpr: &TPR, ndx_com: &Vec<usize>) -> Vec<Residue> {
+    let mut residues: Vec<Residue> = vec![];
+    let mut idx = 0;
+    let mut resind_offset = 0;
+    
+    for mol in &tpr.molecules {
+        for _ in 0..tpr.molecule_types[mol.molecule_type_id].molecules_num {
+            for atom in &mol.atoms {
+                idx += 1;
+                if ndx_com.contains(&idx) && residues.len() <= atom.resind + resind_offset {
+                    let mut cur_res = mol.residues[atom.resind].to_owned();
+                    let prev_resnr_list : Vec<i32> = residues.iter().map(|r| r.nr).collect();
+                    if prev_resnr_list.contains(&cur_res.nr) {
+                        cur_res.nr = *prev_resnr_list.last().unwrap() + 1;
+                    }
+                    residues.push(cur_res);
+                }
+            }
+            resind_offset += mol.residues.len();
+        }
+    }
+    residues
+}
+
+// time list, coordinates, atom properties, receptor and ligand indexes, residues
+pub type SystemData = (Vec<f64>, Array3<f64>, AtomProperties, Vec<usize>, Vec<usize>, Vec<Residue>);
+
+pub fn prepare_system_tpr(receptor_grp: usize, ligand_grp: Option<usize>, 
+                  trj: &String, tpr: &TPR, ndx: &Index, 
+                  tpr_name: &str, ndx_name: &String, 
+                  bt: f64, et: f64, dt: f64, 
+                  wd: &Path, settings: &Settings) -> Result<SystemData, SmError> {
+    // atom indexes
+    println!("Preparing atom indexes...");
+    for grp in Some(receptor_grp).iter().chain(ligand_grp.iter()) {
+        match ndx.groups.get(*grp) {
+            Some(g) if !g.indexes.is_empty() => {},
+            Some(g) => return Err(SmError::Input(format!("Group {} ({}) has no atoms.", grp, g.name))),
+            None => return Err(SmError::Input(format!("Group {} not found in {}.", grp, ndx_name)))
+        }
+    }
+    let ndx_lig = match ligand_grp {
+        Some(ligand_grp) => Some(&ndx.groups[ligand_grp].indexes),
+        None => None
+    };
+    let ndx_rec = &ndx.groups[receptor_grp].indexes;
+    let ndx_com = match ndx_lig {
+        Some(ndx_lig) => {
+            match ndx_lig[0] > ndx_rec[0] {
+                true => {
+                    let mut ndx_com = ndx_rec.to_vec();
+                    ndx_com.extend(ndx_lig);
+                    ndx_com
+                }
+                false => {
+                    let mut ndx_com = ndx_lig.to_vec();
+                    ndx_com.extend(ndx_rec);
+                    ndx_com
+                }
+            }
+        }
+        None => ndx_rec.to_vec()
+    };
+
+    // atom properties
+    println!("Parsing atom properties...");
+    let mut aps = AtomProperties::from_tpr(tpr, &ndx_com);
+    println!("Collecting residues list...");
+    let residues = get_residues_tpr(tpr, &ndx_com);
+
+    // pre-treat trajectory: xtc could be read directly, otherwise converted by gromacs first
+    let read_directly = trj.ends_with(".xtc");
+    let trj_mmpbsa = append_new_name(trj, ".xtc", "_MMPBSA_"); // get trj output file name
+    let tpr_name = append_new_name(tpr_name, ".tpr", ""); // fuck the passed tpr name is dump
+    if !read_directly {
+        // step 1: generate new index
+        println!("Generating Index...");
+        // gmx make_ndx -f md.tpr -n index.idx -o md_trj_whole.xtc -pbc whole
+        let ndx_whole = append_new_name(ndx_name, "_whole.ndx", "_MMPBSA_"); // get extracted index file name
+        if let Some(ligand_grp) = ligand_grp {
+            make_ndx(&vec![
+                format!("{} | {}", receptor_grp, ligand_grp).as_str(),
+                format!("name {} Complex", ndx.groups.len()).as_str(),
+                format!("name {} Receptor", receptor_grp).as_str(),
+                format!("name {} Ligand", ligand_grp).as_str(),
+                "q"
+            ], wd, settings, &tpr_name, ndx_name, &ndx_whole);
+        } else {
+            make_ndx(&vec![
+                // complex is receptor
+                format!("name {} Complex", receptor_grp).as_str(),
+                "q"
+            ], wd, settings, &tpr_name, ndx_name, &ndx_whole);
+        }
+
+        // step 2: extract new trj with old tpr and new index
+        println!("Extracting trajectory, be patient...");
+        trjconv(&vec!["Complex"], wd, settings, &trj, &tpr_name, &ndx_whole, &trj_mmpbsa, 
+            &vec!["-t0", "0", "-dt", &dt.to_string(), "-b", &bt.to_string(), "-e", &et.to_string()]);
+        if !settings.debug_mode {
+            fs::remove_file(&ndx_whole).unwrap();
+        }
+    }
+    
+    // step 3: generate new index of complex
+    println!("Normalizing index...");
+    let (ndx_rec, ndx_lig) = 
+        normalize_index(&ndx.groups[receptor_grp].indexes, match ligand_grp {
+            Some(ligand_grp) => Some(&ndx.groups[ligand_grp].indexes),
+            None => None
+        });
+    // 需要处理一下atom_properties的id
+    aps.atom_props.iter_mut().enumerate().for_each(|(i, ap)| ap.id = i);
+    
+    // extract index file
+    let ndx_mmpbsa = match ligand_grp {
+        Some(_) => {
+            Index::new(vec![
+                IndexGroup::new("Complex", &ndx_rec.iter().chain(ndx_lig.iter()).cloned().collect()), 
+                IndexGroup::new("Receptor", &ndx_rec),
+                IndexGroup::new("Ligand", &ndx_lig)
+            ])
+        },
+        None => {
+            Index::new(vec![
+                IndexGroup::new("Complex", &ndx_rec)
+            ])
+        }
+    };
+    ndx_mmpbsa.to_ndx(wd.join("_MMPBSA_index.ndx").to_str().unwrap());
+
+    println!("Loading trajectory coordinates...");
+    let (time_list, mut coordinates, boxes) = if read_directly {
+        // complex atoms in the same order as trjconv output, time shifted like -t0 0
+        let mut ndx_com = ndx_com.to_vec();
+        ndx_com.sort_unstable();
+        let (mut time_list, coordinates, boxes) = read_xtc(trj, Some(&ndx_com), bt, et, dt)?;
+        let t0 = time_list[0];
+        time_list.iter_mut().for_each(|t| *t -= t0);
+        (time_list, coordinates, boxes)
+    } else {
+        read_xtc(&trj_mmpbsa, None, 0.0, f64::INFINITY, 0.0)?
+    };
+
+    // step 4: remove pbc in place, as convert-trj cannot handle incomplete proteins
+    if settings.fix_pbc {
+        println!("Fixing PBC conditions...");
+        let bonds = tpr.get_bonds(&ndx_com);
+        let ndx_lig_pbc: &[usize] = match ligand_grp {
+            Some(_) => &ndx_lig,
+            None => &[]
+        };
+        fix_pbc(&mut coordinates, &boxes, &bonds, ndx_lig_pbc);
+    }
+
+    Ok((time_list, coordinates, aps, ndx_rec, ndx_lig, residues))
+}
@@ -100,16 +100,6 @@ pub fn grompp(options: &Vec<&str>, wd: &Path, settings: &Settings, f: &str, c: &
     cmd_options(settings, settings.gmx_path.as_ref().unwrap(), options, &args, wd).unwrap();
 }
 
-pub fn convert_tpr(options: &Vec<&str>, wd: &Path, settings: &Settings, s: &str, n: &str, o: &str) {
-    let args = ["convert-tpr", "-s", s, "-n", n, "-o", o];
-    cmd_options(settings, settings.gmx_path.as_ref().unwrap(), options, &args, wd).unwrap();
-}
-
-pub fn convert_trj(options: &Vec<&str>, wd: &Path, settings: &Settings, f: &str, s: &str, n: &str, o: &str, others: &[&str]) {
-    let args: Vec<&str> = ["convert-trj", "-f", f, "-s", s, "-n", n, "-o", o].iter().chain(others.iter()).cloned().collect();
-    cmd_options(settings, settings.gmx_path.as_ref().unwrap(), options, &args, wd).unwrap();
-}
-
 pub fn trjconv(options: &Vec<&str>, wd: &Path, settings: &Settings, f: &str, s: &str, n: &str, o: &str, others: &[&str]) {
     let args: Vec<&str> = ["trjconv", "-f", f, "-s", s, "-n", n, "-o", o].iter().chain(others.iter()).cloned().collect();
     cmd_options(settings, settings.gmx_path.as_ref().unwrap(), options, &args, wd).unwrap();
@@ -175,8 +165,19 @@ pub fn resname_3to1(name: &str) -> Option<String> {
     }
 }
 
+// residue names of mutation targets, neutral HIE for H as pdb2gmx does by default
+pub fn resname_1to3(name: &str) -> Option<String> {
+    let resname_map: HashMap<&str, &str> = HashMap::from([
+        ("A", "ALA"), ("C", "CYS"), ("D", "ASP"), ("E", "GLU"), ("F", "PHE"),
+        ("G", "GLY"), ("H", "HIE"), ("I", "ILE"), ("K", "LYS"), ("L", "LEU"),
+        ("M", "MET"), ("N", "ASN"), ("P", "PRO"), ("Q", "GLN"), ("R", "ARG"),
+        ("S", "SER"), ("T", "THR"), ("V", "VAL"), ("W", "TRP"), ("Y", "TYR"),
+    ]);
+    resname_map.get(name).map(|s| s.to_string())
+}
+
 pub fn get_residue_range_ca(coord: &Array2<f64>, ref_ids: &Vec<usize>, cutoff: f64, 
-        atom_res: &Vec<usize>, atom_names: &Vec<String>, residues: &Vec<Residue>) -> Vec<usize> {
+        atom_res: &[usize], atom_names: &[String], residues: &[Residue]) -> Vec<usize> {
     let mut res_range: Vec<usize> = vec![];
     let ligand_coord = coord.select(Axis(0), ref_ids);
     for res in residues {